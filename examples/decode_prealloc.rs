@@ -0,0 +1,48 @@
+use codecs::huffman::{decode_from_bitstream, Encoder};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+// Counts every allocation, growing a buffer included, so that decoding with
+// and without a stored length can be compared
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn decode_counted(name: &str, data: &[u8]) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let text = decode_from_bitstream(data).unwrap();
+    let count = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    println!("{name}: {} characters, {} allocations in {:?}", text.len(), count, start.elapsed());
+
+    count
+}
+
+fn main() {
+    let text = "Sing, O goddess, the anger of Achilles son of Peleus, that brought countless ills upon the Achaeans. "
+        .repeat(50_000);
+
+    // The stored length lets the decoder size the text up front
+    let sized = Encoder::new().encode(&text).unwrap();
+    let unknown = Encoder::new().integrity(false).encode(&text).unwrap();
+
+    let with_length = decode_counted("with length   ", &sized);
+    let without_length = decode_counted("without length", &unknown);
+
+    assert!(with_length < without_length);
+}
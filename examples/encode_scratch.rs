@@ -0,0 +1,50 @@
+use codecs::huffman::{encode_to_bitstream, EncodeScratch};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+// Counts every allocation, so the two ways of encoding can be compared
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const MESSAGES: usize = 10_000;
+
+fn main() {
+    let messages: Vec<String> = (0..MESSAGES)
+        .map(|i| format!("GET /items/{} HTTP/1.1 status={} bytes={}", i % 97, 200 + i % 5, i * 31))
+        .collect();
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for message in &messages {
+        encode_to_bitstream(message).unwrap();
+    }
+    let fresh = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    println!("encode_to_bitstream: {} allocations in {:?}", fresh, start.elapsed());
+
+    let mut scratch = EncodeScratch::new();
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for message in &messages {
+        scratch.encode_into_scratch(message).unwrap();
+    }
+    let reused = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    println!("EncodeScratch:       {} allocations in {:?}", reused, start.elapsed());
+
+    assert!(reused < fresh);
+}
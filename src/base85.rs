@@ -0,0 +1,115 @@
+//! ## Base85
+//! Base85, in the Ascii85 form used by PostScript and PDF, writes every four
+//! bytes as five printable characters from `!` to `u`, taking a quarter more
+//! room where Base64 takes a third more. A group of four zero bytes is written
+//! as the single character `z`. A last group of one to three bytes is padded
+//! with zeroes and written with one character more than it has bytes. For
+//! more, see [article](https://en.wikipedia.org/wiki/Ascii85).
+//! 
+//! The `<~` and `~>` delimiters are left to the caller, and whitespace is
+//! skipped when decoding.
+//! 
+//! ## Example
+//! 
+//! 
+//! ```
+//! use codecs::base85::{encode, decode};
+//! 
+//! assert_eq!(encode(b"Man is"), "9jqo^Bla");
+//! assert_eq!(decode("9jqo^Bla").unwrap(), b"Man is");
+//! 
+//! // Zero groups shrink to a single character, unless they are cut short
+//! let zeroes = [0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0];
+//! assert_eq!(encode(&zeroes), "zz!<<*");
+//! assert_eq!(decode("zz!<<*").unwrap(), zeroes);
+//! assert_eq!(decode(&encode(&[0, 0])).unwrap(), [0, 0]);
+//! 
+//! for len in 0..12 {
+//!     let bytes: Vec<u8> = (0..len).map(|i| (i * 73 + 200) as u8).collect();
+//!     assert_eq!(decode(&encode(&bytes)).unwrap(), bytes);
+//! }
+//! 
+//! assert!(decode("9jqo^v").is_err());
+//! assert!(decode("9jzo^").is_err());
+//! assert!(decode("9").is_err());
+//! ```
+use anyhow::{anyhow, Result};
+
+// The character for a digit of zero, and so the first of the 85
+const FIRST: u8 = b'!';
+// Stands in for a whole group of zero bytes
+const ZERO_GROUP: char = 'z';
+
+/// Encodes bytes as Ascii85, without delimiters.
+pub fn encode(input: &[u8]) -> String {
+    let mut output = String::with_capacity(input.len().div_ceil(4) * 5);
+
+    for chunk in input.chunks(4) {
+        if chunk == [0; 4] {
+            output.push(ZERO_GROUP);
+            continue;
+        }
+
+        let mut group = [0u8; 4];
+        group[..chunk.len()].copy_from_slice(chunk);
+        let mut value = u32::from_be_bytes(group);
+
+        let mut digits = [0u8; 5];
+        for digit in digits.iter_mut().rev() {
+            *digit = FIRST + (value % 85) as u8;
+            value /= 85;
+        }
+        // A short group only needs one character more than it has bytes
+        output.extend(digits[..(chunk.len() + 1)].iter().map(|&digit| char::from(digit)));
+    }
+
+    output
+}
+
+/// Decodes Ascii85 without delimiters, skipping whitespace.
+pub fn decode(input: &str) -> Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(input.len() / 5 * 4);
+    let mut digits = Vec::with_capacity(5);
+
+    for ch in input.chars().filter(|ch| !ch.is_ascii_whitespace()) {
+        if ch == ZERO_GROUP {
+            if !digits.is_empty() {
+                return Err(anyhow!("Zero group inside a group."));
+            }
+            output.extend_from_slice(&[0; 4]);
+            continue;
+        }
+        if !('!'..='u').contains(&ch) {
+            return Err(anyhow!("Character {:?} is not Ascii85.", ch));
+        }
+
+        digits.push(ch as u8 - FIRST);
+        if digits.len() == 5 {
+            output.extend_from_slice(&decode_group(&digits)?);
+            digits.clear();
+        }
+    }
+
+    match digits.len() {
+        0 => {}
+        1 => return Err(anyhow!("Last group has a single character.")),
+        len => {
+            // Padding with the highest digit rounds the short group back up
+            let keep = len - 1;
+            digits.resize(5, 84);
+            output.extend_from_slice(&decode_group(&digits)?[..keep]);
+        }
+    }
+
+    Ok(output)
+}
+
+// Turn five digits back into four bytes
+fn decode_group(digits: &[u8]) -> Result<[u8; 4]> {
+    let value = digits
+        .iter()
+        .fold(0u64, |value, &digit| value * 85 + digit as u64);
+    let value = u32::try_from(value).map_err(|_| anyhow!("Group out of range."))?;
+
+    Ok(value.to_be_bytes())
+}
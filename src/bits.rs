@@ -0,0 +1,240 @@
+//! ## Bits
+//! Bit level reading and writing shared by the codecs. The `BitRead` and
+//! `BitWrite` traits let several stages of a pipeline share a single bit
+//! cursor, so that one stage can pick up exactly where the previous one left
+//! off. Bits are stored most significant bit first unless another `BitOrder`
+//! is chosen.
+//! 
+//! ## Example
+//! 
+//! 
+//! ```
+//! use codecs::bits::{BitBundle, BitRead, BitWrite, BitWriter};
+//! 
+//! // The first stage writes a 3-bit count, the second the values in unary
+//! let values = [2u8, 0, 3];
+//! let mut writer = BitWriter::new();
+//! writer.write_bits(values.len() as u64, 3);
+//! for &value in &values {
+//!     for _ in 0..value {
+//!         writer.write_bit(1);
+//!     }
+//!     writer.write_bit(0);
+//! }
+//! let packed = writer.finish();
+//! 
+//! let mut reader = BitBundle::new(&packed.bytes);
+//! let count = reader.read_bits(3).unwrap();
+//! let mut decoded = Vec::new();
+//! for _ in 0..count {
+//!     let mut value = 0;
+//!     while reader.read_bit() == Some(1) {
+//!         value += 1;
+//!     }
+//!     decoded.push(value);
+//! }
+//! assert_eq!(decoded, values);
+//! ```
+
+/// The order in which bits fill each byte. Either way, values read or written
+/// with several bits keep their most significant bit first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BitOrder {
+    /// The first bit goes in the most significant position, as in JPEG
+    #[default]
+    MsbFirst,
+    /// The first bit goes in the least significant position, as in DEFLATE
+    LsbFirst,
+}
+
+impl BitOrder {
+    // Position within a byte of the bit at `idx`, counting from the left
+    fn shift(self, idx: u8) -> u8 {
+        match self {
+            BitOrder::MsbFirst => 7 - idx,
+            BitOrder::LsbFirst => idx,
+        }
+    }
+}
+
+/// A source of bits.
+pub trait BitRead {
+    /// Reads the next bit, or `None` once the data runs out.
+    fn read_bit(&mut self) -> Option<u8>;
+
+    /// Reads `count` bits, up to 64, into the low bits of the result with the
+    /// first bit read as the most significant.
+    fn read_bits(&mut self, count: u8) -> Option<u64> {
+        let mut value: u64 = 0;
+        for _ in 0..count {
+            value = (value << 1) | self.read_bit()? as u64;
+        }
+        Some(value)
+    }
+
+    /// Skips any remaining bits of the current byte.
+    fn align(&mut self);
+}
+
+/// A sink for bits.
+pub trait BitWrite {
+    /// Writes a single bit. Only the lowest bit of `bit` is used.
+    fn write_bit(&mut self, bit: u8);
+
+    /// Writes the low `count` bits of `value`, up to 64, most significant
+    /// first.
+    fn write_bits(&mut self, value: u64, count: u8) {
+        for i in (0..count).rev() {
+            self.write_bit(((value >> i) & 1) as u8);
+        }
+    }
+
+    /// Pads the current byte with zeroes.
+    fn align(&mut self);
+}
+
+/// Reads bits from a byte slice.
+pub struct BitBundle<'a> {
+    pub(crate) data: &'a [u8],
+    pub(crate) byte_idx: usize,
+    pub(crate) bit_idx: u8,
+    order: BitOrder,
+}
+
+impl<'a> BitBundle<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self::with_order(data, BitOrder::MsbFirst)
+    }
+
+    pub fn with_order(data: &'a [u8], order: BitOrder) -> Self {
+        Self { data, byte_idx: 0, bit_idx: 0, order }
+    }
+
+    pub fn read_byte(&mut self) -> Option<u8> {
+        self.read_bits(8).map(|byte| byte as u8)
+    }
+
+    /// Number of bits read so far.
+    pub fn position(&self) -> usize {
+        self.byte_idx * 8 + self.bit_idx as usize
+    }
+}
+
+impl BitRead for BitBundle<'_> {
+    fn read_bit(&mut self) -> Option<u8> {
+        if self.byte_idx >= self.data.len() {
+            return None;
+        }
+
+        let bit = (self.data[self.byte_idx] >> self.order.shift(self.bit_idx)) & 1;
+        self.bit_idx += 1;
+        if self.bit_idx == 8 {
+            self.byte_idx += 1;
+            self.bit_idx = 0;
+        }
+
+        Some(bit)
+    }
+
+    fn align(&mut self) {
+        if self.bit_idx != 0 {
+            self.byte_idx += 1;
+            self.bit_idx = 0;
+        }
+    }
+}
+
+/// Bytes written by a `BitWriter`, along with the number of padding bits at
+/// the end of the last byte.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackedBits {
+    pub bytes: Vec<u8>,
+    pub pad: u8,
+}
+
+/// Writes bits into a growing byte vector.
+#[derive(Default)]
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    bit_idx: u8,
+    order: BitOrder,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_order(order: BitOrder) -> Self {
+        Self { order, ..Self::default() }
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        self.write_bits(byte as u64, 8);
+    }
+
+    // Carry on writing after the last bit of already packed data
+    pub(crate) fn resume(bits: PackedBits, order: BitOrder) -> Self {
+        let mut bytes = bits.bytes;
+        let bit_idx = if bytes.is_empty() { 0 } else { (8 - bits.pad % 8) % 8 };
+        if bit_idx > 0 {
+            // Clear the padding in case it was not written as zeroes
+            if let Some(last) = bytes.last_mut() {
+                *last &= match order {
+                    BitOrder::MsbFirst => 0xFF << (8 - bit_idx),
+                    BitOrder::LsbFirst => 0xFF >> (8 - bit_idx),
+                };
+            }
+        }
+        Self { bytes, bit_idx, order }
+    }
+
+    // Write a code given as a string of '0' and '1' characters
+    pub(crate) fn write_code(&mut self, code: &str) {
+        for bit in code.bytes() {
+            self.write_bit(bit - b'0');
+        }
+    }
+
+    /// Finishes writing, padding the last byte with zeroes.
+    /// 
+    /// ## Example
+    /// 
+    /// 
+    /// ```
+    /// use codecs::bits::{BitOrder, BitWrite, BitWriter};
+    /// 
+    /// for order in [BitOrder::MsbFirst, BitOrder::LsbFirst] {
+    ///     for n in 0..=16 {
+    ///         let mut writer = BitWriter::with_order(order);
+    ///         for _ in 0..n {
+    ///             writer.write_bit(1);
+    ///         }
+    ///         let packed = writer.finish();
+    ///         assert_eq!(packed.pad as usize, (8 - n % 8) % 8);
+    ///         assert_eq!(packed.bytes.len(), n.div_ceil(8));
+    ///     }
+    /// }
+    /// ```
+    pub fn finish(self) -> PackedBits {
+        // Padding is always zeroes, as the bytes are zeroed when pushed
+        let pad = (8 - self.bit_idx) % 8;
+        PackedBits { bytes: self.bytes, pad }
+    }
+}
+
+impl BitWrite for BitWriter {
+    fn write_bit(&mut self, bit: u8) {
+        if self.bit_idx == 0 {
+            self.bytes.push(0);
+        }
+
+        let last = self.bytes.len() - 1;
+        self.bytes[last] |= (bit & 1) << self.order.shift(self.bit_idx);
+        self.bit_idx = (self.bit_idx + 1) % 8;
+    }
+
+    fn align(&mut self) {
+        self.bit_idx = 0;
+    }
+}
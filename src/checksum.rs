@@ -0,0 +1,77 @@
+//! ## Checksum
+//! CRC-32 using the IEEE polynomial, as found in zip, gzip and PNG. Used by
+//! the codecs to check that decoded data matches what was encoded.
+//! 
+//! ## Example
+//! 
+//! 
+//! ```
+//! use codecs::checksum::{crc32, Crc32};
+//! 
+//! assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+//! 
+//! let mut crc = Crc32::new();
+//! crc.update(b"1234");
+//! crc.update(b"56789");
+//! assert_eq!(crc.finish(), 0xCBF4_3926);
+//! ```
+
+const TABLE: [u32; 256] = make_table();
+
+// Lookup table for the reversed polynomial, one entry per byte value
+const fn make_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// A running CRC-32, for data that arrives in pieces.
+#[derive(Clone, Copy)]
+pub struct Crc32 {
+    value: u32,
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self { value: 0xFFFF_FFFF }
+    }
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Carries on from a finished CRC, as if the data it covers had been fed
+    /// to this one.
+    pub fn resume(crc: u32) -> Self {
+        Self { value: !crc }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.value = TABLE[((self.value ^ byte as u32) & 0xFF) as usize] ^ (self.value >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.value
+    }
+}
+
+/// Computes the CRC-32 of `data` in one go.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
@@ -0,0 +1,131 @@
+//! ## Codec
+//! The `Codec` trait puts the algorithms behind one interface taking and
+//! giving bytes, so that a pipeline or a dispatcher choosing between them can
+//! hold any of them as a `&dyn Codec`. `boxed()` lists one of each.
+//! 
+//! - `Huffman` encodes the bytes with `encode_bytes_to_bitstream()`.
+//! - `Fibonacci` stores the number of bytes as a variable width number, then
+//!   a Fibonacci code for each byte.
+//! - `Base85` writes the bytes as Ascii85 text.
+//! - `Ints` compresses the bytes as a list of integers with `ints::compress()`.
+//! - `Rle` run-length encodes the bytes with `rle::encode()`.
+//! 
+//! Other codecs, such as LZW, are to join the list once there are modules for
+//! them.
+//! 
+//! ## Example
+//! 
+//! 
+//! ```
+//! use codecs::codec::{boxed, Codec};
+//! 
+//! let input = b"Lorem ipsum dolor sit amet, consectetur adipiscing elit \x00\xFF";
+//! for (name, codec) in boxed() {
+//!     let codec: &dyn Codec = codec.as_ref();
+//!     let data = codec.encode(input).unwrap();
+//!     assert_eq!(codec.decode(&data).unwrap(), input, "{name}");
+//! }
+//! ```
+use crate::huffman::{self, uint_to_vwe, vwe_to_uint};
+use crate::{base85, fibonacci, ints, rle};
+use anyhow::{anyhow, Result};
+
+/// An algorithm that turns bytes into encoded bytes and back.
+pub trait Codec {
+    fn encode(&self, input: &[u8]) -> Result<Vec<u8>>;
+    fn decode(&self, input: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// Huffman coding, with a tree over byte values.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Huffman;
+
+impl Codec for Huffman {
+    fn encode(&self, input: &[u8]) -> Result<Vec<u8>> {
+        huffman::encode_bytes_to_bitstream(input)
+    }
+
+    fn decode(&self, input: &[u8]) -> Result<Vec<u8>> {
+        huffman::decode_bytes_from_bitstream(input)
+    }
+}
+
+/// Fibonacci coding of each byte, after the number of bytes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Fibonacci;
+
+impl Codec for Fibonacci {
+    fn encode(&self, input: &[u8]) -> Result<Vec<u8>> {
+        let values: Vec<u64> = input.iter().map(|&byte| byte as u64).collect();
+        let mut output = uint_to_vwe(input.len())?;
+        output.extend(fibonacci::encode(&values));
+
+        Ok(output)
+    }
+
+    fn decode(&self, input: &[u8]) -> Result<Vec<u8>> {
+        let (count, width) = vwe_to_uint(input)?;
+        fibonacci::decode(&input[width..], count)?
+            .into_iter()
+            .map(|value| u8::try_from(value).map_err(|_| anyhow!("Value out of range.")))
+            .collect()
+    }
+}
+
+/// Ascii85 text.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Base85;
+
+impl Codec for Base85 {
+    fn encode(&self, input: &[u8]) -> Result<Vec<u8>> {
+        Ok(base85::encode(input).into_bytes())
+    }
+
+    fn decode(&self, input: &[u8]) -> Result<Vec<u8>> {
+        let text = std::str::from_utf8(input).map_err(|_| anyhow!("Data is not text."))?;
+        base85::decode(text)
+    }
+}
+
+/// The bytes as integers, compressed with whichever integer code suits them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Ints;
+
+impl Codec for Ints {
+    fn encode(&self, input: &[u8]) -> Result<Vec<u8>> {
+        let values: Vec<u64> = input.iter().map(|&byte| byte as u64).collect();
+        Ok(ints::compress(&values))
+    }
+
+    fn decode(&self, input: &[u8]) -> Result<Vec<u8>> {
+        ints::decompress(input)?
+            .into_iter()
+            .map(|value| u8::try_from(value).map_err(|_| anyhow!("Value out of range.")))
+            .collect()
+    }
+}
+
+/// Run-length encoding, escaped with the least frequent byte.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rle;
+
+impl Codec for Rle {
+    fn encode(&self, input: &[u8]) -> Result<Vec<u8>> {
+        Ok(rle::encode(input))
+    }
+
+    fn decode(&self, input: &[u8]) -> Result<Vec<u8>> {
+        rle::decode(input)
+    }
+}
+
+/// One of each codec, with its name.
+pub fn boxed() -> Vec<(&'static str, Box<dyn Codec>)> {
+    vec![
+        ("huffman", Box::new(Huffman)),
+        ("fibonacci", Box::new(Fibonacci)),
+        ("base85", Box::new(Base85)),
+        ("ints", Box::new(Ints)),
+        ("rle", Box::new(Rle)),
+    ]
+}
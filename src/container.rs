@@ -0,0 +1,151 @@
+//! ## Container
+//! Holds several named files, each compressed on its own with Huffman coding,
+//! followed by a central directory in the manner of ZIP. The directory gives
+//! each entry's name, position, size and CRC-32, so an entry can be found and
+//! extracted without reading through the others.
+//! 
+//! The entries are stored back to back from the start. The directory follows
+//! as the number of entries, then for each its offset, compressed size, name
+//! length and name as variable width numbers and bytes, with its CRC-32 after
+//! the size. The last four bytes give the offset of the directory.
+//! 
+//! ## Example
+//! 
+//! 
+//! ```
+//! use codecs::container::Container;
+//! 
+//! let mut container = Container::new();
+//! container.add("iliad.txt", "Sing, O goddess, the anger of Achilles".as_bytes()).unwrap();
+//! container.add("odyssey.txt", "Tell me, O muse, of that ingenious hero".as_bytes()).unwrap();
+//! container.add("empty.txt", b"").unwrap();
+//! let data = container.to_bytes().unwrap();
+//! 
+//! let opened = Container::from_bytes(&data).unwrap();
+//! let names: Vec<String> = opened.entries().into_iter().map(|entry| entry.name).collect();
+//! assert_eq!(names, ["iliad.txt", "odyssey.txt", "empty.txt"]);
+//! assert_eq!(opened.extract("odyssey.txt").unwrap(), b"Tell me, O muse, of that ingenious hero");
+//! assert_eq!(opened.extract("iliad.txt").unwrap(), b"Sing, O goddess, the anger of Achilles");
+//! assert!(opened.extract("empty.txt").unwrap().is_empty());
+//! assert!(opened.extract("aeneid.txt").is_err());
+//! ```
+use crate::checksum::crc32;
+use crate::huffman::{decode_latin1, encode_latin1, uint_to_vwe, vwe_to_uint};
+use anyhow::{anyhow, Result};
+
+/// Where an entry sits in a container, as recorded in the directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryInfo {
+    pub name: String,
+    /// Position of the compressed entry from the start of the container
+    pub offset: usize,
+    /// Size of the compressed entry in bytes
+    pub size: usize,
+    /// CRC-32 of the entry before compression
+    pub crc: u32,
+}
+
+/// A set of named, separately compressed files.
+#[derive(Debug, Clone, Default)]
+pub struct Container {
+    data: Vec<u8>,
+    entries: Vec<EntryInfo>,
+}
+
+impl Container {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compresses `content` and adds it under `name`, which must not be taken.
+    pub fn add(&mut self, name: &str, content: &[u8]) -> Result<()> {
+        if self.entries.iter().any(|entry| entry.name == name) {
+            return Err(anyhow!("Entry {:?} already exists.", name));
+        }
+
+        // Empty files are stored as nothing at all, as there is no tree to build
+        let compressed = if content.is_empty() { Vec::new() } else { encode_latin1(content)? };
+        self.entries.push(EntryInfo {
+            name: name.to_string(),
+            offset: self.data.len(),
+            size: compressed.len(),
+            crc: crc32(content),
+        });
+        self.data.extend(compressed);
+
+        Ok(())
+    }
+
+    /// Writes out the entries followed by the directory.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut glob = self.data.clone();
+        let dir_offset = u32::try_from(glob.len()).map_err(|_| anyhow!("Container is too large."))?;
+
+        glob.extend(uint_to_vwe(self.entries.len())?);
+        for entry in &self.entries {
+            glob.extend(uint_to_vwe(entry.offset)?);
+            glob.extend(uint_to_vwe(entry.size)?);
+            glob.extend_from_slice(&entry.crc.to_be_bytes());
+            glob.extend(uint_to_vwe(entry.name.len())?);
+            glob.extend_from_slice(entry.name.as_bytes());
+        }
+        glob.extend_from_slice(&dir_offset.to_be_bytes());
+
+        Ok(glob)
+    }
+
+    /// Reads a container's directory. The entries themselves are only read
+    /// when extracted.
+    pub fn from_bytes(input: &[u8]) -> Result<Self> {
+        let end = input.len().checked_sub(4).ok_or_else(|| anyhow!("Truncated container."))?;
+        let dir_offset = u32::from_be_bytes([input[end], input[end + 1], input[end + 2], input[end + 3]]) as usize;
+        let dir = input.get(dir_offset..end).ok_or_else(|| anyhow!("Directory offset out of range."))?;
+
+        let (count, mut idx) = vwe_to_uint(dir)?;
+        // Each entry takes at least seven bytes, which bounds a corrupt count
+        let mut entries = Vec::with_capacity(count.min(dir.len() / 7));
+        for _ in 0..count {
+            let (offset, width) = vwe_to_uint(&dir[idx..])?;
+            idx += width;
+            let (size, width) = vwe_to_uint(&dir[idx..])?;
+            idx += width;
+            let crc = dir.get(idx..(idx + 4)).ok_or_else(|| anyhow!("Truncated directory."))?;
+            let crc = u32::from_be_bytes([crc[0], crc[1], crc[2], crc[3]]);
+            idx += 4;
+            let (name_len, width) = vwe_to_uint(&dir[idx..])?;
+            idx += width;
+            let name = dir.get(idx..(idx + name_len)).ok_or_else(|| anyhow!("Truncated directory."))?;
+            let name = String::from_utf8(name.to_vec()).map_err(|_| anyhow!("Entry name is not valid UTF-8."))?;
+            idx += name_len;
+
+            if offset.checked_add(size).is_none_or(|entry_end| entry_end > dir_offset) {
+                return Err(anyhow!("Entry {:?} lies outside the data.", name));
+            }
+            entries.push(EntryInfo { name, offset, size, crc });
+        }
+
+        Ok(Self { data: input[..dir_offset].to_vec(), entries })
+    }
+
+    /// Lists the entries in the order they were added.
+    pub fn entries(&self) -> Vec<EntryInfo> {
+        self.entries.clone()
+    }
+
+    /// Decompresses the entry called `name`, checking it against its CRC-32.
+    pub fn extract(&self, name: &str) -> Result<Vec<u8>> {
+        let entry = self
+            .entries
+            .iter()
+            .find(|entry| entry.name == name)
+            .ok_or_else(|| anyhow!("No entry named {:?}.", name))?;
+
+        let compressed = &self.data[entry.offset..(entry.offset + entry.size)];
+        let content = if compressed.is_empty() { Vec::new() } else { decode_latin1(compressed)? };
+        if crc32(&content) != entry.crc {
+            return Err(anyhow!("Checksum mismatch in {:?}.", name));
+        }
+
+        Ok(content)
+    }
+}
@@ -0,0 +1,110 @@
+//! ## Fibonacci
+//! Fibonacci coding writes each integer as a sum of non-consecutive Fibonacci
+//! numbers, its Zeckendorf representation, smallest term first and followed
+//! by an extra 1 bit. The representation never has two 1s in a row, so `11`
+//! can only mean the end of a code. A damaged bit therefore only spoils the
+//! codes around it, and the decoder is back in step after the next `11`.
+//! Small numbers get short codes, which suits lengths and counts. For more,
+//! see [article](https://en.wikipedia.org/wiki/Fibonacci_coding).
+//! 
+//! Fibonacci codes start at one, so each value is stored as one more than
+//! itself to allow zero.
+//! 
+//! ## Example
+//! 
+//! 
+//! ```
+//! use codecs::fibonacci::{encode, decode};
+//! 
+//! let values = [3, 14, 1, 0, 27, 5, 8, 2, 100, 6];
+//! let data = encode(&values);
+//! assert_eq!(decode(&data, values.len()).unwrap(), values);
+//! 
+//! let max = [u64::MAX, 0];
+//! assert_eq!(decode(&encode(&max), 2).unwrap(), max);
+//! 
+//! // Damage a bit in the code for 27, which splits it in two
+//! let mut damaged = data.clone();
+//! damaged[2] ^= 0x08;
+//! let output = decode(&damaged, values.len() + 1).unwrap();
+//! assert_eq!(output[..4], values[..4]);
+//! assert_eq!(output[6..], values[5..]);
+//! ```
+use crate::bits::{BitBundle, BitRead, BitWrite, BitWriter};
+use anyhow::{anyhow, Result};
+
+// Every Fibonacci number from 1, 2 that fits in a u64, enough for any value + 1
+const FIBS: [u64; 92] = make_fibs();
+
+const fn make_fibs() -> [u64; 92] {
+    let mut fibs = [0u64; 92];
+    fibs[0] = 1;
+    fibs[1] = 2;
+    let mut i = 2;
+    while i < 92 {
+        fibs[i] = fibs[i - 1] + fibs[i - 2];
+        i += 1;
+    }
+    fibs
+}
+
+// Write the code for one value
+fn encode_value(value: u64, writer: &mut BitWriter) {
+    let mut rest = value as u128 + 1;
+    let top = FIBS.iter().rposition(|&fib| fib as u128 <= rest).unwrap();
+
+    // Greedily taking the largest term that fits gives the Zeckendorf form
+    let mut bits = [0u8; 92];
+    for i in (0..=top).rev() {
+        if FIBS[i] as u128 <= rest {
+            bits[i] = 1;
+            rest -= FIBS[i] as u128;
+        }
+    }
+
+    for &bit in &bits[..=top] {
+        writer.write_bit(bit);
+    }
+    writer.write_bit(1);
+}
+
+/// Encodes a list of integers, padding the last byte with zeroes.
+pub fn encode(values: &[u64]) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+
+    for &value in values {
+        encode_value(value, &mut writer);
+    }
+
+    writer.finish().bytes
+}
+
+// Read the code for one value
+fn decode_value(bundle: &mut BitBundle) -> Result<u64> {
+    let mut sum: u128 = 0;
+    let mut idx = 0;
+    let mut prev = 0;
+
+    loop {
+        let bit = bundle.read_bit().ok_or_else(|| anyhow!("Truncated data."))?;
+        if bit == 1 && prev == 1 {
+            break;
+        }
+        if bit == 1 {
+            let fib = FIBS.get(idx).ok_or_else(|| anyhow!("Code too long."))?;
+            sum += *fib as u128;
+        }
+        prev = bit;
+        idx += 1;
+    }
+
+    u64::try_from(sum - 1).map_err(|_| anyhow!("Value out of range."))
+}
+
+/// Decodes `count` integers. The padding cannot hold a `11`, so the count is
+/// needed to know where the values end.
+pub fn decode(input: &[u8], count: usize) -> Result<Vec<u64>> {
+    let mut bundle = BitBundle::new(input);
+
+    (0..count).map(|_| decode_value(&mut bundle)).collect()
+}
@@ -0,0 +1,207 @@
+//! ## Fuzz
+//! Checks that the decoders reject malformed input with an error rather than
+//! a panic. Streams are generated from random texts with random options, then
+//! mutated by flipping bits, overwriting bytes, truncating or splicing in
+//! noise, and some are plain random bytes. Every decoder is run on each one.
+//! 
+//! The same seed always gives the same inputs, so a failure can be replayed.
+//! The module is only built with the `fuzz` feature, so run it with
+//! `cargo test --features fuzz`.
+//! 
+//! ## Example
+//! 
+//! 
+//! ```
+//! use codecs::fuzz::fuzz_decode;
+//! 
+//! let panics = fuzz_decode(0x5EED, 5000);
+//! assert!(panics.is_empty(), "{} inputs panicked, first {:?}", panics.len(), panics.first());
+//! ```
+use crate::base85;
+use crate::bits::BitOrder;
+use crate::codec;
+use crate::container::Container;
+use crate::fibonacci;
+use crate::huffman::{self, Encoder, HmcFile, HmcMeta, LeafFormat, Level, ReferenceFormat, RecordCodec};
+use crate::ints;
+use crate::rle;
+use std::collections::HashSet;
+use std::panic;
+
+// Small xorshift generator, as the crate has no dependency for randomness
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Zero is a fixed point of xorshift, so it is swapped for a constant
+        Self(if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed })
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    // A number in `0..bound`, which must not be zero
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+}
+
+const ALPHABET: &[char] = &['a', 'b', 'c', 'e', ' ', '\n', 'Z', '0', 'é', '€', '世', '😀'];
+
+fn random_text(rng: &mut Rng) -> String {
+    let len = 1 + rng.below(64);
+    let used = 1 + rng.below(ALPHABET.len());
+    (0..len).map(|_| ALPHABET[rng.below(used)]).collect()
+}
+
+fn random_bytes(rng: &mut Rng, len: usize) -> Vec<u8> {
+    (0..len).map(|_| rng.next() as u8).collect()
+}
+
+// A valid stream from one of the encoders, with random options
+fn valid_stream(rng: &mut Rng) -> Vec<u8> {
+    let text = random_text(rng);
+    let mut encoder = Encoder::new().integrity(rng.below(2) == 0);
+    if rng.below(2) == 0 {
+        encoder = encoder.bit_order(BitOrder::LsbFirst);
+    }
+    if rng.below(2) == 0 {
+        encoder = encoder.leaf_format(LeafFormat::Codepoint);
+    }
+    if rng.below(4) == 0 {
+        encoder = encoder.raw_below(16);
+    }
+    encoder = encoder.level([Level::Fast, Level::Default, Level::Best][rng.below(3)]);
+    encoder = encoder.collapse_whitespace(rng.below(2) == 0);
+    if rng.below(4) == 0 {
+        encoder = encoder.metadata(HmcMeta { filename: String::from("fuzz.txt"), mtime: rng.next() });
+    }
+
+    let stream = match rng.below(5) {
+        0 => huffman::encode_with_rare_escape(&text, 1 + rng.below(4)),
+        1 if rng.below(2) == 0 => {
+            let mut container = Container::new();
+            container
+                .add("a", text.as_bytes())
+                .and_then(|_| container.add("b", b"abc"))
+                .and_then(|_| container.to_bytes())
+        }
+        1 => huffman::encode_shared(&[text.as_str(), "abc"]),
+        2 => RecordCodec::encode(&[vec![text.clone(), String::from("x")]]),
+        _ => encoder.encode(&text),
+    };
+    stream.unwrap_or_default()
+}
+
+fn mutate(rng: &mut Rng, mut data: Vec<u8>) -> Vec<u8> {
+    for _ in 0..(1 + rng.below(4)) {
+        if data.is_empty() {
+            let len = rng.below(8);
+            return random_bytes(rng, len);
+        }
+        let idx = rng.below(data.len());
+        match rng.below(5) {
+            0 => data[idx] ^= 1 << rng.below(8),
+            1 => data[idx] = rng.next() as u8,
+            2 => data.truncate(idx),
+            3 => {
+                let len = 1 + rng.below(8);
+                let noise = random_bytes(rng, len);
+                data.splice(idx..idx, noise);
+            }
+            _ => data[idx] = [0x00, 0x7F, 0x80, 0xFF][rng.below(4)],
+        }
+    }
+    data
+}
+
+// Run every decoder over the input, ignoring what they return
+fn decode_all(input: &[u8]) {
+    let _ = huffman::decode_from_bitstream(input);
+    let _ = huffman::decode_from_bitstream_capped(input, 1000);
+    let _ = huffman::decode_nth(input, 3);
+    let _ = huffman::decode_from_bitstream_budgeted(input, 1000);
+    let _ = huffman::decode_annotated(input);
+    let _ = huffman::decoded_length(input);
+    let _ = huffman::decode_from_bitstream_with_remainder(input);
+    let _ = huffman::decode_utf16(input);
+    let _ = huffman::decode_latin1(input);
+    let _ = huffman::format_breakdown(input);
+    let _ = huffman::read_metadata(input);
+    let _ = HmcFile::from_bytes(input).and_then(|file| file.to_bytes());
+    let _ = huffman::append_compressed(input, "abc");
+    let _ = huffman::decode_shared(input);
+    let _ = huffman::decode_lines(input);
+    let _ = huffman::decode_to_segments(input, 64);
+    let _ = huffman::stream_stats(input);
+    let _ = huffman::HmcHeader::parse(input);
+    let _ = huffman::decode_map(input);
+    let _ = huffman::padding_overhead(input);
+    let _ = huffman::payload_debug(input);
+    let _ = huffman::decode_auto(input);
+    let _ = huffman::decode_allowlist(input, &HashSet::from(['a', 'b', ' ']));
+    let mut decoder = huffman::IncrementalDecoder::new();
+    if input.chunks(7).all(|chunk| decoder.feed(chunk).is_ok()) {
+        let _ = decoder.finish();
+    }
+    let _ = huffman::decode_bytes_from_bitstream(input);
+    let _ = huffman::decode_checked(input);
+    let _ = huffman::decode_from_bitstream_restore_bom(input);
+    let _ = huffman::decode_from_bitstream_with_endian(input, huffman::LengthEndian::Little);
+    let _ = huffman::deserialize_tree(input);
+    let _ = huffman::decode_shared_segment(input, 1);
+    let _ = huffman::deframe(input);
+    let _ = huffman::decode_digrams(input);
+    let _ = huffman::decode_bpe(input);
+    let _ = huffman::decode_hierarchical(input);
+    let _ = huffman::decode_class_partitioned(input);
+    let _ = huffman::decode_fixed_alphabet(input);
+    let _ = huffman::decode_blocks(input);
+    let _ = huffman::decode_blocks_adaptive(input);
+    let _ = RecordCodec::decode(input);
+    let _ = huffman::decode_reference(input, ReferenceFormat::JpegTable);
+    let _ = huffman::decode_reference(input, ReferenceFormat::DeflateLengths);
+    let _ = fibonacci::decode(input, 16);
+    let _ = base85::decode(&String::from_utf8_lossy(input));
+    let _ = ints::decompress(input);
+    let _ = rle::decode(input);
+    for (_, codec) in codec::boxed() {
+        let _ = codec.decode(input);
+    }
+    if let Ok(container) = Container::from_bytes(input) {
+        for entry in container.entries() {
+            let _ = container.extract(&entry.name);
+        }
+    }
+}
+
+/// Runs `iterations` generated inputs through the decoders, and returns each
+/// input that made one of them panic. An empty result means none did.
+pub fn fuzz_decode(seed: u64, iterations: usize) -> Vec<Vec<u8>> {
+    let mut rng = Rng::new(seed);
+    let mut panics = Vec::new();
+
+    for _ in 0..iterations {
+        let input = match rng.below(8) {
+            0 => {
+                let len = rng.below(48);
+                random_bytes(&mut rng, len)
+            }
+            1 => valid_stream(&mut rng),
+            _ => {
+                let stream = valid_stream(&mut rng);
+                mutate(&mut rng, stream)
+            }
+        };
+
+        if panic::catch_unwind(|| decode_all(&input)).is_err() {
+            panics.push(input);
+        }
+    }
+
+    panics
+}
@@ -6,15 +6,112 @@
 //! ### Implementations
 //! 
 //! - `easy_encode()` provides a simple interface to encode a string to terminal.
+//! - `easy_encode_sorted()` does the same with the codes sorted by character.
+//! - `canonical_codes()` returns canonical codes ordered by length and codepoint.
+//! - `codes_only()` returns the codes, freeing the tree as it goes.
+//! - `tree_to_dot()` draws the tree as a Graphviz digraph.
+//! - `tree_adjacency()` lists the nodes of the tree with their children.
 //! - `encode_to_bitstream()` provides a more useful interface that packages the
-//! encoded data with the tree, and can be saved to file.
+//!   encoded data with the tree, and can be saved to file.
 //! - `decode_from_bitstream()` reverses the above function.
+//! - `decode_from_bitstream_with_endian()` reads little endian tree lengths.
+//! - `decode_from_bitstream_with_remainder()` also returns what follows a stream.
+//! - `decoded_length()` reads how many characters a stream holds.
+//! - `decode_for_each()` hands each character to a callback instead.
+//! - `decode_to_segments()` splits the output into fixed size segments.
+//! - `IncrementalDecoder` decodes a stream fed to it in pieces.
+//! - `encode_with_symbol_offsets()` also gives the bits each character takes.
+//! - `EncodeScratch` reuses its buffers to encode many small messages.
+//! - `encode_to_bitstream_progress()` reports its progress to a callback.
+//! - `encode_truncated()` encodes as much of a text as fits in a bit budget.
+//! - `encode_to_bitstream_verified()` decodes the stream again to check it.
+//! - `streams_equivalent()` checks whether two streams hold the same text.
+//! - `decode_from_bitstream_capped()` limits the size of the output.
+//! - `decode_from_bitstream_budgeted()` limits the time spent walking the tree.
+//! - `decode_allowlist()` fails at the first character outside a given set.
+//! - `decode_annotated()` pairs each character with the code it was read from.
+//! - `Encoder` is a builder for encoding with other options, such as the bit
+//!   order, the `LeafFormat` of the tree, the compression `Level` or collapsing
+//!   runs of whitespace.
+//! - `encode_auto_blocks()` picks the block size giving the smallest output.
+//! - `decode_blocks_parallel()` decodes the blocks on several threads, with
+//!   the `parallel` feature.
+//! - `encode_blocks_adaptive()` builds each block's tree from the block before.
+//! - `encode_with_rare_escape()` escapes rare characters to shrink the tree.
+//! - `encode_capped()` caps character frequencies to keep the tree shallow.
+//! - `append_compressed()` adds text to a stream, reusing its tree if it can.
+//! - `encode_utf16()` and `encode_latin1()` take text in other encodings, and
+//!   their decoders give it back in the same encoding.
+//! - `encode_bytes_to_bitstream()` encodes bytes, storing their exact count so
+//!   that `decode_bytes_from_bitstream()` never reads padding as data.
+//! - `encode_auto()` encodes text or bytes, whichever the input turns out to be.
+//! - `decode_checked()` decodes such bytes as UTF-8, naming where it breaks.
+//! - `encode_in_memory()` and `decode_in_memory()` skip tree serialisation when
+//!   both ends are in the same process.
+//! - `encode_to_bitvec()` gives the packed codes with their exact bit count.
+//! - `serialize_tree()` and `deserialize_tree()` store a tree on its own.
+//! - `tree_serialized_bits()` gives the exact size of a serialised tree in bits.
+//! - `optimal_tree()` builds the Huffman tree for given character weights.
+//! - `tree_from_code_lengths()` builds the tree for code lengths chosen elsewhere,
+//!   and `Node::codes()` lists the codes of a tree.
+//! - `validate_tree()` checks that a tree is complete and has no repeated leaf.
+//! - `tree_diff()` and `tree_patch()` send a tree as changes to a known one.
+//! - `TreeCursor` walks a tree a bit at a time for custom decoders.
+//! - `HmcFile` splits a stream into its fields and puts it back together.
+//! - `HmcHeader` reads just the fixed fields at the start of a stream.
+//! - `compress_file()` and `decompress_file()` keep the file name and time in a
+//!   trailer, which `read_metadata()` reads.
+//! - `format_breakdown()` reports how much of a stream is header, tree and data.
+//! - `padding_overhead()` counts the padding bits after the tree and the data.
+//! - `payload_debug()` shows the encoded data as grouped bits.
+//! - `stream_stats()` reports a stream's symbol counts and bits per symbol.
+//! - `entropy_analysis()` compares the code lengths against the entropy.
+//! - `region_entropy()` gives the entropy of each window of the text.
+//! - `symbol_cost()` reports how many bits each character takes up.
+//! - `code_length_histogram()` counts the codes of each length, as DEFLATE needs.
+//! - `avg_code_length()` compares the code lengths against a fixed width code.
+//! - `encode_to_bitstream_strip_bom()` drops a leading byte order mark, which
+//!   `decode_from_bitstream_restore_bom()` can put back.
+//! - `Codebook` shares one tree between many messages, with an escape code for
+//!   characters it has not seen.
+//! - `Codebook::to_rust_const()` and `to_c_header()` export its codes as source.
+//! - `encode_bytes_with_dict()` compresses bytes against a preset dictionary.
+//! - `encode_digrams()` builds the tree over pairs of characters.
+//! - `encode_bpe()` merges recurring substrings into symbols before coding.
+//! - `encode_hierarchical()` splits each character into a block and an offset,
+//!   with a small tree for each, for texts with very large alphabets.
+//! - `encode_class_partitioned()` codes letters, digits, whitespace and the
+//!   rest each with their own tree, behind a selector code for the class.
+//! - `encode_fixed_alphabet()` stores a known small alphabet in place of the
+//!   characters in the tree.
+//! - `encode_lines()` encodes a list of lines so it splits back exactly.
+//! - `encode_map()` and `decode_map()` store a map of strings, sorted by key.
+//! - `encode_shared()` encodes several texts under one tree, each decodable alone.
+//! - `RecordCodec` compresses tabular records with a tree per column.
+//! - `decode_reference()` reads JPEG and DEFLATE style Huffman containers.
+//! - `english_codebook()` is a built in `Codebook` for short English texts.
+//! - `build_codebook_smoothed()` gives every character of an alphabet a code.
+//! - `build_codebook_from_paths()` trains one `Codebook` on several files.
+//! - `frame()` and `deframe()` length-prefix several streams so they can be sent
+//!   back to back and split apart again.
+//! - `MAX_ALPHABET_SIZE` is the most symbols a tree can be built over.
+//! - `prelude` re-exports the commonly used items.
+use crate::bits::{BitBundle, BitOrder, BitRead, BitWrite, BitWriter, PackedBits};
+use crate::checksum::{crc32, Crc32};
 use anyhow::{anyhow, Ok, Result};
-use std::collections::{BinaryHeap, HashMap};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
 use std::cmp::Ordering;
+use std::fs;
+use std::io::Read;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
 
-#[derive(Clone, PartialEq, Eq)]
-struct Node {
+/// A node of a Huffman tree. Leaves hold a character, and internal nodes hold
+/// the two subtrees reached by a `0` and a `1` bit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
     ch:    Option<char>,
     left:  Option<Box<Node>>,
     right: Option<Box<Node>>,
@@ -36,9 +133,30 @@ impl Node {
             right: Some(right),
         }
     }
+
+    // The escape symbol is a leaf without a character
+    fn new_escape() -> Self {
+        Self {
+            ch: None,
+            left: None,
+            right: None,
+        }
+    }
+
+    fn is_escape(&self) -> bool {
+        self.ch.is_none() && self.left.is_none() && self.right.is_none()
+    }
+
+    /// The code of each character under this tree, shortest first and then
+    /// by character. A tree of a single leaf gives it a one bit code.
+    pub fn codes(&self) -> Vec<(char, String)> {
+        let mut codes: Vec<_> = assign_codes(self).into_iter().collect();
+        codes.sort_unstable_by(|(a, a_code), (b, b_code)| (a_code.len(), a).cmp(&(b_code.len(), b)));
+        codes
+    }
 }
 
-#[derive(PartialEq, Eq)]
+// A weighted leaf to build a tree from
 struct Branch {
     node: Box<Node>,
     freq: usize,
@@ -50,54 +168,23 @@ impl Branch {
     }
 }
 
-impl PartialOrd for Branch {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(other.freq.cmp(&self.freq))
-    }
+// A node in the heap while building a tree, by its place in the list of
+// leaves and merged nodes
+#[derive(Debug, PartialEq, Eq)]
+struct HeapBranch {
+    node: usize,
+    freq: usize,
 }
 
-impl Ord for Branch {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.partial_cmp(other).unwrap()
+impl PartialOrd for HeapBranch {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
-struct BitBundle<'a> {
-    data: &'a [u8],
-    byte_idx: usize,
-    bit_idx: u8,
-}
-
-impl<'a> BitBundle<'a> {
-    fn new(data: &'a [u8]) -> Self {
-        Self { data, byte_idx: 0, bit_idx: 0 }
-    }
-
-    fn read_bit(&mut self) -> Option<u8> {
-        if self.byte_idx >= self.data.len() {
-            return None;
-        }
-
-        let bit = (self.data[self.byte_idx] >> (7 - self.bit_idx)) & 1;
-        self.bit_idx += 1;
-        if self.bit_idx == 8 {
-            self.byte_idx += 1;
-            self.bit_idx = 0;
-        }
-
-        Some(bit)
-    }
-
-    fn read_byte(&mut self) -> Option<u8> {
-        let mut byte: u8 = 0;
-        for _ in 0..8 {
-            if let Some(bit) = self.read_bit() {
-                byte = (byte << 1) | bit;
-            } else {
-                return None;
-            }
-        }
-        Some(byte)
+impl Ord for HeapBranch {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.freq.cmp(&self.freq)
     }
 }
 
@@ -106,41 +193,85 @@ impl<'a> BitBundle<'a> {
 //     u16::from_ne_bytes([1, 0]) == 1
 // }
 
-// Build a Huffman tree and discard frequencies (greatly reduces the size of the tree when serialised)
-fn gen_tree(input: &str) -> Node {
-    // Count the characters
-    let mut char_count: HashMap<char, usize> = HashMap::new();
+// Count the characters. Ordered keys keep the tree, and so the codes,
+// identical between runs when frequencies tie.
+fn count_chars(input: &str) -> BTreeMap<char, usize> {
+    let mut char_count: BTreeMap<char, usize> = BTreeMap::new();
     for c in input.chars() {
         *char_count.entry(c).or_insert(0) += 1;
     }
 
-    // Populate a min-heap to start building a tree
-    let mut tree: BinaryHeap<Branch> = char_count
+    char_count
+}
+
+// Build a Huffman tree and discard frequencies (greatly reduces the size of the tree when serialised).
+// This is `optimal_tree()` over the counts of the text.
+fn gen_tree(input: &str) -> Node {
+    tree_from_counts(count_chars(input))
+}
+
+fn tree_from_counts(counts: BTreeMap<char, usize>) -> Node {
+    let leaves = counts
         .into_iter()
         .map(|(ch, freq)| Branch::new(Box::new(Node::new_leaf(ch)), freq))
         .collect();
 
-    // Build the Huffman tree using greedy algorithm
-    while tree.len() > 1 {
-        let left = Box::new(tree.pop().unwrap());
-        let right = Box::new(tree.pop().unwrap());
+    build_huffman(leaves)
+}
 
-        let interior = Branch {
-            node: Box::new(Node::new_node(left.node, right.node)),
-            freq: left.freq + right.freq,
-        };
+// Build the Huffman tree from weighted leaves using greedy algorithm
+fn build_huffman(leaves: Vec<Branch>) -> Node {
+    let (freqs, mut nodes): (Vec<usize>, Vec<Option<Box<Node>>>) =
+        leaves.into_iter().map(|leaf| (leaf.freq, Some(leaf.node))).unzip();
+    let mut merges = Vec::with_capacity(nodes.len());
+    let root = huffman_merges(freqs, &mut Vec::new(), &mut merges);
+
+    for (left, right) in merges {
+        let left = nodes[left].take().unwrap();
+        let right = nodes[right].take().unwrap();
+        nodes.push(Some(Box::new(Node::new_node(left, right))));
+    }
 
-        tree.push(interior);
+    // Moving the root out of its box avoids a deep copy of the whole tree
+    *nodes[root].take().unwrap()
+}
+
+// Runs the greedy merges over leaves with the weights `freqs`, numbered from
+// zero, and returns the root. Each merged node takes the next number after
+// the leaves and earlier merges, and `merges` gets the pair it joins. `heap`
+// is working space, so that callers can keep it between builds.
+fn huffman_merges(
+    freqs: impl IntoIterator<Item = usize>,
+    heap: &mut Vec<HeapBranch>,
+    merges: &mut Vec<(usize, usize)>,
+) -> usize {
+    heap.clear();
+    heap.extend(freqs.into_iter().enumerate().map(|(node, freq)| HeapBranch { node, freq }));
+    let leaves = heap.len();
+    merges.clear();
+
+    // Populate a min-heap to start building a tree
+    let mut tree = BinaryHeap::from(std::mem::take(heap));
+    while tree.len() > 1 {
+        let left = tree.pop().unwrap();
+        let right = tree.pop().unwrap();
+        merges.push((left.node, right.node));
+        tree.push(HeapBranch { node: leaves + merges.len() - 1, freq: left.freq + right.freq });
     }
 
     // The root of the tree is the final node left in the heap
-    tree.pop().unwrap().node.as_ref().to_owned()
+    let root = tree.pop().unwrap().node;
+    *heap = tree.into_vec();
+
+    root
 }
 
 fn assign_codes(root: &Node) -> HashMap<char, String> {
-    // Generate the codes
+    // Generate the codes. A tree that is a single leaf still needs one bit for
+    // each character.
     let mut codes = HashMap::new();
-    _assign_codes(root, &mut codes, String::new());
+    let code = if root.ch.is_some() { "0" } else { "" };
+    _assign_codes(root, &mut codes, code.to_string());
     codes
 }
 
@@ -158,73 +289,205 @@ fn _assign_codes(node: &Node, codes: &mut HashMap<char, String>, code: String) {
     }
 }
 
-// Convert a String of bits to a vector of bytes
-fn bits_to_bytes(bits: String) -> Vec<u8> {
-    let mut data = Vec::new();
-    let mut chunk_start = 0;
-    while let Some(chunk) = bits.get(chunk_start..chunk_start + 8) {
-        data.push(u8::from_str_radix(chunk, 2).unwrap());
-        chunk_start += 8;
+// Assign canonical codes from code lengths. Symbols are ordered by code length
+// and then by codepoint, and each code is the previous one plus one, shifted
+// left whenever the length grows.
+fn assign_canonical(mut lengths: Vec<(char, usize)>) -> Vec<(char, String)> {
+    lengths.sort_unstable_by_key(|&(ch, len)| (len, ch));
+
+    assign_canonical_sorted(lengths)
+}
+
+// Same as `assign_canonical()`, for lengths already in the order codes are given out
+fn assign_canonical_sorted(lengths: Vec<(char, usize)>) -> Vec<(char, String)> {
+    let mut codes = Vec::with_capacity(lengths.len());
+    let mut code: u64 = 0;
+    let mut prev_len = 0;
+    for (ch, len) in lengths {
+        code <<= len - prev_len;
+        codes.push((ch, format!("{:0width$b}", code, width = len)));
+        code += 1;
+        prev_len = len;
+    }
+
+    codes
+}
+
+/// The most distinct symbols a tree can be built over, being the number of
+/// Unicode scalar values. Encoders of symbols other than characters stand in
+/// for each with a character, and fail with an error naming this limit once
+/// they run out, as do decoders reading a symbol table larger than it.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{decode_digrams, encode_fixed_alphabet, MAX_ALPHABET_SIZE};
+/// 
+/// assert_eq!((0..=char::MAX as u32).filter_map(char::from_u32).count(), MAX_ALPHABET_SIZE);
+/// 
+/// // An alphabet one symbol past the limit
+/// let alphabet = vec!['a'; MAX_ALPHABET_SIZE + 1];
+/// let error = encode_fixed_alphabet("a", &alphabet).unwrap_err().to_string();
+/// assert!(error.contains(&MAX_ALPHABET_SIZE.to_string()), "{error}");
+/// 
+/// // A symbol table claiming two million entries, as a variable width number
+/// let oversized = [0xDE, 0x84, 0x80, 0x01, b'a'];
+/// let error = decode_digrams(&oversized).unwrap_err().to_string();
+/// assert!(error.contains(&MAX_ALPHABET_SIZE.to_string()), "{error}");
+/// ```
+pub const MAX_ALPHABET_SIZE: usize = 0x11_0000 - 0x800;
+
+// Fails with a clear error for alphabets too large to stand in for with characters
+fn check_alphabet(size: usize) -> Result<()> {
+    if size > MAX_ALPHABET_SIZE {
+        return Err(anyhow!(
+            "Alphabet of {} symbols is larger than the limit of {}.",
+            size,
+            MAX_ALPHABET_SIZE
+        ));
     }
+    Ok(())
+}
+
+// Stand in for the symbol at `index` with a character, so that trees over
+// symbols other than characters can be built and stored as usual. The
+// surrogate range is skipped, as it holds no characters.
+fn index_to_char(index: usize) -> Option<char> {
+    let code = if index < 0xD800 { index } else { index + 0x800 };
+    char::from_u32(u32::try_from(code).ok()?)
+}
 
-    data
+fn char_to_index(ch: char) -> usize {
+    let code = ch as usize;
+    if code < 0xD800 { code } else { code - 0x800 }
+}
+
+// Map each byte to the character with the same value, as Latin-1 does
+fn bytes_to_latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&byte| char::from(byte)).collect()
+}
+
+fn latin1_to_bytes(text: &str) -> Result<Vec<u8>> {
+    text.chars()
+        .map(|ch| u8::try_from(ch).map_err(|_| anyhow!("Character {:?} is not a byte.", ch)))
+        .collect()
 }
 
 // Convert unicode bytes to 32-bit Unicode character
-fn vec_to_char(bytes: Vec<u8>) -> char {
-    std::str::from_utf8(&bytes).unwrap().chars().next().unwrap()
+fn vec_to_char(bytes: Vec<u8>) -> Option<char> {
+    std::str::from_utf8(&bytes).ok()?.chars().next()
 }
 
 // Recursive function to traverse the tree
-fn traverse_tree(node: &Node, bit_str: &mut String) {
-    if let Some(ch) = node.ch {
-        bit_str.push('1');
-        // As it turns out, endianness is abstracted away
-        for &ch in ch.to_string().as_bytes() {
-            bit_str.push_str(&format!("{:08b}", &ch));
+fn traverse_tree(node: &Node, writer: &mut BitWriter, leaves: LeafFormat) {
+    if node.is_escape() {
+        writer.write_bit(1);
+        match leaves {
+            LeafFormat::Utf8 => writer.write_byte(ESCAPE_BYTE),
+            LeafFormat::Codepoint => writer.write_bits(ESCAPE_CODEPOINT as u64, 32),
+        }
+    } else if let Some(ch) = node.ch {
+        writer.write_bit(1);
+        match leaves {
+            // As it turns out, endianness is abstracted away
+            LeafFormat::Utf8 => {
+                for &byte in ch.to_string().as_bytes() {
+                    writer.write_byte(byte);
+                }
+            }
+            LeafFormat::Codepoint => writer.write_bits(ch as u64, 32),
         }
     } else {
-        bit_str.push('0');
-        traverse_tree(node.left.as_ref().unwrap(), bit_str);
-        traverse_tree(node.right.as_ref().unwrap(), bit_str);
+        writer.write_bit(0);
+        traverse_tree(node.left.as_ref().unwrap(), writer, leaves);
+        traverse_tree(node.right.as_ref().unwrap(), writer, leaves);
     }
 }
 
+// Number of bits `traverse_tree()` writes for the tree, without writing them
+fn tree_bits(node: &Node, leaves: LeafFormat) -> usize {
+    let leaf_bits = |ch: Option<char>| match leaves {
+        LeafFormat::Utf8 => 8 * ch.map_or(1, char::len_utf8),
+        LeafFormat::Codepoint => 32,
+    };
+
+    if node.is_escape() {
+        1 + leaf_bits(None)
+    } else if node.ch.is_some() {
+        1 + leaf_bits(node.ch)
+    } else {
+        1 + tree_bits(node.left.as_ref().unwrap(), leaves) + tree_bits(node.right.as_ref().unwrap(), leaves)
+    }
+}
+
+// Stands in for the character of an escape leaf, as it never starts a UTF-8 sequence
+const ESCAPE_BYTE: u8 = 0xFF;
+// The same for fixed width leaves, being well past the last codepoint
+const ESCAPE_CODEPOINT: u32 = u32::MAX;
+
 // Serialise binary tree. This is done via preoder traversal of the tree.
 // Preliminary tests show this compresses the tree to a fifth of the original.
-fn ser_tree(tree: Node) -> Vec<u8> {
-    let mut bit_str = String::new();
+fn ser_tree(tree: Node, leaves: LeafFormat) -> Vec<u8> {
+    let mut writer = BitWriter::new();
 
-    traverse_tree(&tree, &mut bit_str);
+    traverse_tree(&tree, &mut writer, leaves);
+
+    writer.finish().bytes
+}
 
-    let pack = (8 - bit_str.len() % 8) % 8;
-    bit_str.push_str(&"0".repeat(pack));
+// Read a single UTF-8 character, using the leading byte to tell how many
+// bytes follow
+fn read_char(bundle: &mut BitBundle) -> Option<char> {
+    let ch = bundle.read_byte()?;
+    read_char_from(ch, bundle)
+}
 
-    bits_to_bytes(bit_str)
+// Same as `read_char()`, for when the leading byte has already been read
+fn read_char_from(ch: u8, bundle: &mut BitBundle) -> Option<char> {
+    if ch & 0x80 == 0 {
+        Some(char::from(ch))
+    } else {
+        let mut unicode = vec![ch];
+        unicode.push(bundle.read_byte()?);
+        if ch & 0xE0 == 0xE0 {
+            unicode.push(bundle.read_byte()?);
+        }
+        if ch & 0xF0 == 0xF0 {
+            unicode.push(bundle.read_byte()?);
+        }
+        vec_to_char(unicode)
+    }
 }
 
-fn build_tree(bundle: &mut BitBundle) -> Option<Node> {
+// Deepest tree accepted when reading, far beyond anything real frequencies give
+const MAX_TREE_DEPTH: usize = 256;
+
+// Returns `None` if the data runs out or does not describe a tree
+fn build_tree(bundle: &mut BitBundle, depth: usize, leaves: LeafFormat) -> Option<Node> {
+    if depth > MAX_TREE_DEPTH {
+        return None;
+    }
+
     if let Some(bit) = bundle.read_bit() {
         if bit == 1 {
             // Leaf node
-            let ch = bundle.read_byte().unwrap();
-            if ch & 0x80 == 0 {
-                return Some(Node::new_leaf(char::from(ch)));
-            } else {
-                let mut unicode = vec![ch];
-                unicode.push(bundle.read_byte().unwrap());
-                if ch & 0xE0 == 0xE0 {
-                    unicode.push(bundle.read_byte().unwrap());
-                }
-                if ch & 0xF0 == 0xF0 {
-                    unicode.push(bundle.read_byte().unwrap());
+            if leaves == LeafFormat::Codepoint {
+                let codepoint = bundle.read_bits(32)? as u32;
+                if codepoint == ESCAPE_CODEPOINT {
+                    return Some(Node::new_escape());
                 }
-                return Some(Node::new_leaf(vec_to_char(unicode)));
+                return Some(Node::new_leaf(char::from_u32(codepoint)?));
             }
+            let lead = bundle.read_byte()?;
+            if lead == ESCAPE_BYTE {
+                return Some(Node::new_escape());
+            }
+            return Some(Node::new_leaf(read_char_from(lead, bundle)?));
         } else if bundle.byte_idx + 1 != bundle.data.len() {
             // Internal node
-            let left = Box::new(build_tree(bundle).unwrap());
-            let right = Box::new(build_tree(bundle).unwrap());
+            let left = Box::new(build_tree(bundle, depth + 1, leaves)?);
+            let right = Box::new(build_tree(bundle, depth + 1, leaves)?);
             return Some(Node::new_node(left, right));
         }
     }
@@ -233,11 +496,96 @@ fn build_tree(bundle: &mut BitBundle) -> Option<Node> {
 }
 
 // Restores binary tree from serialisation
-fn des_tree(bytes: &[u8]) -> Node {
+fn des_tree(bytes: &[u8], leaves: LeafFormat) -> Result<Node> {
     let mut bundle = BitBundle::new(bytes);
-    build_tree(&mut bundle).unwrap()
+    let tree = build_tree(&mut bundle, 0, leaves).ok_or_else(|| anyhow!("Malformed tree."))?;
+    validate_tree(&tree)?;
+
+    Ok(tree)
 }
-#[cfg(not(feature = "vwe_header"))]
+
+// Longest canonical code a table may describe. Codes are built in 64 bits,
+// with room to count one past the last code.
+const MAX_CANONICAL_LEN: usize = 63;
+
+// Serialise canonical codes as the longest code length in a byte, the number
+// of codes of each length from one bit up as variable width numbers, and then
+// the characters in code order
+fn ser_canonical(codes: &[(char, String)], leaves: LeafFormat) -> Result<Vec<u8>> {
+    let max_len = codes.iter().map(|(_, code)| code.len()).max().unwrap_or(0);
+    if max_len > MAX_CANONICAL_LEN {
+        return Err(anyhow!("Code is too long for a canonical table."));
+    }
+
+    let mut counts = vec![0; max_len];
+    for (_, code) in codes {
+        counts[code.len() - 1] += 1;
+    }
+
+    let mut writer = BitWriter::new();
+    writer.write_byte(max_len as u8);
+    for count in counts {
+        for byte in uint_to_vwe(count)? {
+            writer.write_byte(byte);
+        }
+    }
+    for &(ch, _) in codes {
+        match leaves {
+            LeafFormat::Utf8 => ch.to_string().bytes().for_each(|byte| writer.write_byte(byte)),
+            LeafFormat::Codepoint => writer.write_bits(ch as u64, 32),
+        }
+    }
+
+    Ok(writer.finish().bytes)
+}
+
+// Restores the tree of a canonical code table
+fn des_canonical(bytes: &[u8], leaves: LeafFormat) -> Result<Node> {
+    let mut bundle = BitBundle::new(bytes);
+    let truncated = || anyhow!("Truncated code table.");
+
+    let max_len = bundle.read_byte().ok_or_else(truncated)? as usize;
+    if max_len > MAX_CANONICAL_LEN {
+        return Err(anyhow!("Invalid code table."));
+    }
+    let mut counts = Vec::with_capacity(max_len);
+    for _ in 0..max_len {
+        counts.push(read_vwe(&mut bundle)?);
+    }
+
+    // More codes than the lengths have room for cannot be told apart
+    let room: u128 = counts
+        .iter()
+        .enumerate()
+        .map(|(idx, &count)| (count as u128) << (MAX_CANONICAL_LEN - idx - 1))
+        .sum();
+    if room > 1 << MAX_CANONICAL_LEN {
+        return Err(anyhow!("Invalid code table."));
+    }
+
+    let mut lengths = Vec::new();
+    for (idx, count) in counts.into_iter().enumerate() {
+        for _ in 0..count {
+            let ch = match leaves {
+                LeafFormat::Utf8 => read_char(&mut bundle),
+                LeafFormat::Codepoint => bundle.read_bits(32).and_then(|code| char::from_u32(code as u32)),
+            };
+            lengths.push((ch.ok_or_else(truncated)?, idx + 1));
+        }
+    }
+
+    tree_from_codes(&assign_canonical_sorted(lengths))
+}
+
+// Restores the tree of a stream, however it was stored
+fn read_tree(sections: &Sections) -> Result<Node> {
+    if sections.flags & FLAG_CANONICAL != 0 {
+        des_canonical(sections.tree, sections.leaves)
+    } else {
+        des_tree(sections.tree, sections.leaves)
+    }
+}
+
 fn split_u16(value: u16) -> Vec<u8> {
     let high = (value >> 8) as u8;
     let low = value as u8;
@@ -245,13 +593,11 @@ fn split_u16(value: u16) -> Vec<u8> {
     vec![high, low]
 }
 
-#[cfg(not(feature = "vwe_header"))]
 fn recombine_u16(bytes: &[u8]) -> usize {
-    (bytes[0] as usize) << 8 | (bytes[1] as usize) << 0
+    (bytes[0] as usize) << 8 | (bytes[1] as usize)
 }
 
-#[cfg(feature = "vwe_header")]
-fn uint_to_vwe(num: usize) -> Result<Vec<u8>> {
+pub(crate) fn uint_to_vwe(num: usize) -> Result<Vec<u8>> {
     if num > 268_435_455 {
         return Err(anyhow!("Number is too large."));
     }
@@ -263,20 +609,24 @@ fn uint_to_vwe(num: usize) -> Result<Vec<u8>> {
     let byte4 = num as u8;
 
     if num < 128 {
-        return Ok(vec![num as u8]);
+        Ok(vec![num as u8])
     } else if num < 16_384 {
-        return Ok(vec![byte3 | 0x80, byte4]);
+        Ok(vec![byte3 | 0x80, byte4])
     } else if num < 2_097_152 {
-        return Ok(vec![byte2 | 0xC0, byte3, byte4]);
+        Ok(vec![byte2 | 0xC0, byte3, byte4])
     } else {
-        return Ok(vec![byte1 | 0xE0, byte2, byte3, byte4]);
+        Ok(vec![byte1 | 0xE0, byte2, byte3, byte4])
     }
 }
 
-#[cfg(feature = "vwe_header")]
-fn vwe_to_uint(chunk: &[u8]) -> (usize, usize) {
+// Returns the decoded number and the number of bytes it occupied
+pub(crate) fn vwe_to_uint(chunk: &[u8]) -> Result<(usize, usize)> {
+    if chunk.is_empty() {
+        return Err(anyhow!("Missing variable width number."));
+    }
+
     if chunk[0] & 0x80 == 0 {
-        return (chunk[0] as usize, 1);
+        Ok((chunk[0] as usize, 1))
     } else {
         let mut width = 1;
         let mut first = chunk[0];
@@ -290,177 +640,6224 @@ fn vwe_to_uint(chunk: &[u8]) -> (usize, usize) {
                 break;
             }
         }
-        let mut byte_vec = vec![first];
-        for i in 1..width {
-            byte_vec.push(chunk[i]);
+        if chunk.len() < width {
+            return Err(anyhow!("Truncated variable width number."));
         }
+        let mut byte_vec = vec![first];
+        byte_vec.extend_from_slice(&chunk[1..width]);
         let mut num: u32 = 0;
         for i in 0..width {
             let byte = byte_vec.pop().unwrap() as u32;
             num |= byte << (8 * i);
         }
-        return (num as usize, width);
+        Ok((num as usize, width))
     }
 }
 
-// Main encoder function
-fn encode(input: &str, codes: &HashMap<char, String>) -> String {
-    let mut output = String::new();
+// Flags opening every stream, stored as a variable width number. The lowest
+// two bits give the width of the tree length that follows.
+const WIDTH_MASK: usize = 0b11;
+const WIDTH_U8: usize = 0;
+const WIDTH_U16: usize = 1;
+const WIDTH_U32: usize = 2;
+const WIDTH_VWE: usize = 3;
+// A CRC-32 of the text follows the tree length
+const FLAG_CHECKSUM: usize = 1 << 2;
+// The number of characters in the text follows the tree length
+const FLAG_LENGTH: usize = 1 << 3;
+// Bits four and five give the encoding the text came from
+const ENCODING_MASK: usize = 0b11 << 4;
+const ENCODING_UTF16: usize = 1 << 4;
+const ENCODING_LATIN1: usize = 2 << 4;
+// Set when the data bits fill each byte from the least significant end
+const FLAG_LSB_FIRST: usize = 1 << 6;
+// Set when a trailer with the original file name and time follows the data
+const FLAG_TRAILER: usize = 1 << 7;
+// Set when the stream is a block size followed by framed streams, one per block
+const FLAG_BLOCKS: usize = 1 << 8;
+// Set when each leaf of the tree holds its codepoint as four bytes
+const FLAG_CODEPOINT_LEAVES: usize = 1 << 9;
+// Set when the text follows the flags as plain UTF-8, with no tree at all,
+// after the number of characters when `FLAG_LENGTH` is also set
+const FLAG_RAW: usize = 1 << 10;
+// Set when the tree is stored as the lengths of canonical codes
+const FLAG_CANONICAL: usize = 1 << 11;
+// Set when runs of spaces and tabs were collapsed into run characters
+const FLAG_WHITESPACE_RUNS: usize = 1 << 12;
+// Set when the text is one character repeated, which follows the flags as
+// UTF-8 with the number of repeats after it, and there is no tree
+const FLAG_SINGLE: usize = 1 << 13;
+// Set when a leading byte order mark was removed before encoding
+const FLAG_BOM: usize = 1 << 14;
 
-    for ch in input.chars() {
-        let t = codes.get(&ch).unwrap();
-        output.push_str(t);
+// Runs of spaces and tabs are collapsed into characters of the last private
+// use plane, a run of `n` spaces becoming `SPACE_RUNS + n` and one of tabs
+// `TAB_RUNS + n`. Texts using the plane themselves are left as they are.
+const SPACE_RUNS: u32 = 0x10_0000;
+const TAB_RUNS: u32 = 0x10_8000;
+const MAX_RUN: usize = 0x7FFF;
+
+// Collapse runs of two or more spaces or tabs, or `None` when the text uses
+// the run characters itself
+fn collapse_runs(input: &str) -> Option<String> {
+    if input.chars().any(|ch| ch as u32 >= SPACE_RUNS) {
+        return None;
     }
 
-    output
+    let mut output = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+    while let Some(ch) = chars.next() {
+        let base = match ch {
+            ' ' => SPACE_RUNS,
+            '\t' => TAB_RUNS,
+            _ => {
+                output.push(ch);
+                continue;
+            }
+        };
+        let mut run = 1;
+        while run < MAX_RUN && chars.peek() == Some(&ch) {
+            chars.next();
+            run += 1;
+        }
+        if run == 1 {
+            output.push(ch);
+        } else {
+            output.push(char::from_u32(base + run as u32).unwrap());
+        }
+    }
+
+    Some(output)
 }
 
-/// A fun little function for a quick output showing codes and an encoded
-/// string. This function is one way.
-/// 
-/// ## Example
-/// 
-/// 
-/// ```
-/// use codecs::huffman::easy_encode;
-/// 
-/// let input = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culpa qui officia deserunt mollit anim id est laborum.";
-/// let (_, output) = easy_encode(input).unwrap();
-/// println!("Encoded string: {output}");
-/// ```
-pub fn easy_encode(input: &str) -> Result<(HashMap<char, String>, String)> {
-    if input.is_empty() {
-        return Err(anyhow!("Input string is empty."));
+// Hands `emit` the whitespace a run character stands for, or the character
+// itself if it is not one
+fn expand_run(ch: char, emit: &mut impl FnMut(char) -> ControlFlow<()>) -> ControlFlow<()> {
+    let (ws, run) = match ch as u32 {
+        code if code >= TAB_RUNS => ('\t', code - TAB_RUNS),
+        code if code >= SPACE_RUNS => (' ', code - SPACE_RUNS),
+        _ => return emit(ch),
+    };
+    for _ in 0..run {
+        emit(ws)?;
     }
 
-    let tree = gen_tree(input);
-    let codes = assign_codes(&tree);
-    let encoded = encode(input, &codes);
+    ControlFlow::Continue(())
+}
 
-    Ok((codes, encoded))
+// Decoders that work on the characters of the tree directly cannot expand runs
+fn reject_runs(sections: &Sections) -> Result<()> {
+    if sections.flags & FLAG_WHITESPACE_RUNS != 0 {
+        return Err(anyhow!("Stream collapses whitespace runs, see `decode_from_bitstream()`."));
+    }
+
+    Ok(())
 }
 
-/// Encodes a text and packages it with the tree in a compact binary format for portability.
-/// Useful for transmission or archival purposes, and can be decompressed later.
-/// 
-/// ## Example
-/// 
-/// 
-/// ```
-/// use codecs::huffman::encode_to_bitstream;
-/// 
-/// let input = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culpa qui officia deserunt mollit anim id est laborum.";
-/// let data = match encode_to_bitstream(&input) {
-///     Ok(data) => data,
-///     Err(err) => panic!("Something went wrong: {}", err),
-/// };
-/// 
-/// if let Err(err) = fs::write("output.hmc", data) {
-///     eprintln!("Error writing to file: {}", err);
-/// } else {
-///     println!("Data successfully written.");
-/// }
-/// ```
-pub fn encode_to_bitstream(input: &str) -> Result<Vec<u8>> {
-    if input.is_empty() {
-        return Err(anyhow!("Input string is empty."));
+// Texts shorter than this many bytes are stored raw by `encode_to_bitstream()`,
+// as the tree and header would otherwise be several times their size
+const RAW_BELOW: usize = 8;
+
+// The text of a raw or single character stream, or `None` for a stream with
+// a tree
+fn raw_text(input: &[u8]) -> Result<Option<Cow<'_, str>>> {
+    if let Some((ch, count)) = single_symbol(input)? {
+        return Ok(Some(Cow::Owned(std::iter::repeat_n(ch, count).collect())));
     }
 
-    let tree = gen_tree(input);
-    let codes = assign_codes(&tree);
-    let mut encoded = encode(input, &codes);
-    let stree = ser_tree(tree);
-    let pack = (8 - encoded.len() % 8) % 8;
-    encoded.push_str(&"0".repeat(pack));
+    match split_raw(input)? {
+        Some((text, [])) => Ok(Some(Cow::Borrowed(text))),
+        Some(_) => Err(anyhow!("Raw text is longer than its stored length.")),
+        None => Ok(None),
+    }
+}
 
-    // Serialise all data according to schema
-    let mut glob = Vec::new();
+// The text of a raw stream and whatever follows it, or `None` for any other
+// stream. The text runs to the end unless the stream stores its number of
+// characters.
+fn split_raw(input: &[u8]) -> Result<Option<(&str, &[u8])>> {
+    let (flags, mut idx) = vwe_to_uint(input)?;
+    if flags & FLAG_RAW == 0 {
+        return Ok(None);
+    }
 
-    // Fixed width header
-    #[cfg(not(feature = "vwe_header"))]
-    glob.extend(split_u16(stree.len() as u16));
-    // Variable width header
-    #[cfg(feature = "vwe_header")]
-    glob.extend(uint_to_vwe(stree.len()).unwrap());
-    glob.extend_from_slice(&stree);
-    glob.push(pack as u8);
-    glob.extend_from_slice(&bits_to_bytes(encoded));
+    let mut end = input.len();
+    if flags & FLAG_LENGTH != 0 {
+        let (count, width) = vwe_to_uint(&input[idx..])?;
+        idx += width;
+        end = idx;
+        for _ in 0..count {
+            let lead = *input.get(end).ok_or_else(|| anyhow!("Truncated raw text."))?;
+            end += match lead.leading_ones() {
+                0 => 1,
+                ones @ 2..=4 => ones as usize,
+                _ => return Err(anyhow!("Raw text is not valid UTF-8.")),
+            };
+        }
+        if end > input.len() {
+            return Err(anyhow!("Truncated raw text."));
+        }
+    }
 
-    Ok(glob)
+    let text = std::str::from_utf8(&input[idx..end]).map_err(|_| anyhow!("Raw text is not valid UTF-8."))?;
+
+    Ok(Some((text, &input[end..])))
 }
 
-/// Decompresses a raw binary format and retrieves the tree and encoded data for decoding.
-/// 
-/// ## Example
-/// 
-/// 
-/// ```
-/// use codecs::huffman::decode_from_bitstream;
-/// 
-/// let file = "output.hmc";
-/// let data: Vec<u8> = fs::read(file).expect("File not found.");
-/// let output = decode_from_bitstream(&data)?;
-/// println!("{output}");
-/// ```
-pub fn decode_from_bitstream(input: &[u8]) -> Result<String> {
-    if input.len() < 4 {
+// The character and number of repeats of a single character stream, or `None`
+// for any other stream
+fn single_symbol(input: &[u8]) -> Result<Option<(char, usize)>> {
+    match split_single(input)? {
+        Some((ch, count, [])) => Ok(Some((ch, count))),
+        Some(_) => Err(anyhow!("Malformed input.")),
+        None => Ok(None),
+    }
+}
+
+// Same as `single_symbol()`, also returning whatever follows the stream
+fn split_single(input: &[u8]) -> Result<Option<(char, usize, &[u8])>> {
+    let (flags, flags_len) = vwe_to_uint(input)?;
+    if flags & FLAG_SINGLE == 0 {
+        return Ok(None);
+    }
+    // The character and count take the place of every other field
+    if flags != FLAG_SINGLE {
         return Err(anyhow!("Malformed input."));
     }
-    let mut output = String::new();
 
-    #[allow(unused_assignments)]
-    let mut tree_len: usize = 0;
-    #[cfg(feature = "vwe_header")]
-    #[allow(unused_assignments)]
-    let mut header_bytes: usize = 0;
-    #[allow(unused_assignments)]
-    let mut tree_bytes: &[u8] = &[];
-    #[allow(unused_assignments)]
-    let mut pack: u8 = 0;
-    #[allow(unused_assignments)]
-    let mut data: Vec<u8> = Vec::new();
-
-    // Deserialise binary data to variables
-    #[cfg(not(feature = "vwe_header"))]
-    // Fixed width tree length header
-    {
-        tree_len = recombine_u16(&input[0..2]);
-        tree_bytes = &input[2..(2 + tree_len)];
-        pack = input[2 + tree_len];
-        data = input[(3 + tree_len)..].to_vec();
-    }
-    // Variable width tree length header
-    #[cfg(feature = "vwe_header")]
-    {
-        (tree_len, header_bytes) = vwe_to_uint(&input[0..4]);
-        tree_bytes = &input[header_bytes..(header_bytes + tree_len)];
-        pack = input[header_bytes + tree_len];
-        data = input[(header_bytes + tree_len + 1)..].to_vec();
-    }
-
-    if tree_bytes.len() < tree_len {
-        return Err(anyhow!("Tree size mismatch."));
+    let ch = read_char(&mut BitBundle::new(&input[flags_len..])).ok_or_else(|| anyhow!("Malformed input."))?;
+    let (count, count_len) = vwe_to_uint(&input[(flags_len + ch.len_utf8())..])?;
+    if count == 0 {
+        return Err(anyhow!("Malformed input."));
     }
 
-    // Decode the data
-    let last_byte = data.len() - 1;
-    let tree = des_tree(tree_bytes);
-    let mut nodeptr = &tree;
-    for (count, byte) in data.iter().enumerate() {
-        let end_bit = if count != last_byte { 0 } else { pack };
-        for i in (end_bit..8).rev() {
-            let bit = (byte >> i) & 1;
-            if bit == 0 {
-                nodeptr = nodeptr.left.as_ref().unwrap();
-            } else {
-                nodeptr = nodeptr.right.as_ref().unwrap();
-            }
-            if let Some(ch) = nodeptr.ch {
-                output.push(ch);
-                nodeptr = &tree;
-            }
-        }
+    Ok(Some((ch, count, &input[(flags_len + ch.len_utf8() + count_len)..])))
+}
+
+// The fields of a raw or single character stream, which has no tree
+struct Untreed<'a> {
+    flags: usize,
+    // Number of characters, if a raw stream stores it
+    length: Option<usize>,
+    header_len: usize,
+    // The raw text, or the character and count
+    body: &'a [u8],
+}
+
+// Splits a raw or single character stream into its fields, or `None` for a
+// stream with a tree
+fn untreed(input: &[u8]) -> Result<Option<Untreed<'_>>> {
+    let (flags, flags_len) = vwe_to_uint(input)?;
+    if single_symbol(input)?.is_some() {
+        return Ok(Some(Untreed { flags, length: None, header_len: flags_len, body: &input[flags_len..] }));
     }
+    let Some(text) = raw_text(input)? else {
+        return Ok(None);
+    };
 
-    Ok(output)
-} 
\ No newline at end of file
+    let header_len = input.len() - text.len();
+    let length = (flags & FLAG_LENGTH != 0).then(|| text.chars().count());
+    Ok(Some(Untreed { flags, length, header_len, body: &input[header_len..] }))
+}
+
+// The single character a text repeats, if it has only one
+fn repeated_char(input: &str) -> Option<char> {
+    let first = input.chars().next()?;
+    input.chars().all(|ch| ch == first).then_some(first)
+}
+
+// Pick the narrowest width for the tree length. Variable width only wins in
+// the range where it needs three bytes.
+#[cfg(not(feature = "vwe_header"))]
+fn tree_len_width(len: usize) -> usize {
+    if len < 256 {
+        WIDTH_U8
+    } else if len < 65_536 {
+        WIDTH_U16
+    } else if len < 2_097_152 {
+        WIDTH_VWE
+    } else {
+        WIDTH_U32
+    }
+}
+
+// Always use a variable width tree length
+#[cfg(feature = "vwe_header")]
+fn tree_len_width(_len: usize) -> usize {
+    WIDTH_VWE
+}
+
+fn write_tree_len(len: usize, width: usize) -> Result<Vec<u8>> {
+    match width {
+        WIDTH_U8 => {
+            let len = u8::try_from(len).map_err(|_| anyhow!("Tree is too large."))?;
+            Ok(vec![len])
+        }
+        WIDTH_U16 => {
+            let len = u16::try_from(len).map_err(|_| anyhow!("Tree is too large."))?;
+            Ok(split_u16(len))
+        }
+        WIDTH_U32 => {
+            let len = u32::try_from(len).map_err(|_| anyhow!("Tree is too large."))?;
+            Ok(len.to_be_bytes().to_vec())
+        }
+        _ => uint_to_vwe(len),
+    }
+}
+
+// Returns the tree length and the number of bytes it occupied
+fn read_tree_len(bytes: &[u8], width: usize, endian: LengthEndian) -> Result<(usize, usize)> {
+    let size = match width {
+        WIDTH_U8 => 1,
+        WIDTH_U16 => 2,
+        WIDTH_U32 => 4,
+        _ => return vwe_to_uint(bytes),
+    };
+    if bytes.len() < size {
+        return Err(anyhow!("Truncated tree length."));
+    }
+
+    match (width, endian) {
+        (WIDTH_U8, _) => Ok((bytes[0] as usize, 1)),
+        (WIDTH_U16, LengthEndian::Big) => Ok((recombine_u16(bytes), 2)),
+        (WIDTH_U16, LengthEndian::Little) => Ok((u16::from_le_bytes([bytes[0], bytes[1]]) as usize, 2)),
+        (_, LengthEndian::Big) => Ok((u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize, 4)),
+        (_, LengthEndian::Little) => Ok((u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize, 4)),
+    }
+}
+
+// The parts of an encoded stream
+struct Sections<'a> {
+    flags: usize,
+    header_len: usize,
+    length: Option<usize>,
+    checksum: Option<u32>,
+    order: BitOrder,
+    leaves: LeafFormat,
+    tree: &'a [u8],
+    pack: u8,
+    data: &'a [u8],
+    meta: Option<HmcMeta>,
+}
+
+// Deserialise binary data into its parts according to schema
+fn split_sections(input: &[u8]) -> Result<Sections<'_>> {
+    split_sections_with(input, LengthEndian::Big)
+}
+
+// Same as `split_sections()`, reading a fixed width tree length in `endian` order
+fn split_sections_with(input: &[u8], endian: LengthEndian) -> Result<Sections<'_>> {
+    let (header, rest) = HmcHeader::parse_with(input, endian)?;
+    let tree_len = header.tree_len;
+    if rest.len() < tree_len + 1 {
+        return Err(anyhow!("Tree size mismatch."));
+    }
+
+    let mut end = rest.len();
+    let mut meta = None;
+    if header.flags & FLAG_TRAILER != 0 {
+        let (trailer, trailer_len) = read_trailer(&rest[(tree_len + 1)..])?;
+        meta = Some(trailer);
+        end -= trailer_len;
+    }
+
+    let flags = header.flags;
+    Ok(Sections {
+        flags,
+        header_len: header.size,
+        length: header.length,
+        checksum: header.checksum,
+        order: if flags & FLAG_LSB_FIRST != 0 { BitOrder::LsbFirst } else { BitOrder::MsbFirst },
+        leaves: if flags & FLAG_CODEPOINT_LEAVES != 0 { LeafFormat::Codepoint } else { LeafFormat::Utf8 },
+        tree: &rest[..tree_len],
+        pack: rest[tree_len],
+        data: &rest[(tree_len + 1)..end],
+        meta,
+    })
+}
+
+/// The fixed fields at the start of a stream, ahead of the tree: the flags,
+/// the length of the tree, and the number of characters and CRC-32 when the
+/// flags say they are there. `parse()` reads them, so that no caller needs to
+/// work out where one field ends and the next begins.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{encode_to_bitstream, format_breakdown, Encoder, HmcHeader};
+/// 
+/// let data = encode_to_bitstream("Lorem ipsum dolor sit amet").unwrap();
+/// let (header, rest) = HmcHeader::parse(&data).unwrap();
+/// assert_eq!(header.length, Some(26));
+/// assert!(header.checksum.is_some());
+/// assert_eq!(header.size + rest.len(), data.len());
+/// assert!(header.size <= HmcHeader::MAX_LEN);
+/// // The tree follows, then the data packing byte and the data
+/// assert_eq!(header.tree_len, format_breakdown(&data).unwrap().tree_bytes);
+/// 
+/// let fast = Encoder::new().integrity(false).encode("Lorem ipsum dolor sit amet").unwrap();
+/// let (header, _) = HmcHeader::parse(&fast).unwrap();
+/// assert_eq!((header.length, header.checksum), (None, None));
+/// 
+/// // Cut short in the checksum, and with no bytes at all
+/// assert!(HmcHeader::parse(&data[..header.size + 2]).is_err());
+/// assert!(HmcHeader::parse(&[]).is_err());
+/// // A raw stream has no header past the flags
+/// assert!(HmcHeader::parse(&encode_to_bitstream("abc").unwrap()).is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HmcHeader {
+    /// Flags, as in `HmcFile`
+    pub flags: usize,
+    /// Bytes taken by the serialised tree that follows the header
+    pub tree_len: usize,
+    /// Number of characters in the text
+    pub length: Option<usize>,
+    /// CRC-32 of the text
+    pub checksum: Option<u32>,
+    /// Bytes taken by the header itself
+    pub size: usize,
+}
+
+impl HmcHeader {
+    /// The most bytes a header can take: four each for the flags, the tree
+    /// length, the number of characters and the checksum.
+    pub const MAX_LEN: usize = 16;
+
+    /// Reads the header of a stream, returning it and the bytes after it.
+    pub fn parse(input: &[u8]) -> Result<(Self, &[u8])> {
+        Self::parse_with(input, LengthEndian::Big)
+    }
+
+    fn parse_with(input: &[u8], endian: LengthEndian) -> Result<(Self, &[u8])> {
+        let (flags, flags_len) = vwe_to_uint(input)?;
+        if flags & FLAG_BLOCKS != 0 {
+            return Err(anyhow!("Stream is split into blocks, see `decode_blocks()`."));
+        }
+        if flags & FLAG_RAW != 0 {
+            return Err(anyhow!("Stream holds raw text with no tree."));
+        }
+        if flags & FLAG_SINGLE != 0 {
+            return Err(anyhow!("Stream holds a single repeated character with no tree."));
+        }
+        let (tree_len, len_width) = read_tree_len(&input[flags_len..], flags & WIDTH_MASK, endian)?;
+        let mut size = flags_len + len_width;
+
+        let mut length = None;
+        if flags & FLAG_LENGTH != 0 {
+            let (count, width) = vwe_to_uint(&input[size..])?;
+            length = Some(count);
+            size += width;
+        }
+
+        let mut checksum = None;
+        if flags & FLAG_CHECKSUM != 0 {
+            let bytes = input
+                .get(size..(size + 4))
+                .ok_or_else(|| anyhow!("Truncated checksum."))?;
+            checksum = Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+            size += 4;
+        }
+
+        Ok((Self { flags, tree_len, length, checksum, size }, &input[size..]))
+    }
+}
+
+// The trailer is the file name, the time as eight bytes and then the length of
+// the name as two bytes, so that it can be found from the end of the stream
+fn write_trailer(meta: &HmcMeta) -> Result<Vec<u8>> {
+    let name_len = u16::try_from(meta.filename.len()).map_err(|_| anyhow!("File name is too long."))?;
+
+    let mut trailer = meta.filename.as_bytes().to_vec();
+    trailer.extend_from_slice(&meta.mtime.to_be_bytes());
+    trailer.extend(split_u16(name_len));
+
+    Ok(trailer)
+}
+
+// Returns the trailer at the end of `bytes` and the number of bytes it occupied
+fn read_trailer(bytes: &[u8]) -> Result<(HmcMeta, usize)> {
+    let truncated = || anyhow!("Truncated trailer.");
+    let len_at = bytes.len().checked_sub(2).ok_or_else(truncated)?;
+    let name_len = recombine_u16(&bytes[len_at..]);
+    let name_at = len_at.checked_sub(8 + name_len).ok_or_else(truncated)?;
+
+    let filename = std::str::from_utf8(&bytes[name_at..(name_at + name_len)])
+        .map_err(|_| anyhow!("File name is not valid UTF-8."))?
+        .to_string();
+    let mut mtime = [0; 8];
+    mtime.copy_from_slice(&bytes[(name_at + name_len)..len_at]);
+
+    Ok((HmcMeta { filename, mtime: u64::from_be_bytes(mtime) }, bytes.len() - name_at))
+}
+
+// Reads bits until the tree yields one character
+fn read_symbol(tree: &Node, bundle: &mut BitBundle) -> Result<char> {
+    // A tree that is a single leaf codes each character as a single bit
+    if let Some(ch) = tree.ch {
+        bundle.read_bit().ok_or_else(|| anyhow!("Truncated data."))?;
+        return Ok(ch);
+    }
+
+    let mut nodeptr = tree;
+    loop {
+        let bit = bundle.read_bit().ok_or_else(|| anyhow!("Truncated data."))?;
+        let next = if bit == 0 { &nodeptr.left } else { &nodeptr.right };
+        nodeptr = next.as_ref().ok_or_else(|| anyhow!("Invalid code in data."))?;
+        if let Some(ch) = nodeptr.ch {
+            return Ok(ch);
+        } else if nodeptr.is_escape() {
+            return read_char(bundle).ok_or_else(|| anyhow!("Truncated escaped character."));
+        }
+    }
+}
+
+// Read a variable width number written byte by byte into a bitstream
+fn read_vwe(bundle: &mut BitBundle) -> Result<usize> {
+    let first = bundle.read_byte().ok_or_else(|| anyhow!("Truncated data."))?;
+    let mut bytes = vec![first];
+    for _ in 0..first.leading_ones().min(3) {
+        bytes.push(bundle.read_byte().ok_or_else(|| anyhow!("Truncated data."))?);
+    }
+
+    Ok(vwe_to_uint(&bytes)?.0)
+}
+
+// Walks the payload bits through the tree, handing each decoded character to
+// `emit`. An escape leaf is followed by the literal UTF-8 bytes of the character.
+fn walk_payload(tree: &Node, payload: &[u8], pad: u8, mut emit: impl FnMut(char)) -> Result<()> {
+    walk_payload_until(tree, payload, pad, BitOrder::MsbFirst, |ch| {
+        emit(ch);
+        ControlFlow::Continue(())
+    })
+}
+
+// Same as `walk_payload()`, but stops early once `emit` breaks
+fn walk_payload_until(
+    tree: &Node,
+    payload: &[u8],
+    pad: u8,
+    order: BitOrder,
+    mut emit: impl FnMut(char) -> ControlFlow<()>,
+) -> Result<()> {
+    let total_bits = (payload.len() * 8)
+        .checked_sub(pad as usize)
+        .ok_or_else(|| anyhow!("Padding exceeds data length."))?;
+
+    // A tree that is a single leaf codes each character as a single bit
+    if let Some(ch) = tree.ch {
+        for _ in 0..total_bits {
+            if emit(ch).is_break() {
+                break;
+            }
+        }
+        return Ok(());
+    }
+
+    let mut bundle = BitBundle::with_order(payload, order);
+    let mut nodeptr = tree;
+    while bundle.position() < total_bits {
+        let bit = bundle.read_bit().unwrap();
+        let next = if bit == 0 { &nodeptr.left } else { &nodeptr.right };
+        nodeptr = next.as_ref().ok_or_else(|| anyhow!("Invalid code in data."))?;
+        let ch = if let Some(ch) = nodeptr.ch {
+            ch
+        } else if nodeptr.is_escape() {
+            read_char(&mut bundle).ok_or_else(|| anyhow!("Truncated escaped character."))?
+        } else {
+            continue;
+        };
+        if emit(ch).is_break() {
+            break;
+        }
+        nodeptr = tree;
+    }
+
+    Ok(())
+}
+
+// Decodes a complete stream, handing each character to `emit`
+fn decode_stream(input: &[u8], emit: impl FnMut(char)) -> Result<()> {
+    if let Some(text) = raw_text(input)? {
+        text.chars().for_each(emit);
+        return Ok(());
+    }
+
+    decode_sections(&split_sections(input)?, emit)
+}
+
+// Decodes the parts of a stream, checking the result against the stored
+// length and checksum when there are any
+fn decode_sections(sections: &Sections, mut emit: impl FnMut(char)) -> Result<()> {
+    decode_sections_until(sections, |ch| {
+        emit(ch);
+        ControlFlow::Continue(())
+    })
+}
+
+// Same as `decode_sections()`, but `emit` can cut decoding short, in which case
+// there is nothing to check against
+fn decode_sections_until(
+    sections: &Sections,
+    mut emit: impl FnMut(char) -> ControlFlow<()>,
+) -> Result<()> {
+    let tree = read_tree(sections)?;
+
+    let runs = sections.flags & FLAG_WHITESPACE_RUNS != 0;
+    let mut count = 0;
+    let mut crc = Crc32::new();
+    let mut stopped = false;
+    walk_payload_until(&tree, sections.data, sections.pack, sections.order, |ch| {
+        count += 1;
+        crc.update(ch.encode_utf8(&mut [0; 4]).as_bytes());
+        let flow = if runs { expand_run(ch, &mut emit) } else { emit(ch) };
+        stopped = flow.is_break();
+        flow
+    })?;
+    if stopped {
+        return Ok(());
+    }
+
+    check_sections(sections, count, &crc)
+}
+
+// Compares what was decoded against the stored length and checksum
+fn check_sections(sections: &Sections, count: usize, crc: &Crc32) -> Result<()> {
+    if sections.length.is_some_and(|length| length != count) {
+        return Err(anyhow!("Length mismatch."));
+    }
+    match sections.checksum {
+        Some(checksum) if checksum != crc.finish() => Err(anyhow!("Checksum mismatch.")),
+        _ => Ok(()),
+    }
+}
+
+// Main encoder function
+fn encode(input: &str, codes: &HashMap<char, String>) -> String {
+    let mut output = String::new();
+
+    for ch in input.chars() {
+        let t = codes.get(&ch).unwrap();
+        output.push_str(t);
+    }
+
+    output
+}
+
+// Encoder for the bitstream, packing the codes straight into bytes
+fn encode_packed(input: &str, codes: &HashMap<char, String>, order: BitOrder) -> PackedBits {
+    let mut writer = BitWriter::with_order(order);
+
+    for ch in input.chars() {
+        writer.write_code(codes.get(&ch).unwrap());
+    }
+
+    writer.finish()
+}
+
+// Same as `encode_packed()`, but characters without a code are written as the
+// escape code followed by their literal UTF-8 bytes
+fn encode_escaped(input: &str, codes: &HashMap<char, String>, escape: &str) -> PackedBits {
+    let mut writer = BitWriter::new();
+
+    for ch in input.chars() {
+        if let Some(code) = codes.get(&ch) {
+            writer.write_code(code);
+        } else {
+            writer.write_code(escape);
+            for &byte in ch.to_string().as_bytes() {
+                writer.write_byte(byte);
+            }
+        }
+    }
+
+    writer.finish()
+}
+
+/// A fun little function for a quick output showing codes and an encoded
+/// string. This function is one way.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::easy_encode;
+/// 
+/// let input = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culpa qui officia deserunt mollit anim id est laborum.";
+/// let (_, output) = easy_encode(input).unwrap();
+/// println!("Encoded string: {output}");
+/// ```
+pub fn easy_encode(input: &str) -> Result<(HashMap<char, String>, String)> {
+    if input.is_empty() {
+        return Err(anyhow!("Input string is empty."));
+    }
+
+    let tree = gen_tree(input);
+    let codes = assign_codes(&tree);
+    let encoded = encode(input, &codes);
+
+    Ok((codes, encoded))
+}
+
+/// Returns just the code of each character of `input`. The tree is taken
+/// apart as the codes are given out, each node freed once its subtree is
+/// done, so it is never held alongside the whole code map.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{codes_only, easy_encode};
+/// 
+/// let book = include_str!("../examples/book.txt");
+/// assert_eq!(codes_only(book).unwrap(), easy_encode(book).unwrap().0);
+/// assert_eq!(codes_only("aaa").unwrap()[&'a'], "0");
+/// assert!(codes_only("").is_err());
+/// ```
+pub fn codes_only(input: &str) -> Result<HashMap<char, String>> {
+    if input.is_empty() {
+        return Err(anyhow!("Input string is empty."));
+    }
+
+    let tree = gen_tree(input);
+    let mut codes = HashMap::new();
+    let code = if tree.ch.is_some() { "0" } else { "" };
+    take_codes(tree, code.to_string(), &mut codes);
+
+    Ok(codes)
+}
+
+// Same as `_assign_codes()`, but consumes the tree, dropping each node as it
+// is left
+fn take_codes(node: Node, code: String, codes: &mut HashMap<char, String>) {
+    if let Some(ch) = node.ch {
+        codes.insert(ch, code);
+        return;
+    }
+    if let Some(left) = node.left {
+        take_codes(*left, code.clone() + "0", codes);
+    }
+    if let Some(right) = node.right {
+        take_codes(*right, code + "1", codes);
+    }
+}
+
+/// Same as `easy_encode()`, but the codes are returned sorted by character so
+/// the output is stable for display and diffing.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::easy_encode_sorted;
+/// 
+/// let input = "abracadabra";
+/// let (codes, output) = easy_encode_sorted(input).unwrap();
+/// let symbols: Vec<char> = codes.iter().map(|(ch, _)| *ch).collect();
+/// assert_eq!(symbols, vec!['a', 'b', 'c', 'd', 'r']);
+/// assert_eq!(easy_encode_sorted(input).unwrap(), (codes, output));
+/// ```
+pub fn easy_encode_sorted(input: &str) -> Result<(Vec<(char, String)>, String)> {
+    let (codes, encoded) = easy_encode(input)?;
+    let mut codes: Vec<(char, String)> = codes.into_iter().collect();
+    codes.sort_unstable();
+
+    Ok((codes, encoded))
+}
+
+/// Returns canonical Huffman codes for a text. Code lengths come from the
+/// Huffman tree, then codes are handed out in order of length and, for equal
+/// lengths, in order of codepoint. This is the ordering DEFLATE relies on, and
+/// it gives the same codes for the same input regardless of how the tree was
+/// built. A text with a single distinct character gets the code `0`.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::canonical_codes;
+/// 
+/// let codes = canonical_codes("bbbbaacd").unwrap();
+/// assert_eq!(codes, vec![
+///     ('b', "0".to_string()),
+///     ('a', "10".to_string()),
+///     ('c', "110".to_string()),
+///     ('d', "111".to_string()),
+/// ]);
+/// ```
+pub fn canonical_codes(input: &str) -> Result<Vec<(char, String)>> {
+    if input.is_empty() {
+        return Err(anyhow!("Input string is empty."));
+    }
+
+    let lengths = assign_codes(&gen_tree(input))
+        .into_iter()
+        .map(|(ch, code)| (ch, code.len()))
+        .collect();
+
+    Ok(assign_canonical(lengths))
+}
+
+/// Scores how alike the character distributions of two texts are, from 0 for
+/// texts with no characters in common to 1 for texts with the same
+/// proportions of each character. This is the cosine similarity of their
+/// character frequencies, and gives a feel for whether a shared `Codebook`
+/// would suit both. An empty text scores 0 against anything.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::codebook_similarity;
+/// 
+/// let text = "the quick brown fox jumps over the lazy dog";
+/// assert!((codebook_similarity(text, text) - 1.0).abs() < 1e-9);
+/// assert!(codebook_similarity("abcabc", "xyzxyz") < 1e-9);
+/// ```
+pub fn codebook_similarity(a: &str, b: &str) -> f64 {
+    let freq_a = count_chars(a);
+    let freq_b = count_chars(b);
+
+    let dot: f64 = freq_a
+        .iter()
+        .filter_map(|(ch, &fa)| freq_b.get(ch).map(|&fb| fa as f64 * fb as f64))
+        .sum();
+    let norm = |freqs: &BTreeMap<char, usize>| {
+        freqs.values().map(|&f| (f as f64).powi(2)).sum::<f64>().sqrt()
+    };
+    let norms = norm(&freq_a) * norm(&freq_b);
+
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+/// Returns the character with the shortest code and the character with the
+/// longest code, each with its code, as `(shortest, code, longest, code)`.
+/// Ties go to the lowest codepoint.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::code_extremes;
+/// 
+/// let (short, short_code, long, long_code) = code_extremes("eeeeeeeeeetaoinz").unwrap();
+/// assert_eq!(short, 'e');
+/// assert!(short_code.len() < long_code.len());
+/// ```
+pub fn code_extremes(input: &str) -> Result<(char, String, char, String)> {
+    if input.is_empty() {
+        return Err(anyhow!("Input string is empty."));
+    }
+
+    let mut codes: Vec<(char, String)> = assign_codes(&gen_tree(input)).into_iter().collect();
+    codes.sort_unstable();
+    let shortest = codes.iter().min_by_key(|(_, code)| code.len()).unwrap().clone();
+    // `max_by_key` keeps the last of equal elements, so reverse to favour low codepoints
+    let longest = codes.iter().rev().max_by_key(|(_, code)| code.len()).unwrap().clone();
+
+    Ok((shortest.0, shortest.1, longest.0, longest.1))
+}
+
+/// Draws the Huffman tree for a text as a Graphviz DOT digraph, which can be
+/// rendered with `dot -Tsvg`. Internal nodes are unlabelled circles, leaves are
+/// boxes labelled with their character, and edges are labelled with their bit.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::tree_to_dot;
+/// 
+/// let dot = tree_to_dot("abracadabra").unwrap();
+/// assert!(dot.starts_with("digraph"));
+/// assert_eq!(dot.matches('{').count(), dot.matches('}').count());
+/// // Five leaves and four internal nodes
+/// let nodes = dot.lines().filter(|line| line.contains("shape=")).count();
+/// assert_eq!(nodes, 9);
+/// ```
+pub fn tree_to_dot(input: &str) -> Result<String> {
+    if input.is_empty() {
+        return Err(anyhow!("Input string is empty."));
+    }
+
+    let mut output = String::from("digraph huffman {\n");
+    dot_node(&gen_tree(input), &mut 0, &mut output);
+    output.push_str("}\n");
+
+    Ok(output)
+}
+
+// Write a node and those below it, returning the node's id
+fn dot_node(node: &Node, next_id: &mut usize, output: &mut String) -> usize {
+    let id = *next_id;
+    *next_id += 1;
+
+    if node.is_escape() {
+        output.push_str(&format!("    n{id} [label=\"ESC\", shape=box];\n"));
+    } else if let Some(ch) = node.ch {
+        output.push_str(&format!("    n{id} [label=\"{}\", shape=box];\n", dot_label(ch)));
+    } else {
+        output.push_str(&format!("    n{id} [label=\"\", shape=circle];\n"));
+        for (bit, child) in [(0, &node.left), (1, &node.right)] {
+            if let Some(child) = child {
+                let child_id = dot_node(child, next_id, output);
+                output.push_str(&format!("    n{id} -> n{child_id} [label=\"{bit}\"];\n"));
+            }
+        }
+    }
+
+    id
+}
+
+/// A node of `tree_adjacency()`: its index, the indices of the children
+/// reached by a `0` and a `1` bit, and the character of a leaf.
+pub type AdjacencyNode = (usize, Option<usize>, Option<usize>, Option<char>);
+
+/// Lists the nodes of the tree for `input` in preorder, so that the root is
+/// node 0, each with the indices of its children and, for a leaf, its
+/// character. A flat list like this is easy to read from other languages.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{codes_only, tree_adjacency, AdjacencyNode};
+/// use std::collections::HashMap;
+/// 
+/// let input = "abracadabra";
+/// let nodes = tree_adjacency(input).unwrap();
+/// assert_eq!(nodes.len(), 9);
+/// assert!(nodes.iter().enumerate().all(|(idx, node)| node.0 == idx));
+/// 
+/// // Walking the list from the root gives back the codes of the tree
+/// fn walk(nodes: &[AdjacencyNode], idx: usize, code: String, codes: &mut HashMap<char, String>) {
+///     let (_, left, right, ch) = nodes[idx];
+///     if let Some(ch) = ch {
+///         assert_eq!((left, right), (None, None));
+///         codes.insert(ch, code);
+///         return;
+///     }
+///     walk(nodes, left.unwrap(), code.clone() + "0", codes);
+///     walk(nodes, right.unwrap(), code + "1", codes);
+/// }
+/// let mut codes = HashMap::new();
+/// walk(&nodes, 0, String::new(), &mut codes);
+/// assert_eq!(codes, codes_only(input).unwrap());
+/// ```
+pub fn tree_adjacency(input: &str) -> Result<Vec<AdjacencyNode>> {
+    if input.is_empty() {
+        return Err(anyhow!("Input string is empty."));
+    }
+
+    let mut nodes = Vec::new();
+    adjacency_node(&gen_tree(input), &mut nodes);
+
+    Ok(nodes)
+}
+
+// Add a node and those below it, returning the node's index
+fn adjacency_node(node: &Node, nodes: &mut Vec<AdjacencyNode>) -> usize {
+    let idx = nodes.len();
+    nodes.push((idx, None, None, node.ch));
+
+    let left = node.left.as_ref().map(|child| adjacency_node(child, nodes));
+    let right = node.right.as_ref().map(|child| adjacency_node(child, nodes));
+    nodes[idx].1 = left;
+    nodes[idx].2 = right;
+
+    idx
+}
+
+// Characters that would break or vanish from a DOT label are spelled out
+fn dot_label(ch: char) -> String {
+    match ch {
+        ' ' => String::from("␣"),
+        '"' => String::from("\\\""),
+        '\\' => String::from("\\\\"),
+        ch if ch.is_control() => format!("U+{:04X}", ch as u32),
+        ch => ch.to_string(),
+    }
+}
+
+// Block sizes in characters tried by `encode_auto_blocks()`
+const BLOCK_SIZES: [usize; 3] = [4096, 16384, 65536];
+
+/// Encodes a text whole and split into blocks of a few sizes, each block with
+/// its own tree, and keeps whichever output is smallest. Blocks pay off when
+/// the makeup of a text changes along the way, as each tree only covers the
+/// characters its block uses. `decode_blocks()` reads either form.
+/// 
+/// When the whole text wins, the output is the same as `encode_to_bitstream()`.
+/// Otherwise it starts with flags marking it as split, followed by the block
+/// size in characters as a variable width number, and the block streams
+/// joined with `frame()`.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{encode_auto_blocks, decode_blocks, encode_to_bitstream};
+/// 
+/// let book = include_str!("../examples/book.txt");
+/// let data = encode_auto_blocks(book).unwrap();
+/// assert!(data.len() <= encode_to_bitstream(book).unwrap().len());
+/// assert_eq!(decode_blocks(&data).unwrap(), book);
+/// 
+/// // English followed by digits suits separate trees
+/// let mixed = book.to_string() + &"3141592653589793238462643383279".repeat(300);
+/// let data = encode_auto_blocks(&mixed).unwrap();
+/// assert!(data.len() < encode_to_bitstream(&mixed).unwrap().len());
+/// assert_eq!(decode_blocks(&data).unwrap(), mixed);
+/// ```
+pub fn encode_auto_blocks(input: &str) -> Result<Vec<u8>> {
+    let mut best = encode_to_bitstream(input)?;
+    let chars: Vec<char> = input.chars().collect();
+
+    for size in BLOCK_SIZES.into_iter().filter(|&size| size < chars.len()) {
+        let mut blocks = Vec::new();
+        for block in chars.chunks(size) {
+            blocks.push(encode_to_bitstream(&block.iter().collect::<String>())?);
+        }
+
+        let mut glob = uint_to_vwe(FLAG_BLOCKS)?;
+        glob.extend(uint_to_vwe(size)?);
+        glob.extend(frame(&blocks)?);
+        if glob.len() < best.len() {
+            best = glob;
+        }
+    }
+
+    Ok(best)
+}
+
+/// Decodes a stream produced by `encode_auto_blocks()`, or any stream that
+/// `decode_from_bitstream()` reads.
+pub fn decode_blocks(input: &[u8]) -> Result<String> {
+    let (flags, flags_len) = vwe_to_uint(input)?;
+    if flags & FLAG_BLOCKS == 0 {
+        return decode_from_bitstream(input);
+    }
+
+    // The block size is only needed by the encoder, as each block carries its length
+    let (_, size_len) = vwe_to_uint(&input[flags_len..])?;
+    let mut output = String::new();
+    for block in deframe(&input[(flags_len + size_len)..])? {
+        output.push_str(&decode_from_bitstream(&block)?);
+    }
+
+    Ok(output)
+}
+
+/// Same as `decode_blocks()`, but the blocks are shared out between threads,
+/// one per available core, and decoded at the same time. Each block has its
+/// own tree, so none depends on another, and the text is joined back in
+/// order. Available with the `parallel` feature, which uses the standard
+/// library's scoped threads rather than adding a dependency.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{decode_blocks, decode_blocks_parallel, encode_auto_blocks};
+/// 
+/// let book = include_str!("../examples/book.txt");
+/// let data = encode_auto_blocks(book).unwrap();
+/// assert_eq!(decode_blocks_parallel(&data).unwrap(), decode_blocks(&data).unwrap());
+/// assert_eq!(decode_blocks_parallel(&data).unwrap(), book);
+/// ```
+#[cfg(feature = "parallel")]
+pub fn decode_blocks_parallel(input: &[u8]) -> Result<String> {
+    let (flags, flags_len) = vwe_to_uint(input)?;
+    if flags & FLAG_BLOCKS == 0 {
+        return decode_from_bitstream(input);
+    }
+
+    let (_, size_len) = vwe_to_uint(&input[flags_len..])?;
+    let blocks = deframe(&input[(flags_len + size_len)..])?;
+    let threads = std::thread::available_parallelism().map_or(1, |count| count.get());
+    let per_thread = blocks.len().div_ceil(threads).max(1);
+
+    let decoded: Vec<Result<Vec<String>>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = blocks
+            .chunks(per_thread)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(|block| decode_from_bitstream(block)).collect()))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap_or_else(|_| Err(anyhow!("Decoding thread panicked."))))
+            .collect()
+    });
+
+    let mut output = String::new();
+    for texts in decoded {
+        texts?.iter().for_each(|text| output.push_str(text));
+    }
+
+    Ok(output)
+}
+
+/// Encodes a text in blocks of `block_size` characters, where only the first
+/// block's tree is stored. Every later block is coded with a tree the decoder
+/// builds itself from the characters of the block before, so the model keeps
+/// up with a text whose makeup drifts without a tree per block. Characters
+/// from earlier blocks keep a code, and each tree has an escape code, after
+/// which a character new to the text follows as its literal UTF-8 bytes. A
+/// sudden change, such as to another script, costs more than a fresh tree
+/// would. `decode_blocks_adaptive()` reverses this.
+/// 
+/// The output holds the block size and the number of characters as variable
+/// width numbers, a CRC-32 of the text, the first tree prefixed with its
+/// length as a variable width number, then the number of data packing bits
+/// and the data of all blocks back to back.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{decode_blocks_adaptive, encode_blocks_adaptive, encode_to_bitstream, frame};
+/// 
+/// // The English part of the book, before it moves on to other scripts
+/// let book: String = include_str!("../examples/book.txt").chars().take(23_000).collect();
+/// let data = encode_blocks_adaptive(&book, 4096).unwrap();
+/// assert_eq!(decode_blocks_adaptive(&data).unwrap(), book);
+/// 
+/// // The same blocks, each with its own tree
+/// let chars: Vec<char> = book.chars().collect();
+/// let blocks: Vec<Vec<u8>> = chars
+///     .chunks(4096)
+///     .map(|block| encode_to_bitstream(&block.iter().collect::<String>()).unwrap())
+///     .collect();
+/// assert!(data.len() < frame(&blocks).unwrap().len());
+/// ```
+pub fn encode_blocks_adaptive(input: &str, block_size: usize) -> Result<Vec<u8>> {
+    if input.is_empty() {
+        return Err(anyhow!("Input string is empty."));
+    }
+    if block_size == 0 {
+        return Err(anyhow!("Block size must be at least one."));
+    }
+
+    let chars: Vec<char> = input.chars().collect();
+    let mut blocks = chars.chunks(block_size).map(|block| block.iter().collect::<String>());
+    let first = blocks.next().unwrap();
+
+    let mut codebook = Codebook::from_counts(count_chars(&first));
+    let mut seen = BTreeMap::new();
+    let stree = ser_tree(codebook.tree.clone(), LeafFormat::Utf8);
+
+    let mut writer = BitWriter::new();
+    let mut block = first;
+    loop {
+        for ch in block.chars() {
+            match codebook.codes.get(&ch) {
+                Some(code) => writer.write_code(code),
+                None => {
+                    writer.write_code(&codebook.escape);
+                    ch.to_string().bytes().for_each(|byte| writer.write_byte(byte));
+                }
+            }
+        }
+        match blocks.next() {
+            Some(next) => {
+                codebook = Codebook::from_counts(adaptive_counts(&block, &mut seen));
+                block = next;
+            }
+            None => break,
+        }
+    }
+    let encoded = writer.finish();
+
+    let mut glob = uint_to_vwe(block_size)?;
+    glob.extend(uint_to_vwe(chars.len())?);
+    glob.extend_from_slice(&crc32(input.as_bytes()).to_be_bytes());
+    glob.extend(uint_to_vwe(stree.len())?);
+    glob.extend_from_slice(&stree);
+    glob.push(encoded.pad);
+    glob.extend_from_slice(&encoded.bytes);
+
+    Ok(glob)
+}
+
+// Frequencies for the tree of the block after `block`. Characters seen in
+// earlier blocks but not this one keep a count of one, so that they keep a
+// code rather than falling back on the escape.
+fn adaptive_counts(block: &str, seen: &mut BTreeMap<char, usize>) -> BTreeMap<char, usize> {
+    let mut counts = count_chars(block);
+    for ch in counts.keys() {
+        seen.insert(*ch, 1);
+    }
+    for (&ch, &one) in seen.iter() {
+        counts.entry(ch).or_insert(one);
+    }
+
+    counts
+}
+
+/// Decodes a text produced by `encode_blocks_adaptive()`.
+pub fn decode_blocks_adaptive(input: &[u8]) -> Result<String> {
+    let (block_size, mut idx) = vwe_to_uint(input)?;
+    if block_size == 0 {
+        return Err(anyhow!("Block size must be at least one."));
+    }
+    let (count, width) = vwe_to_uint(&input[idx..])?;
+    idx += width;
+    let checksum = input
+        .get(idx..(idx + 4))
+        .ok_or_else(|| anyhow!("Truncated checksum."))?;
+    let checksum = u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]);
+    idx += 4;
+    let (tree_len, width) = vwe_to_uint(&input[idx..])?;
+    idx += width;
+    let stree = input
+        .get(idx..(idx + tree_len))
+        .ok_or_else(|| anyhow!("Tree size mismatch."))?;
+    let data = input
+        .get((idx + tree_len + 1)..)
+        .ok_or_else(|| anyhow!("Missing data."))?;
+
+    let mut tree = des_tree(stree, LeafFormat::Utf8)?;
+    let mut seen = BTreeMap::new();
+    let mut bundle = BitBundle::new(data);
+    let mut output = String::with_capacity(count.min(data.len() * 8));
+    let mut decoded = 0;
+    while decoded < count {
+        let mut block = String::new();
+        for _ in 0..block_size.min(count - decoded) {
+            block.push(read_symbol(&tree, &mut bundle)?);
+        }
+        decoded += block.chars().count();
+        tree = Codebook::from_counts(adaptive_counts(&block, &mut seen)).tree;
+        output.push_str(&block);
+    }
+
+    if crc32(output.as_bytes()) != checksum {
+        return Err(anyhow!("Checksum mismatch."));
+    }
+
+    Ok(output)
+}
+
+/// Encodes a text and packages it with the tree in a compact binary format for portability.
+/// Useful for transmission or archival purposes, and can be decompressed later.
+/// 
+/// A leading byte order mark (U+FEFF) is treated like any other character and
+/// survives the round trip. Use `encode_to_bitstream_strip_bom()` to drop it.
+/// 
+/// Texts under 8 bytes are stored as plain UTF-8 after the flags and the
+/// number of characters instead, as the tree alone would take more room than
+/// the text. `decode_from_bitstream()` reads either kind of stream.
+/// 
+/// ```
+/// use codecs::huffman::{encode_to_bitstream, decode_from_bitstream};
+/// 
+/// let data = encode_to_bitstream("abc").unwrap();
+/// assert!(data.len() <= "abc".len() + 3);
+/// assert_eq!(decode_from_bitstream(&data).unwrap(), "abc");
+/// ```
+/// 
+/// A text of one character repeated is stored as that character and the
+/// number of repeats after the flags, with no tree or data at all.
+/// 
+/// ```
+/// use codecs::huffman::{encode_to_bitstream, decode_from_bitstream};
+/// 
+/// let data = encode_to_bitstream("zzzz").unwrap();
+/// assert_eq!(data, [0xA0, 0x00, b'z', 4]);
+/// assert_eq!(decode_from_bitstream(&data).unwrap(), "zzzz");
+/// 
+/// let long = "€".repeat(100_000);
+/// assert_eq!(encode_to_bitstream(&long).unwrap().len(), 8);
+/// assert_eq!(decode_from_bitstream(&encode_to_bitstream(&long).unwrap()).unwrap(), long);
+/// ```
+/// 
+/// The tree length is stored in one, two or four bytes, or as a variable width
+/// number, whichever is smallest for the size of the tree. The choice is
+/// recorded in the flags at the start of the stream. With the `vwe_header`
+/// feature the variable width number is always used.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```no_run
+/// use codecs::huffman::encode_to_bitstream;
+/// use std::fs;
+/// 
+/// let input = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culpa qui officia deserunt mollit anim id est laborum.";
+/// let data = match encode_to_bitstream(&input) {
+///     Ok(data) => data,
+///     Err(err) => panic!("Something went wrong: {}", err),
+/// };
+/// 
+/// if let Err(err) = fs::write("output.hmc", data) {
+///     eprintln!("Error writing to file: {}", err);
+/// } else {
+///     println!("Data successfully written.");
+/// }
+/// ```
+/// 
+/// Larger alphabets need larger trees, and so wider tree lengths. Leaving out
+/// the text length and checksum, the header is just the flags, the tree length
+/// and the data packing byte.
+/// 
+/// ```
+/// use codecs::huffman::{Encoder, decode_from_bitstream, format_breakdown};
+/// 
+/// // Distinct characters from a starting codepoint, all of the same UTF-8 width
+/// let alphabet = |start: u32, count: u32| -> String {
+///     (start..start + count).filter_map(char::from_u32).collect()
+/// };
+/// 
+/// for (input, header_bytes) in [
+///     (alphabet(0x61, 26), 3),
+///     (alphabet(0x100, 200), 4),
+///     (alphabet(0x4E00, 25_000), 5),
+///     (alphabet(0x10000, 500_000), 6),
+/// ] {
+///     let data = Encoder::new().integrity(false).encode(&input).unwrap();
+///     # #[cfg(not(feature = "vwe_header"))]
+///     assert_eq!(format_breakdown(&data).unwrap().header_bytes, header_bytes);
+///     assert_eq!(decode_from_bitstream(&data).unwrap(), input);
+/// }
+/// ```
+pub fn encode_to_bitstream(input: &str) -> Result<Vec<u8>> {
+    Encoder::new().raw_below(RAW_BELOW).encode(input)
+}
+
+/// Buffers kept between encodes, for encoding many small messages one after
+/// another. `encode_into_scratch()` gives the same stream as
+/// `encode_to_bitstream()`, as both make the same merges, but keeps the tree
+/// in a table of nodes rather than boxing each one, and clears and refills
+/// the same tables and buffers each time instead of allocating new ones.
+/// Once they have grown to fit the messages, only the few bytes of the
+/// header are allocated.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{encode_to_bitstream, EncodeScratch};
+/// 
+/// let mut scratch = EncodeScratch::new();
+/// let book = include_str!("../examples/book.txt");
+/// // Equal counts everywhere, so that every merge is a tie
+/// let ties = "abcdefghijklmnopqrstuvwxyz0123456789".repeat(4);
+/// let pairs = "aabbccddeeffgghhiijjkkllmmnnoopp";
+/// for input in ["GET /index.html 200", "POST /api/login 401", "abc", "zzzzzzzzzz", "Größe 42 €", book, &ties, pairs, "GET /"] {
+///     let data = scratch.encode_into_scratch(input).unwrap();
+///     assert_eq!(data, encode_to_bitstream(input).unwrap());
+/// }
+/// assert!(scratch.encode_into_scratch("").is_err());
+/// ```
+#[derive(Debug, Default)]
+pub struct EncodeScratch {
+    counts: HashMap<char, usize>,
+    leaves: Vec<(char, usize)>,
+    // Leaves first, in character order, then the internal nodes as they are made
+    nodes: Vec<ScratchNode>,
+    heap: Vec<HeapBranch>,
+    merges: Vec<(usize, usize)>,
+    // Each character's code as a range of `bits`
+    codes: HashMap<char, (usize, usize)>,
+    bits: Vec<u8>,
+    path: Vec<u8>,
+    // Nodes still to give codes to, with their depth and the bit leading to them
+    code_stack: Vec<(usize, usize, u8)>,
+    // Nodes still to serialise
+    tree_stack: Vec<usize>,
+    tree: Vec<u8>,
+    data: Vec<u8>,
+    output: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ScratchNode {
+    Leaf(char),
+    Internal(usize, usize),
+}
+
+impl EncodeScratch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Encodes a text, returning the stream, which holds until the next call.
+    pub fn encode_into_scratch(&mut self, input: &str) -> Result<&[u8]> {
+        if input.is_empty() {
+            return Err(anyhow!("Input string is empty."));
+        }
+        let encoder = Encoder::new().raw_below(RAW_BELOW);
+        self.output.clear();
+        if encoder.write_untreed(input, 0, &mut self.output)? {
+            return Ok(&self.output);
+        }
+
+        self.counts.clear();
+        for ch in input.chars() {
+            *self.counts.entry(ch).or_insert(0) += 1;
+        }
+        let root = self.build_tree();
+        self.assign_codes(root);
+
+        self.tree.clear();
+        let mut writer = BitWriter::resume(PackedBits { bytes: std::mem::take(&mut self.tree), pad: 0 }, BitOrder::MsbFirst);
+        self.tree_stack.clear();
+        self.tree_stack.push(root);
+        while let Some(node) = self.tree_stack.pop() {
+            match self.nodes[node] {
+                ScratchNode::Leaf(ch) => {
+                    writer.write_bit(1);
+                    ch.encode_utf8(&mut [0; 4]).bytes().for_each(|byte| writer.write_byte(byte));
+                }
+                ScratchNode::Internal(left, right) => {
+                    writer.write_bit(0);
+                    self.tree_stack.push(right);
+                    self.tree_stack.push(left);
+                }
+            }
+        }
+        let tree = writer.finish();
+
+        self.data.clear();
+        let mut writer = BitWriter::resume(PackedBits { bytes: std::mem::take(&mut self.data), pad: 0 }, BitOrder::MsbFirst);
+        for ch in input.chars() {
+            let (start, len) = self.codes[&ch];
+            self.bits[start..(start + len)].iter().for_each(|&bit| writer.write_bit(bit));
+        }
+        let encoded = writer.finish();
+
+        encoder.package_into(input, &tree.bytes, &encoded, 0, &mut self.output)?;
+        self.tree = tree.bytes;
+        self.data = encoded.bytes;
+
+        Ok(&self.output)
+    }
+
+    // Builds the same tree as `tree_from_counts()` in `nodes`, with the same
+    // merges, returning the root
+    fn build_tree(&mut self) -> usize {
+        // In character order, as the leaves of `tree_from_counts()` are
+        self.leaves.clear();
+        self.leaves.extend(self.counts.iter().map(|(&ch, &freq)| (ch, freq)));
+        self.leaves.sort_unstable();
+
+        let root = huffman_merges(self.leaves.iter().map(|&(_, freq)| freq), &mut self.heap, &mut self.merges);
+        self.nodes.clear();
+        self.nodes.extend(self.leaves.iter().map(|&(ch, _)| ScratchNode::Leaf(ch)));
+        self.nodes.extend(self.merges.iter().map(|&(left, right)| ScratchNode::Internal(left, right)));
+
+        root
+    }
+
+    // Gives each leaf the path to it as its code, or a single zero bit for a
+    // tree that is only a leaf
+    fn assign_codes(&mut self, root: usize) {
+        self.codes.clear();
+        self.bits.clear();
+        self.code_stack.clear();
+        self.code_stack.push((root, 0, 0));
+        self.path.clear();
+        while let Some((node, depth, bit)) = self.code_stack.pop() {
+            if depth > 0 {
+                self.path.truncate(depth - 1);
+                self.path.push(bit);
+            }
+            match self.nodes[node] {
+                ScratchNode::Leaf(ch) => {
+                    let code: &[u8] = if self.path.is_empty() { &[0] } else { &self.path };
+                    self.codes.insert(ch, (self.bits.len(), code.len()));
+                    self.bits.extend_from_slice(code);
+                }
+                ScratchNode::Internal(left, right) => {
+                    self.code_stack.push((right, depth + 1, 1));
+                    self.code_stack.push((left, depth + 1, 0));
+                }
+            }
+        }
+    }
+}
+
+/// Same as `encode_to_bitstream()`, but calls `on_progress` with the fraction
+/// of the work done, from 0.0 to 1.0, as it counts the characters and then
+/// encodes them. Each pass reports at most once per hundredth of the text,
+/// every value is larger than the one before, and the last is always 1.0.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{encode_to_bitstream, encode_to_bitstream_progress};
+/// 
+/// let book = include_str!("../examples/book.txt");
+/// let mut seen = Vec::new();
+/// let data = encode_to_bitstream_progress(book, |fraction| seen.push(fraction)).unwrap();
+/// assert_eq!(data, encode_to_bitstream(book).unwrap());
+/// assert!(seen.windows(2).all(|pair| pair[0] < pair[1]));
+/// assert_eq!(seen.last(), Some(&1.0));
+/// assert!(seen.len() <= 201);
+/// ```
+pub fn encode_to_bitstream_progress(input: &str, mut on_progress: impl FnMut(f64)) -> Result<Vec<u8>> {
+    if input.len() < RAW_BELOW || repeated_char(input).is_some() {
+        let data = encode_to_bitstream(input)?;
+        on_progress(1.0);
+        return Ok(data);
+    }
+
+    // Each pass takes half the bar, and reports once per step of bytes
+    let step = input.len().div_ceil(100);
+    let mut last = 0.0;
+    let mut report = |pass: usize, offset: usize| {
+        let fraction = (pass as f64 + offset as f64 / input.len() as f64) / 2.0;
+        if fraction > last {
+            last = fraction;
+            on_progress(fraction);
+        }
+    };
+
+    let mut counts = BTreeMap::new();
+    let mut next = step;
+    for (offset, ch) in input.char_indices() {
+        if offset >= next {
+            report(0, offset);
+            next = offset + step;
+        }
+        *counts.entry(ch).or_insert(0) += 1;
+    }
+
+    let tree = tree_from_counts(counts);
+    let codes = assign_codes(&tree);
+    let mut writer = BitWriter::new();
+    let mut next = 0;
+    for (offset, ch) in input.char_indices() {
+        if offset >= next {
+            report(1, offset);
+            next = offset + step;
+        }
+        writer.write_code(&codes[&ch]);
+    }
+
+    let stream = Encoder::new().package(input, ser_tree(tree, LeafFormat::Utf8), writer.finish(), 0)?;
+    report(2, 0);
+
+    Ok(stream)
+}
+
+/// Encodes as much of a text as fits in `max_bits` bits of encoded data, for
+/// links that can only carry so much. Returns the stream and the number of
+/// characters it holds, which are a prefix of the text. The tree is built
+/// from the whole text, and the stored length and checksum cover the prefix,
+/// so `decode_from_bitstream()` gives back exactly the prefix.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{decode_from_bitstream, encode_truncated, format_breakdown};
+/// 
+/// let book = include_str!("../examples/book.txt");
+/// let (data, count) = encode_truncated(book, 10_000).unwrap();
+/// let prefix: String = book.chars().take(count).collect();
+/// assert_eq!(decode_from_bitstream(&data).unwrap(), prefix);
+/// assert!(count > 0 && count < book.chars().count());
+/// 
+/// let breakdown = format_breakdown(&data).unwrap();
+/// assert!(breakdown.payload_bytes * 8 - breakdown.pad_bits as usize <= 10_000);
+/// 
+/// let (data, count) = encode_truncated("abc", 1_000).unwrap();
+/// assert_eq!((decode_from_bitstream(&data).unwrap().as_str(), count), ("abc", 3));
+/// ```
+pub fn encode_truncated(input: &str, max_bits: usize) -> Result<(Vec<u8>, usize)> {
+    if input.is_empty() {
+        return Err(anyhow!("Input string is empty."));
+    }
+
+    let tree = gen_tree(input);
+    let codes = assign_codes(&tree);
+    let mut writer = BitWriter::new();
+    let mut bits = 0;
+    let mut count = 0;
+    let mut end = input.len();
+    for (offset, ch) in input.char_indices() {
+        let code = &codes[&ch];
+        if bits + code.len() > max_bits {
+            end = offset;
+            break;
+        }
+        writer.write_code(code);
+        bits += code.len();
+        count += 1;
+    }
+
+    let stream = Encoder::new().package(&input[..end], ser_tree(tree, LeafFormat::Utf8), writer.finish(), 0)?;
+
+    Ok((stream, count))
+}
+
+/// Bits from `start` up to `end` in the encoded data.
+pub type BitRange = (usize, usize);
+
+/// Same as `encode_to_bitstream()`, but also returns where each character
+/// of the text ended up, as the range of bits from `start` up to `end` that
+/// its code takes in the encoded data. Useful for highlighting, or for
+/// working out what to re-encode after a partial change.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{encode_with_symbol_offsets, format_breakdown};
+/// 
+/// let (data, offsets) = encode_with_symbol_offsets("abracadabra").unwrap();
+/// assert_eq!(offsets.len(), 11);
+/// assert_eq!(offsets[0].0, 0);
+/// assert!(offsets.windows(2).all(|pair| pair[0].1 == pair[1].0));
+/// 
+/// let breakdown = format_breakdown(&data).unwrap();
+/// assert_eq!(offsets[10].1, breakdown.payload_bytes * 8 - breakdown.pad_bits as usize);
+/// 
+/// // One character repeated still gets a tree, and a bit for each character
+/// let (data, offsets) = encode_with_symbol_offsets("zzzz").unwrap();
+/// assert_eq!(offsets, [(0, 1), (1, 2), (2, 3), (3, 4)]);
+/// assert_eq!(format_breakdown(&data).unwrap().payload_bytes, 1);
+/// ```
+pub fn encode_with_symbol_offsets(input: &str) -> Result<(Vec<u8>, Vec<BitRange>)> {
+    if input.is_empty() {
+        return Err(anyhow!("Input string is empty."));
+    }
+
+    // Always with a tree, even for a single repeated character, so that there
+    // are codes to give the places of
+    let tree = gen_tree(input);
+    let codes = assign_codes(&tree);
+    let encoded = encode_packed(input, &codes, BitOrder::MsbFirst);
+    let glob = Encoder::new().package(input, ser_tree(tree, LeafFormat::Utf8), encoded, 0)?;
+
+    let mut start = 0;
+    let offsets = input
+        .chars()
+        .map(|ch| {
+            let end = start + codes[&ch].len();
+            let range = (start, end);
+            start = end;
+            range
+        })
+        .collect();
+
+    Ok((glob, offsets))
+}
+
+/// Same as `encode_to_bitstream()`, but fails if the stream is larger than
+/// `max_ratio` times the size of the text in bytes. Pipelines can use this to
+/// spot text that does not compress and store it raw instead.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::encode_to_bitstream_checked;
+/// 
+/// // Short pseudorandom text leaves nothing for the codes to exploit
+/// let mut seed: u32 = 7;
+/// let noise: String = (0..64)
+///     .map(|_| {
+///         seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+///         char::from(b'!' + (seed >> 16) as u8 % 94)
+///     })
+///     .collect();
+/// assert!(encode_to_bitstream_checked(&noise, 0.9).is_err());
+/// assert!(encode_to_bitstream_checked(&"abab".repeat(100), 0.9).is_ok());
+/// ```
+pub fn encode_to_bitstream_checked(input: &str, max_ratio: f64) -> Result<Vec<u8>> {
+    let glob = encode_to_bitstream(input)?;
+    let ratio = glob.len() as f64 / input.len() as f64;
+
+    if ratio > max_ratio {
+        return Err(anyhow!("Compression ratio {:.3} exceeds {:.3}.", ratio, max_ratio));
+    }
+
+    Ok(glob)
+}
+
+/// Same as `encode_to_bitstream()`, but the stream is decoded again and
+/// compared with the text before it is returned, so a fault in the codec shows
+/// up now rather than when the data is next read. This costs a second pass.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{decode_from_bitstream, encode_to_bitstream_verified, verify_bitstream};
+/// 
+/// let data = encode_to_bitstream_verified("Lorem ipsum dolor sit amet").unwrap();
+/// assert_eq!(decode_from_bitstream(&data).unwrap(), "Lorem ipsum dolor sit amet");
+/// 
+/// // The check it runs turns a stream that does not give the text back into an error
+/// let error = verify_bitstream("Lorem ipsum dolor sit amen", &data).unwrap_err();
+/// assert_eq!(error.to_string(), "Round trip mismatch.");
+/// ```
+pub fn encode_to_bitstream_verified(input: &str) -> Result<Vec<u8>> {
+    let glob = encode_to_bitstream(input)?;
+    verify_bitstream(input, &glob)?;
+
+    Ok(glob)
+}
+
+/// Checks that a stream decodes to exactly `expected`. The text is compared
+/// directly, so this also works for streams without a checksum.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{verify_bitstream, Encoder};
+/// 
+/// let data = Encoder::new().integrity(false).encode("Hello, World?").unwrap();
+/// assert!(verify_bitstream("Hello, World?", &data).is_ok());
+/// assert!(verify_bitstream("Hello, World!", &data).is_err());
+/// ```
+pub fn verify_bitstream(expected: &str, input: &[u8]) -> Result<()> {
+    if decode_from_bitstream(input)? != expected {
+        return Err(anyhow!("Round trip mismatch."));
+    }
+
+    Ok(())
+}
+
+/// Checks whether two streams decode to the same text, however each was
+/// encoded, without handing the text back. Either can be any stream that
+/// `decode_blocks()` reads. Fails if either stream does not decode.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::bits::BitOrder;
+/// use codecs::huffman::{encode_auto_blocks, encode_to_bitstream, streams_equivalent, Encoder, Level};
+/// 
+/// let input = "Lorem ipsum dolor sit amet, consectetur adipiscing elit";
+/// let original = encode_to_bitstream(input).unwrap();
+/// let reencoded = Encoder::new()
+///     .integrity(false)
+///     .bit_order(BitOrder::LsbFirst)
+///     .level(Level::Default)
+///     .encode(input)
+///     .unwrap();
+/// assert_ne!(original, reencoded);
+/// assert!(streams_equivalent(&original, &reencoded).unwrap());
+/// assert!(streams_equivalent(&original, &encode_auto_blocks(input).unwrap()).unwrap());
+/// 
+/// let other = encode_to_bitstream("Lorem ipsum dolor sit amet").unwrap();
+/// assert!(!streams_equivalent(&original, &other).unwrap());
+/// ```
+pub fn streams_equivalent(a: &[u8], b: &[u8]) -> Result<bool> {
+    Ok(decode_blocks(a)? == decode_blocks(b)?)
+}
+
+/// Same as `encode_to_bitstream()`, but characters that occur fewer than
+/// `min_freq` times get no leaf of their own. They share an escape leaf
+/// instead, and are written as its code followed by their literal UTF-8 bytes.
+/// Noisy text with many one-off characters then carries a much smaller tree.
+/// The most frequent character always keeps its leaf. `decode_from_bitstream()`
+/// reads the result like any other stream.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{encode_with_rare_escape, decode_from_bitstream, format_breakdown};
+/// 
+/// let input = "the cat sat on the mat with a hat, ¿qué? № 1 ✓ ♫ ∑ ☃ — ok";
+/// let full = encode_with_rare_escape(input, 1).unwrap();
+/// let trimmed = encode_with_rare_escape(input, 2).unwrap();
+/// assert!(format_breakdown(&trimmed).unwrap().tree_bytes < format_breakdown(&full).unwrap().tree_bytes);
+/// assert_eq!(decode_from_bitstream(&full).unwrap(), input);
+/// assert_eq!(decode_from_bitstream(&trimmed).unwrap(), input);
+/// ```
+pub fn encode_with_rare_escape(input: &str, min_freq: usize) -> Result<Vec<u8>> {
+    if input.is_empty() {
+        return Err(anyhow!("Input string is empty."));
+    }
+
+    let counts = count_chars(input);
+    let top = counts.iter().max_by_key(|(_, &freq)| freq).map(|(&ch, _)| ch).unwrap();
+    let (kept, rare): (BTreeMap<char, usize>, BTreeMap<char, usize>) = counts
+        .into_iter()
+        .partition(|&(ch, freq)| freq >= min_freq || ch == top);
+
+    let mut leaves: Vec<Branch> = kept
+        .into_iter()
+        .map(|(ch, freq)| Branch::new(Box::new(Node::new_leaf(ch)), freq))
+        .collect();
+    let escaped: usize = rare.values().sum();
+    if escaped > 0 {
+        leaves.push(Branch::new(Box::new(Node::new_escape()), escaped));
+    }
+    let tree = build_huffman(leaves);
+    let codes = assign_codes(&tree);
+    // Only needed if some characters were left out of the tree
+    let escape = escape_code(&tree, String::new()).unwrap_or_default();
+    let encoded = encode_escaped(input, &codes, &escape);
+
+    Encoder::new().package(input, ser_tree(tree, LeafFormat::Utf8), encoded, 0)
+}
+
+/// Same as `encode_to_bitstream()`, but no character counts for more than
+/// `cap_ratio` of the text when building the tree. Very skewed frequencies,
+/// such as those growing like the Fibonacci numbers, give a code one bit
+/// longer for each rarer character, and capping the common ones flattens the
+/// tree at a small cost in size. `cap_ratio` is between 0 and 1, and a cap
+/// that rounds below one occurrence is raised to one, which gives every
+/// character the same weight. `decode_from_bitstream()` reads the result like
+/// any other stream.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{decode_from_bitstream, deserialize_tree, encode_capped, encode_to_bitstream, HmcFile};
+/// 
+/// // Twenty characters appearing 1, 1, 2, 3, 5, 8, ... times
+/// let (mut a, mut b) = (1, 1);
+/// let mut input = String::new();
+/// for ch in 'a'..='t' {
+///     input.extend(std::iter::repeat_n(ch, a));
+///     (a, b) = (b, a + b);
+/// }
+/// let longest = |data: &[u8]| {
+///     let tree = deserialize_tree(&HmcFile::from_bytes(data).unwrap().tree).unwrap();
+///     tree.codes().last().unwrap().1.len()
+/// };
+/// 
+/// let plain = encode_to_bitstream(&input).unwrap();
+/// let capped = encode_capped(&input, 0.1).unwrap();
+/// assert_eq!(longest(&plain), 19);
+/// assert!(longest(&capped) < 19);
+/// assert_eq!(decode_from_bitstream(&capped).unwrap(), input);
+/// assert!(capped.len() < plain.len() + plain.len() / 100);
+/// 
+/// assert_eq!(encode_capped(&input, 1.0).unwrap(), plain);
+/// assert!(encode_capped(&input, 0.0).is_err());
+/// ```
+pub fn encode_capped(input: &str, cap_ratio: f64) -> Result<Vec<u8>> {
+    if input.is_empty() {
+        return Err(anyhow!("Input string is empty."));
+    }
+    if !(cap_ratio > 0.0 && cap_ratio <= 1.0) {
+        return Err(anyhow!("Cap ratio must be above 0 and at most 1."));
+    }
+
+    let mut counts = count_chars(input);
+    let total: usize = counts.values().sum();
+    let cap = ((cap_ratio * total as f64) as usize).max(1);
+    for freq in counts.values_mut() {
+        *freq = (*freq).min(cap);
+    }
+    let tree = tree_from_counts(counts);
+    let codes = assign_codes(&tree);
+    let encoded = encode_packed(input, &codes, BitOrder::MsbFirst);
+
+    Encoder::new().package(input, ser_tree(tree, LeafFormat::Utf8), encoded, 0)
+}
+
+/// How each leaf of a serialised tree stores its character.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{decode_from_bitstream, format_breakdown, Encoder, LeafFormat};
+/// 
+/// // Characters taking one, two, three and four bytes in UTF-8
+/// let input = "aé€😀 aé€ aé a";
+/// let fixed = Encoder::new().leaf_format(LeafFormat::Codepoint).encode(input).unwrap();
+/// assert_eq!(decode_from_bitstream(&fixed).unwrap(), input);
+/// 
+/// let utf8 = Encoder::new().encode(input).unwrap();
+/// let tree_bytes = |data: &[u8]| format_breakdown(data).unwrap().tree_bytes;
+/// assert!(tree_bytes(&fixed) > tree_bytes(&utf8));
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LeafFormat {
+    /// The UTF-8 bytes of the character, their number given by the first
+    #[default]
+    Utf8,
+    /// The codepoint as a fixed four bytes, which takes more room but does not
+    /// depend on reading the UTF-8 lead byte correctly
+    Codepoint,
+}
+
+/// How much work `Encoder` puts into making a stream small. Every level is
+/// recorded in the flags, so `decode_from_bitstream()` reads them all.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{decode_from_bitstream, Encoder, Level};
+/// 
+/// let book = include_str!("../examples/book.txt");
+/// let encode = |level| Encoder::new().level(level).encode(book).unwrap();
+/// for level in [Level::Fast, Level::Default, Level::Best] {
+///     assert_eq!(decode_from_bitstream(&encode(level)).unwrap(), book);
+/// }
+/// assert!(encode(Level::Best).len() <= encode(Level::Fast).len());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    /// Plain Huffman codes, with the shape of the tree stored as is
+    Fast,
+    /// Canonical codes, so that the tree is stored as the number of codes of
+    /// each length followed by the characters in code order
+    Default,
+    /// Whichever of the above gives the smaller stream. Other schemes, such
+    /// as LZ77 ahead of the Huffman stage, are to join the choice once there
+    /// are modules for them.
+    Best,
+}
+
+/// Builder for encoding with options other than the defaults used by
+/// `encode_to_bitstream()`.
+/// 
+/// By default the number of characters and a CRC-32 of the text are stored
+/// with the tree, and decoding fails if the decoded text does not match them.
+/// The length also lets decoders allocate the output up front. Turning
+/// `integrity` off gives the
+/// fast mode, which leaves out all integrity metadata for the smallest
+/// possible stream. This is meant for trusted, in-process use. Decoders tell
+/// the modes apart from the flags at the start of the stream, so
+/// `decode_from_bitstream()` reads both.
+/// 
+/// The encoded data fills each byte most significant bit first by default.
+/// `bit_order()` can switch it to least significant bit first for formats
+/// that expect it. The order is also recorded in the flags, as is the
+/// `LeafFormat` chosen with `leaf_format()`.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{Encoder, encode_to_bitstream, decode_from_bitstream};
+/// 
+/// let input = "Lorem ipsum dolor sit amet, consectetur adipiscing elit";
+/// let fast = Encoder::new().integrity(false).encode(input).unwrap();
+/// assert!(fast.len() < encode_to_bitstream(input).unwrap().len());
+/// assert_eq!(decode_from_bitstream(&fast).unwrap(), input);
+/// ```
+/// 
+/// Both bit orders decode, but the data of one cannot be read in the other.
+/// 
+/// ```
+/// use codecs::bits::BitOrder;
+/// use codecs::huffman::{Encoder, HmcFile, decode_from_bitstream};
+/// 
+/// let input = "Lorem ipsum dolor sit amet";
+/// let msb = Encoder::new().bit_order(BitOrder::MsbFirst).encode(input).unwrap();
+/// let lsb = Encoder::new().bit_order(BitOrder::LsbFirst).encode(input).unwrap();
+/// assert_eq!(decode_from_bitstream(&msb).unwrap(), input);
+/// assert_eq!(decode_from_bitstream(&lsb).unwrap(), input);
+/// 
+/// let mut swapped = HmcFile::from_bytes(&msb).unwrap();
+/// swapped.payload = HmcFile::from_bytes(&lsb).unwrap().payload;
+/// assert!(decode_from_bitstream(&swapped.to_bytes().unwrap()).is_err());
+/// ```
+#[derive(Debug, Clone)]
+pub struct Encoder {
+    integrity: bool,
+    order: BitOrder,
+    leaves: LeafFormat,
+    level: Level,
+    raw_below: usize,
+    collapse_whitespace: bool,
+    meta: Option<HmcMeta>,
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Self {
+            integrity: true,
+            order: BitOrder::MsbFirst,
+            leaves: LeafFormat::Utf8,
+            level: Level::Fast,
+            raw_below: 0,
+            collapse_whitespace: false,
+            meta: None,
+        }
+    }
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether to store the length and a checksum of the text. Defaults to
+    /// `true`.
+    pub fn integrity(mut self, integrity: bool) -> Self {
+        self.integrity = integrity;
+        self
+    }
+
+    /// The order in which the encoded data fills each byte. Defaults to
+    /// `BitOrder::MsbFirst`.
+    pub fn bit_order(mut self, order: BitOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// How the characters in the leaves of the tree are stored. Defaults to
+    /// `LeafFormat::Utf8`.
+    pub fn leaf_format(mut self, leaves: LeafFormat) -> Self {
+        self.leaves = leaves;
+        self
+    }
+
+    /// How hard to work at making the stream small. Defaults to `Level::Fast`,
+    /// which writes streams just as they were before levels were added.
+    pub fn level(mut self, level: Level) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Texts shorter than `len` bytes are stored as plain UTF-8 after the
+    /// flags, with no tree or checksum, unless there is metadata to store.
+    /// The number of characters goes between them unless integrity data is
+    /// turned off. Off by default, while `encode_to_bitstream()` stores texts under
+    /// 8 bytes this way.
+    pub fn raw_below(mut self, len: usize) -> Self {
+        self.raw_below = len;
+        self
+    }
+
+    /// Whether to collapse each run of two or more spaces, or of tabs, into a
+    /// single character counting them before building the tree, which
+    /// shrinks indented text such as source code. Decoders expand the runs
+    /// again. Texts using the last private use plane (U+100000 and up) are
+    /// encoded as they are, as that is where the run characters live.
+    /// Defaults to `false`.
+    /// 
+    /// ## Example
+    /// 
+    /// 
+    /// ```
+    /// use codecs::huffman::{append_compressed, decode_from_bitstream, decode_nth, decoded_length, Encoder};
+    /// 
+    /// let source = include_str!("bits.rs");
+    /// let plain = Encoder::new().encode(source).unwrap();
+    /// let collapsed = Encoder::new().collapse_whitespace(true).encode(source).unwrap();
+    /// assert!(collapsed.len() < plain.len(), "{} not below {}", collapsed.len(), plain.len());
+    /// assert_eq!(decode_from_bitstream(&collapsed).unwrap(), source);
+    /// assert_eq!(decoded_length(&collapsed).unwrap(), source.chars().count());
+    /// 
+    /// let tabbed = "fn main() {\n\t\tlet x  =  1;\n\t\t\tx\n}\n";
+    /// let data = Encoder::new().collapse_whitespace(true).encode(tabbed).unwrap();
+    /// assert_eq!(decode_from_bitstream(&data).unwrap(), tabbed);
+    /// assert_eq!(decode_nth(&data, 14).unwrap(), 'l');
+    /// let longer = append_compressed(&data, "\t\tx  \n").unwrap();
+    /// assert_eq!(decode_from_bitstream(&longer).unwrap(), tabbed.to_string() + "\t\tx  \n");
+    /// ```
+    pub fn collapse_whitespace(mut self, collapse: bool) -> Self {
+        self.collapse_whitespace = collapse;
+        self
+    }
+
+    /// File details to store in a trailer after the data, which
+    /// `read_metadata()` reads back. None are stored by default.
+    pub fn metadata(mut self, meta: HmcMeta) -> Self {
+        self.meta = Some(meta);
+        self
+    }
+
+    /// Encodes a text with the chosen options.
+    pub fn encode(&self, input: &str) -> Result<Vec<u8>> {
+        self.encode_text(input, 0)
+    }
+
+    // Encodes a text as `encode()` does, collapsing whitespace first if asked
+    fn encode_text(&self, input: &str, extra: usize) -> Result<Vec<u8>> {
+        if self.collapse_whitespace {
+            if let Some(collapsed) = collapse_runs(input).filter(|collapsed| collapsed.len() < input.len()) {
+                return self.encode_with_flags(&collapsed, extra | FLAG_WHITESPACE_RUNS);
+            }
+        }
+
+        self.encode_with_flags(input, extra)
+    }
+
+    // Encodes a text, setting `extra` in the flags alongside those the options
+    // call for
+    fn encode_with_flags(&self, input: &str, extra: usize) -> Result<Vec<u8>> {
+        if input.is_empty() {
+            return Err(anyhow!("Input string is empty."));
+        }
+        let mut glob = Vec::new();
+        if self.write_untreed(input, extra, &mut glob)? {
+            return Ok(glob);
+        }
+
+        match self.level {
+            Level::Fast => {
+                let tree = gen_tree(input);
+                let codes = assign_codes(&tree);
+                let encoded = encode_packed(input, &codes, self.order);
+
+                self.package(input, ser_tree(tree, self.leaves), encoded, extra)
+            }
+            Level::Default => {
+                let lengths = assign_codes(&gen_tree(input))
+                    .into_iter()
+                    .map(|(ch, code)| (ch, code.len()))
+                    .collect();
+                let canonical = assign_canonical(lengths);
+                let codes: HashMap<char, String> = canonical.iter().cloned().collect();
+                let encoded = encode_packed(input, &codes, self.order);
+
+                self.package(input, ser_canonical(&canonical, self.leaves)?, encoded, extra | FLAG_CANONICAL)
+            }
+            Level::Best => {
+                let fast = self.clone().level(Level::Fast).encode_with_flags(input, extra)?;
+                let canonical = self.clone().level(Level::Default).encode_with_flags(input, extra)?;
+                Ok(if canonical.len() < fast.len() { canonical } else { fast })
+            }
+        }
+    }
+
+    // Writes `input` to `glob` in one of the forms without a tree, a single
+    // character repeated or raw text, returning whether it did
+    fn write_untreed(&self, input: &str, extra: usize, glob: &mut Vec<u8>) -> Result<bool> {
+        if extra != 0 || self.meta.is_some() {
+            return Ok(false);
+        }
+
+        // One character repeated needs no tree, only the character and a count
+        let single = repeated_char(input).zip(uint_to_vwe(input.chars().count()).ok());
+        if let Some((ch, count)) = single {
+            glob.extend(uint_to_vwe(FLAG_SINGLE)?);
+            glob.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes());
+            glob.extend(count);
+            return Ok(true);
+        }
+        if input.len() < self.raw_below {
+            // The number of characters lets the end of the text be found when
+            // other data follows it
+            match uint_to_vwe(input.chars().count()).ok().filter(|_| self.integrity) {
+                Some(length) => {
+                    glob.extend(uint_to_vwe(FLAG_RAW | FLAG_LENGTH)?);
+                    glob.extend(length);
+                }
+                None => glob.extend(uint_to_vwe(FLAG_RAW)?),
+            }
+            glob.extend_from_slice(input.as_bytes());
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    // Writes the header, the serialised tree and the encoded data of `input`
+    // as a stream
+    fn package(&self, input: &str, stree: Vec<u8>, encoded: PackedBits, extra: usize) -> Result<Vec<u8>> {
+        let mut glob = Vec::new();
+        self.package_into(input, &stree, &encoded, extra, &mut glob)?;
+
+        Ok(glob)
+    }
+
+    // Same as `package()`, appending the stream to `glob`
+    fn package_into(
+        &self,
+        input: &str,
+        stree: &[u8],
+        encoded: &PackedBits,
+        extra: usize,
+        glob: &mut Vec<u8>,
+    ) -> Result<()> {
+        // Serialise all data according to schema. Flags, then the tree length in the narrowest width that fits
+        let width = tree_len_width(stree.len());
+        let mut flags = width | extra;
+        // Texts too long for a variable width number go without a length
+        let length = uint_to_vwe(input.chars().count()).ok().filter(|_| self.integrity);
+        if length.is_some() {
+            flags |= FLAG_LENGTH;
+        }
+        if self.integrity {
+            flags |= FLAG_CHECKSUM;
+        }
+        if self.order == BitOrder::LsbFirst {
+            flags |= FLAG_LSB_FIRST;
+        }
+        if self.leaves == LeafFormat::Codepoint {
+            flags |= FLAG_CODEPOINT_LEAVES;
+        }
+        if self.meta.is_some() {
+            flags |= FLAG_TRAILER;
+        }
+        glob.extend(uint_to_vwe(flags)?);
+        glob.extend(write_tree_len(stree.len(), width)?);
+        if let Some(length) = length {
+            glob.extend(length);
+        }
+        if self.integrity {
+            glob.extend_from_slice(&crc32(input.as_bytes()).to_be_bytes());
+        }
+        glob.extend_from_slice(stree);
+        glob.push(encoded.pad);
+        glob.extend_from_slice(&encoded.bytes);
+        if let Some(meta) = &self.meta {
+            glob.extend(write_trailer(meta)?);
+        }
+
+        Ok(())
+    }
+}
+
+/// Decompresses a raw binary format and retrieves the tree and encoded data for decoding.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```no_run
+/// use codecs::huffman::decode_from_bitstream;
+/// use std::fs;
+/// 
+/// # fn main() -> anyhow::Result<()> {
+/// let file = "output.hmc";
+/// let data: Vec<u8> = fs::read(file).expect("File not found.");
+/// let output = decode_from_bitstream(&data)?;
+/// println!("{output}");
+/// # Ok(())
+/// # }
+/// ```
+/// 
+/// A text made of a single repeated character has a tree that is a lone leaf.
+/// Each character is then coded as a single `0` bit.
+/// 
+/// ```
+/// use codecs::huffman::{encode_to_bitstream, decode_from_bitstream, Encoder};
+/// 
+/// let data = encode_to_bitstream("aaaaaaaaaa").unwrap();
+/// assert_eq!(decode_from_bitstream(&data).unwrap(), "aaaaaaaaaa");
+/// let fast = Encoder::new().integrity(false).encode("a").unwrap();
+/// assert_eq!(decode_from_bitstream(&fast).unwrap(), "a");
+/// ```
+/// 
+/// Large alphabets make deep trees with tens of thousands of leaves, which
+/// are built without copying any part of them.
+/// 
+/// ```
+/// use codecs::huffman::{encode_to_bitstream, decode_from_bitstream};
+/// 
+/// // Every character from U+10000 on, 50,000 of them, some more often than others
+/// let text: String = (0..50_000u32)
+///     .flat_map(|i| std::iter::repeat_n(char::from_u32(0x10000 + i).unwrap(), 1 + (i % 3) as usize))
+///     .collect();
+/// let data = encode_to_bitstream(&text).unwrap();
+/// assert_eq!(decode_from_bitstream(&data).unwrap(), text);
+/// ```
+pub fn decode_from_bitstream(input: &[u8]) -> Result<String> {
+    decode_from_bitstream_with_endian(input, LengthEndian::Big)
+}
+
+/// Byte order of a two or four byte tree length. Streams written by this
+/// crate are always big endian, but some variants of the format wrote little
+/// endian lengths, which `decode_from_bitstream_with_endian()` can read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LengthEndian {
+    /// Most significant byte first, as this crate writes
+    #[default]
+    Big,
+    /// Least significant byte first, as in some older files
+    Little,
+}
+
+/// Same as `decode_from_bitstream()`, reading a fixed width tree length in
+/// `endian` byte order. One byte and variable width lengths read the same
+/// either way.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{decode_from_bitstream, decode_from_bitstream_with_endian, encode_to_bitstream, LengthEndian};
+/// 
+/// // Flags for a two byte tree length and nothing else, the tree length of 3
+/// // little endian, the tree with 'a' as 0 and 'b' as 1, then "abba"
+/// let data = [0x01, 0x03, 0x00, 0x58, 0x6C, 0x40, 4, 0b0110_0000];
+/// assert_eq!(decode_from_bitstream_with_endian(&data, LengthEndian::Little).unwrap(), "abba");
+/// assert!(decode_from_bitstream(&data).is_err());
+/// 
+/// let data = encode_to_bitstream("Lorem ipsum dolor sit amet").unwrap();
+/// assert_eq!(decode_from_bitstream_with_endian(&data, LengthEndian::Big).unwrap(), "Lorem ipsum dolor sit amet");
+/// ```
+pub fn decode_from_bitstream_with_endian(input: &[u8], endian: LengthEndian) -> Result<String> {
+    if let Some(text) = raw_text(input)? {
+        return Ok(text.to_string());
+    }
+
+    let sections = split_sections_with(input, endian)?;
+    // The stored length is exact for ASCII and a lower bound otherwise, but
+    // is not trusted past one character per bit of data
+    let capacity = sections.length.unwrap_or(0).min(sections.data.len() * 8);
+    let mut output = String::with_capacity(capacity);
+
+    decode_sections(&sections, |ch| output.push(ch))?;
+
+    Ok(output)
+}
+
+/// Returns the number of characters a stream decodes to, for showing progress
+/// or allocating up front. This is read from the stored length when there is
+/// one, and otherwise counted by decoding the stream without keeping the text.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{decode_from_bitstream, decoded_length, encode_to_bitstream, Encoder};
+/// 
+/// let input = "Größe: 42 €";
+/// for data in [encode_to_bitstream(input).unwrap(), Encoder::new().integrity(false).encode(input).unwrap()] {
+///     assert_eq!(decoded_length(&data).unwrap(), decode_from_bitstream(&data).unwrap().chars().count());
+/// }
+/// assert_eq!(decoded_length(&encode_to_bitstream("abc").unwrap()).unwrap(), 3);
+/// ```
+pub fn decoded_length(input: &[u8]) -> Result<usize> {
+    if let Some((_, count)) = single_symbol(input)? {
+        return Ok(count);
+    }
+    if let Some(text) = raw_text(input)? {
+        return Ok(text.chars().count());
+    }
+
+    let sections = split_sections(input)?;
+    // The stored length counts runs of whitespace as single characters
+    if let Some(length) = sections.length.filter(|_| sections.flags & FLAG_WHITESPACE_RUNS == 0) {
+        return Ok(length);
+    }
+
+    let mut count = 0;
+    decode_sections(&sections, |_| count += 1)?;
+
+    Ok(count)
+}
+
+/// Same as `decode_from_bitstream()`, for a stream followed by other data,
+/// such as the next stream in a buffer. Decoding stops after the number of
+/// characters in the stored length, and whatever follows the stream is
+/// returned alongside the text. Raw and single character streams end after
+/// their stored number of characters or repeats. Fails for streams without a
+/// stored length, and for streams with a trailer, as its place is only known
+/// from the end.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{decode_from_bitstream_with_remainder, encode_to_bitstream};
+/// 
+/// let mut buffer = encode_to_bitstream("Lorem ipsum dolor sit amet").unwrap();
+/// buffer.extend_from_slice(b"trailing bytes");
+/// let (text, rest) = decode_from_bitstream_with_remainder(&buffer).unwrap();
+/// assert_eq!(text, "Lorem ipsum dolor sit amet");
+/// assert_eq!(rest, b"trailing bytes");
+/// 
+/// let mut joined = encode_to_bitstream("first stream").unwrap();
+/// joined.extend(encode_to_bitstream("second stream").unwrap());
+/// let (first, rest) = decode_from_bitstream_with_remainder(&joined).unwrap();
+/// let (second, rest) = decode_from_bitstream_with_remainder(rest).unwrap();
+/// assert_eq!((first.as_str(), second.as_str(), rest), ("first stream", "second stream", &[][..]));
+/// 
+/// // Short and repeated texts have no tree, but still know where they end
+/// let mut short = encode_to_bitstream("abc").unwrap();
+/// short.extend(encode_to_bitstream("zzzz").unwrap());
+/// short.extend_from_slice(b"trailing bytes");
+/// let (first, rest) = decode_from_bitstream_with_remainder(&short).unwrap();
+/// let (second, rest) = decode_from_bitstream_with_remainder(rest).unwrap();
+/// assert_eq!((first.as_str(), second.as_str(), rest), ("abc", "zzzz", &b"trailing bytes"[..]));
+/// ```
+pub fn decode_from_bitstream_with_remainder(input: &[u8]) -> Result<(String, &[u8])> {
+    if let Some((ch, count, rest)) = split_single(input)? {
+        return Ok((std::iter::repeat_n(ch, count).collect(), rest));
+    }
+    if let Some((text, rest)) = split_raw(input)? {
+        let (flags, _) = vwe_to_uint(input)?;
+        if flags & FLAG_LENGTH == 0 {
+            return Err(anyhow!("Stream has no stored length, so its end cannot be found."));
+        }
+        return Ok((text.to_string(), rest));
+    }
+
+    let sections = split_sections(input)?;
+    reject_runs(&sections)?;
+    if sections.meta.is_some() {
+        return Err(anyhow!("Stream has a trailer, so its end cannot be found."));
+    }
+    let length = sections
+        .length
+        .ok_or_else(|| anyhow!("Stream has no stored length, so its end cannot be found."))?;
+    let tree = read_tree(&sections)?;
+
+    let mut bundle = BitBundle::with_order(sections.data, sections.order);
+    let mut cursor = TreeCursor::new(&tree);
+    let mut output = String::with_capacity(length.min(sections.data.len() * 8));
+    let mut count = 0;
+    let mut crc = Crc32::new();
+    while count < length {
+        let bit = bundle.read_bit().ok_or_else(|| anyhow!("Truncated data."))?;
+        let ch = match cursor.step(bit) {
+            StepResult::Internal => continue,
+            StepResult::Leaf(ch) => ch,
+            StepResult::Escape => read_char(&mut bundle).ok_or_else(|| anyhow!("Truncated escaped character."))?,
+            StepResult::Invalid => return Err(anyhow!("Invalid code in data.")),
+        };
+        count += 1;
+        crc.update(ch.encode_utf8(&mut [0; 4]).as_bytes());
+        output.push(ch);
+    }
+    check_sections(&sections, count, &crc)?;
+
+    // The stored padding must fill out the last byte of data exactly
+    let bits = bundle.position();
+    let used = bits.div_ceil(8);
+    if used * 8 - bits != sections.pack as usize {
+        return Err(anyhow!("Padding mismatch."));
+    }
+
+    Ok((output, &sections.data[used..]))
+}
+
+/// Decodes a stream, handing each character to `f` as it is read rather than
+/// collecting them into a `String`. The length and checksum can only be
+/// checked at the end, so `f` may already have seen the text of a stream that
+/// then fails.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{decode_for_each, decode_from_bitstream, encode_to_bitstream};
+/// 
+/// let data = encode_to_bitstream("Lorem ipsum dolor sit amet").unwrap();
+/// let mut text = String::new();
+/// let mut vowels = 0;
+/// decode_for_each(&data, |ch| {
+///     text.push(ch);
+///     vowels += "aeiou".contains(ch) as usize;
+/// })
+/// .unwrap();
+/// assert_eq!(text, decode_from_bitstream(&data).unwrap());
+/// assert_eq!(vowels, 9);
+/// ```
+pub fn decode_for_each<F: FnMut(char)>(input: &[u8], f: F) -> Result<()> {
+    decode_stream(input, f)
+}
+
+/// Decodes a stream into segments of `segment_size` characters each, the last
+/// one possibly shorter, for outputs too large to hold comfortably in one
+/// `String`. Each segment is allocated on its own as it fills, so no single
+/// allocation holds more than a segment.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{decode_from_bitstream, decode_to_segments, encode_to_bitstream};
+/// 
+/// let book = include_str!("../examples/book.txt");
+/// let data = encode_to_bitstream(book).unwrap();
+/// let segments = decode_to_segments(&data, 4096).unwrap();
+/// assert_eq!(segments.concat(), decode_from_bitstream(&data).unwrap());
+/// assert!(segments.iter().all(|segment| segment.chars().count() <= 4096));
+/// assert_eq!(segments.len(), book.chars().count().div_ceil(4096));
+/// 
+/// assert_eq!(decode_to_segments(&encode_to_bitstream("abc").unwrap(), 2).unwrap(), ["ab", "c"]);
+/// assert!(decode_to_segments(&data, 0).is_err());
+/// ```
+pub fn decode_to_segments(input: &[u8], segment_size: usize) -> Result<Vec<String>> {
+    if segment_size == 0 {
+        return Err(anyhow!("Segment size must be at least one character."));
+    }
+
+    let mut segments = Vec::new();
+    let mut segment = String::new();
+    let mut count = 0;
+    decode_stream(input, |ch| {
+        segment.push(ch);
+        count += 1;
+        if count == segment_size {
+            segments.push(std::mem::take(&mut segment));
+            count = 0;
+        }
+    })?;
+    if count > 0 {
+        segments.push(segment);
+    }
+
+    Ok(segments)
+}
+
+/// Decodes a stream that arrives in pieces, such as one read off a socket,
+/// handing back each character as soon as the bytes for it are in. The header
+/// and tree are held until they are complete, and after that each `feed()`
+/// gives the characters its bytes finish. `finish()` decodes what is left and
+/// checks the stored length and checksum, so that together they give exactly
+/// what `decode_from_bitstream()` gives for the whole stream.
+/// 
+/// Memory stays bounded by the tree and the last partial code. Raw streams,
+/// single character streams and streams with a trailer are held whole and
+/// decoded by `finish()`, as only the end of the stream says where their text
+/// stops. So are streams split into blocks, which `finish()` decodes as
+/// `decode_blocks()` does.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{decode_from_bitstream, encode_auto_blocks, encode_to_bitstream, Encoder, IncrementalDecoder};
+/// 
+/// let input = "It was the best of times, it was the worst of times, ¿verdad? 🦀";
+/// let complete = Encoder::new().collapse_whitespace(true).encode("tabs\t\t\t\tand    spaces").unwrap();
+/// for data in [encode_to_bitstream(input).unwrap(), encode_to_bitstream("abc").unwrap(), complete] {
+///     // One byte at a time, as slowly as a stream can arrive
+///     let mut decoder = IncrementalDecoder::new();
+///     let mut output = Vec::new();
+///     for &byte in &data {
+///         output.extend(decoder.feed(&[byte]).unwrap());
+///     }
+///     output.extend(decoder.finish().unwrap());
+///     assert_eq!(output.into_iter().collect::<String>(), decode_from_bitstream(&data).unwrap());
+/// }
+/// 
+/// // Characters arrive before the stream ends
+/// let data = encode_to_bitstream(input).unwrap();
+/// let mut decoder = IncrementalDecoder::new();
+/// assert!(!decoder.feed(&data[..data.len() - 2]).unwrap().is_empty());
+/// 
+/// // A stream split into blocks gives nothing back until it is finished
+/// let mixed = input.repeat(40) + &"3141592653589793238462643383279".repeat(60);
+/// let blocks = encode_auto_blocks(&mixed).unwrap();
+/// assert!(decode_from_bitstream(&blocks).is_err());
+/// let mut decoder = IncrementalDecoder::new();
+/// assert!(decoder.feed(&blocks).unwrap().is_empty());
+/// assert_eq!(decoder.finish().unwrap().into_iter().collect::<String>(), mixed);
+/// 
+/// let mut corrupt = data.clone();
+/// *corrupt.last_mut().unwrap() ^= 0x80;
+/// let mut decoder = IncrementalDecoder::new();
+/// decoder.feed(&corrupt).unwrap();
+/// assert!(decoder.finish().is_err());
+/// ```
+#[derive(Default)]
+pub struct IncrementalDecoder {
+    buf: Vec<u8>,
+    stream: Option<Incremental>,
+    buffered: bool,
+}
+
+// State of a stream whose header and tree have been read. `buf` then holds
+// only data, from the byte holding bit `bit`, the start of the next code.
+struct Incremental {
+    header: HmcHeader,
+    tree: Node,
+    order: BitOrder,
+    pack: u8,
+    bit: usize,
+    count: usize,
+    crc: Crc32,
+}
+
+impl IncrementalDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes the next piece of the stream, returning the characters it
+    /// completes.
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<Vec<char>> {
+        self.buf.extend_from_slice(bytes);
+        let mut output = Vec::new();
+        if self.stream.is_none() && !self.buffered {
+            self.start()?;
+        }
+
+        if let Some(stream) = &mut self.stream {
+            // The padding only ever sits in the last byte, so every bit before
+            // the last `pack` bits is data, wherever the stream ends
+            let end = (self.buf.len() * 8).saturating_sub(stream.pack as usize);
+            stream.advance(&self.buf, end, false, &mut output)?;
+            self.buf.drain(..(stream.bit / 8));
+            stream.bit %= 8;
+        }
+
+        Ok(output)
+    }
+
+    /// Ends the stream, returning the characters not yet handed back and
+    /// checking the whole against the stored length and checksum.
+    pub fn finish(self) -> Result<Vec<char>> {
+        let Some(mut stream) = self.stream else {
+            return Ok(decode_blocks(&self.buf)?.chars().collect());
+        };
+
+        let end = (self.buf.len() * 8)
+            .checked_sub(stream.pack as usize)
+            .ok_or_else(|| anyhow!("Padding exceeds data length."))?;
+        let mut output = Vec::new();
+        stream.advance(&self.buf, end, true, &mut output)?;
+
+        if stream.header.length.is_some_and(|length| length != stream.count) {
+            return Err(anyhow!("Length mismatch."));
+        }
+        match stream.header.checksum {
+            Some(checksum) if checksum != stream.crc.finish() => Err(anyhow!("Checksum mismatch.")),
+            _ => Ok(output),
+        }
+    }
+
+    // Reads the header and tree once they are all in
+    fn start(&mut self) -> Result<()> {
+        let flags = match vwe_to_uint(&self.buf) {
+            Err(_) if self.buf.len() < HmcHeader::MAX_LEN => return Ok(()),
+            parsed => parsed?.0,
+        };
+        if flags & (FLAG_RAW | FLAG_SINGLE | FLAG_BLOCKS | FLAG_TRAILER) != 0 {
+            self.buffered = true;
+            return Ok(());
+        }
+
+        let (header, rest) = match HmcHeader::parse(&self.buf) {
+            Err(_) if self.buf.len() < HmcHeader::MAX_LEN => return Ok(()),
+            parsed => parsed?,
+        };
+        if rest.len() < header.tree_len + 1 {
+            return Ok(());
+        }
+
+        let leaves = if flags & FLAG_CODEPOINT_LEAVES != 0 { LeafFormat::Codepoint } else { LeafFormat::Utf8 };
+        let tree_bytes = &rest[..header.tree_len];
+        let tree = if flags & FLAG_CANONICAL != 0 {
+            des_canonical(tree_bytes, leaves)?
+        } else {
+            des_tree(tree_bytes, leaves)?
+        };
+        self.stream = Some(Incremental {
+            header,
+            tree,
+            order: if flags & FLAG_LSB_FIRST != 0 { BitOrder::LsbFirst } else { BitOrder::MsbFirst },
+            pack: rest[header.tree_len],
+            bit: 0,
+            count: 0,
+            crc: Crc32::new(),
+        });
+        self.buf.drain(..(header.size + header.tree_len + 1));
+
+        Ok(())
+    }
+}
+
+impl Incremental {
+    // Decodes every whole code in `data` before bit `end`, leaving `bit` at
+    // the start of the first code that is not yet complete. On the last call
+    // an escaped character cut short is an error rather than a wait for more.
+    fn advance(&mut self, data: &[u8], end: usize, last: bool, output: &mut Vec<char>) -> Result<()> {
+        let runs = self.header.flags & FLAG_WHITESPACE_RUNS != 0;
+        let mut emit = |ch: char| {
+            self.count += 1;
+            self.crc.update(ch.encode_utf8(&mut [0; 4]).as_bytes());
+            if runs {
+                let _ = expand_run(ch, &mut |ch| {
+                    output.push(ch);
+                    ControlFlow::Continue(())
+                });
+            } else {
+                output.push(ch);
+            }
+        };
+
+        // A tree that is a single leaf codes each character as a single bit
+        if let Some(ch) = self.tree.ch {
+            for _ in self.bit..end {
+                emit(ch);
+            }
+            self.bit = self.bit.max(end);
+            return Ok(());
+        }
+
+        let mut bundle = BitBundle::with_order(data, self.order);
+        bundle.read_bits(self.bit as u8);
+        let mut nodeptr = &self.tree;
+        while bundle.position() < end {
+            let bit = bundle.read_bit().unwrap();
+            let next = if bit == 0 { &nodeptr.left } else { &nodeptr.right };
+            nodeptr = next.as_ref().ok_or_else(|| anyhow!("Invalid code in data."))?;
+            let ch = if let Some(ch) = nodeptr.ch {
+                ch
+            } else if nodeptr.is_escape() {
+                match read_char(&mut bundle) {
+                    Some(ch) if bundle.position() <= end => ch,
+                    _ if last => return Err(anyhow!("Truncated escaped character.")),
+                    _ => break,
+                }
+            } else {
+                continue;
+            };
+            emit(ch);
+            self.bit = bundle.position();
+            nodeptr = &self.tree;
+        }
+
+        Ok(())
+    }
+}
+
+/// Same as `decode_from_bitstream()`, but gives up with an error once more
+/// than `max_chars` characters would be produced. A crafted stream can expand
+/// to far more text than its size suggests, so use this on untrusted input to
+/// bound the memory spent on the output.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{Encoder, decode_from_bitstream_capped};
+/// 
+/// let data = Encoder::new().integrity(false).encode("a").unwrap();
+/// assert_eq!(decode_from_bitstream_capped(&data, 1).unwrap(), "a");
+/// 
+/// // With a single leaf tree every bit is a character, so this is over 8 million
+/// let mut bomb = data.clone();
+/// bomb.extend(vec![0; 1_000_000]);
+/// assert!(decode_from_bitstream_capped(&bomb, 1000).is_err());
+/// ```
+pub fn decode_from_bitstream_capped(input: &[u8], max_chars: usize) -> Result<String> {
+    if single_symbol(input)?.is_some_and(|(_, count)| count > max_chars) {
+        return Err(anyhow!("Output exceeds {max_chars} characters."));
+    }
+    if let Some(text) = raw_text(input)? {
+        if text.chars().count() > max_chars {
+            return Err(anyhow!("Output exceeds {max_chars} characters."));
+        }
+        return Ok(text.to_string());
+    }
+
+    let sections = split_sections(input)?;
+    if sections.length.is_some_and(|length| length > max_chars) {
+        return Err(anyhow!("Output exceeds {max_chars} characters."));
+    }
+
+    let mut output = String::with_capacity(sections.length.unwrap_or(0).min(sections.data.len() * 8));
+    let mut count = 0;
+    let mut overflow = false;
+    decode_sections_until(&sections, |ch| {
+        if count == max_chars {
+            overflow = true;
+            return ControlFlow::Break(());
+        }
+        count += 1;
+        output.push(ch);
+        ControlFlow::Continue(())
+    })?;
+    if overflow {
+        return Err(anyhow!("Output exceeds {max_chars} characters."));
+    }
+
+    Ok(output)
+}
+
+/// Same as `decode_from_bitstream()`, but gives up with an error once more
+/// than `max_steps` bits have been walked through the tree. This bounds the
+/// time spent on untrusted input whatever the size of the output, as a
+/// crafted tree can make every code very long.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{append_compressed, decode_from_bitstream_budgeted, encode_to_bitstream};
+/// 
+/// // Fibonacci frequencies give the rarest letter a fifteen bit code
+/// let mut fib = (1, 1);
+/// let mut text = String::new();
+/// for ch in 'a'..='p' {
+///     text.extend(std::iter::repeat_n(ch, fib.0));
+///     fib = (fib.1, fib.0 + fib.1);
+/// }
+/// let data = append_compressed(&encode_to_bitstream(&text).unwrap(), &"a".repeat(1000)).unwrap();
+/// 
+/// assert!(decode_from_bitstream_budgeted(&data, 20_000).is_err());
+/// let output = decode_from_bitstream_budgeted(&data, 100_000).unwrap();
+/// assert_eq!(output.len(), text.len() + 1000);
+/// ```
+pub fn decode_from_bitstream_budgeted(input: &[u8], max_steps: usize) -> Result<String> {
+    // There is no tree to walk in a raw stream
+    if let Some(text) = raw_text(input)? {
+        return Ok(text.to_string());
+    }
+
+    let sections = split_sections(input)?;
+    reject_runs(&sections)?;
+    let tree = read_tree(&sections)?;
+    let total_bits = (sections.data.len() * 8)
+        .checked_sub(sections.pack as usize)
+        .ok_or_else(|| anyhow!("Padding exceeds data length."))?;
+
+    let mut bundle = BitBundle::with_order(sections.data, sections.order);
+    let mut cursor = TreeCursor::new(&tree);
+    let mut output = String::new();
+    let mut count = 0;
+    let mut crc = Crc32::new();
+    while bundle.position() < total_bits {
+        if bundle.position() >= max_steps {
+            return Err(anyhow!("Decoding exceeds {max_steps} steps."));
+        }
+        let ch = match cursor.step(bundle.read_bit().unwrap()) {
+            StepResult::Internal => continue,
+            StepResult::Leaf(ch) => ch,
+            StepResult::Escape => read_char(&mut bundle).ok_or_else(|| anyhow!("Truncated escaped character."))?,
+            StepResult::Invalid => return Err(anyhow!("Invalid code in data.")),
+        };
+        count += 1;
+        crc.update(ch.encode_utf8(&mut [0; 4]).as_bytes());
+        output.push(ch);
+    }
+
+    check_sections(&sections, count, &crc)?;
+
+    Ok(output)
+}
+
+/// Decodes a stream, pairing each character with the code that produced it.
+/// An escaped character's code is the escape code followed by the bits of
+/// the character itself, so that the codes run back to back through the data.
+/// The characters of a raw stream are stored whole, so each has its own
+/// UTF-8 bits as its code, and those of a single character stream have none.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{decode_annotated, encode_to_bitstream, HmcFile};
+/// 
+/// let data = encode_to_bitstream("abracadabra").unwrap();
+/// let annotated = decode_annotated(&data).unwrap();
+/// let text: String = annotated.iter().map(|(ch, _)| ch).collect();
+/// assert_eq!(text, "abracadabra");
+/// 
+/// let file = HmcFile::from_bytes(&data).unwrap();
+/// let mut bits: String = file.payload.iter().map(|byte| format!("{byte:08b}")).collect();
+/// bits.truncate(bits.len() - file.pad as usize);
+/// let codes: String = annotated.iter().map(|(_, code)| code.as_str()).collect();
+/// assert_eq!(codes, bits);
+/// 
+/// let raw = decode_annotated(&encode_to_bitstream("abc").unwrap()).unwrap();
+/// assert_eq!(raw[0], ('a', String::from("01100001")));
+/// assert_eq!(raw.len(), 3);
+/// 
+/// let single = decode_annotated(&encode_to_bitstream("zzzz").unwrap()).unwrap();
+/// assert_eq!(single, vec![('z', String::new()); 4]);
+/// ```
+pub fn decode_annotated(input: &[u8]) -> Result<Vec<(char, String)>> {
+    if let Some((ch, count)) = single_symbol(input)? {
+        // A count of a few bytes can ask for far more than there is to give
+        let mut output = Vec::new();
+        output
+            .try_reserve_exact(count)
+            .map_err(|_| anyhow!("Not enough memory for {} characters.", count))?;
+        output.resize(count, (ch, String::new()));
+        return Ok(output);
+    }
+    if let Some(text) = raw_text(input)? {
+        return Ok(text.chars().map(|ch| (ch, utf8_bits(ch))).collect());
+    }
+
+    let sections = split_sections(input)?;
+    reject_runs(&sections)?;
+    let tree = read_tree(&sections)?;
+    let total_bits = (sections.data.len() * 8)
+        .checked_sub(sections.pack as usize)
+        .ok_or_else(|| anyhow!("Padding exceeds data length."))?;
+
+    let mut bundle = BitBundle::with_order(sections.data, sections.order);
+    let mut cursor = TreeCursor::new(&tree);
+    let mut output = Vec::new();
+    let mut code = String::new();
+    let mut crc = Crc32::new();
+    while bundle.position() < total_bits {
+        let bit = bundle.read_bit().unwrap();
+        code.push(if bit == 0 { '0' } else { '1' });
+        let ch = match cursor.step(bit) {
+            StepResult::Internal => continue,
+            StepResult::Leaf(ch) => ch,
+            StepResult::Escape => {
+                let ch = read_char(&mut bundle).ok_or_else(|| anyhow!("Truncated escaped character."))?;
+                code.push_str(&utf8_bits(ch));
+                ch
+            }
+            StepResult::Invalid => return Err(anyhow!("Invalid code in data.")),
+        };
+        crc.update(ch.encode_utf8(&mut [0; 4]).as_bytes());
+        output.push((ch, std::mem::take(&mut code)));
+    }
+
+    check_sections(&sections, output.len(), &crc)?;
+
+    Ok(output)
+}
+
+// The bits of a character's UTF-8 bytes as text
+fn utf8_bits(ch: char) -> String {
+    ch.encode_utf8(&mut [0; 4]).bytes().map(|byte| format!("{byte:08b}")).collect()
+}
+
+/// Returns the character at position `n` of an encoded stream. Huffman codes
+/// cannot be jumped into, so the data is still walked from the start, but
+/// decoding stops as soon as the character is found and no text is built.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{encode_to_bitstream, decode_from_bitstream, decode_nth};
+/// 
+/// let data = encode_to_bitstream("Lorem ipsum dolor sit amet").unwrap();
+/// let text = decode_from_bitstream(&data).unwrap();
+/// for k in [0, 6, 25] {
+///     assert_eq!(decode_nth(&data, k).ok(), text.chars().nth(k));
+/// }
+/// assert!(decode_nth(&data, 26).is_err());
+/// ```
+pub fn decode_nth(input: &[u8], n: usize) -> Result<char> {
+    if let Some(text) = raw_text(input)? {
+        return text.chars().nth(n).ok_or_else(|| anyhow!("Stream has fewer than {} characters.", n + 1));
+    }
+
+    let sections = split_sections(input)?;
+    let tree = read_tree(&sections)?;
+
+    let runs = sections.flags & FLAG_WHITESPACE_RUNS != 0;
+    let mut count = 0;
+    let mut found = None;
+    let mut visit = |ch| {
+        if count == n {
+            found = Some(ch);
+            return ControlFlow::Break(());
+        }
+        count += 1;
+        ControlFlow::Continue(())
+    };
+    walk_payload_until(&tree, sections.data, sections.pack, sections.order, |ch| {
+        if runs { expand_run(ch, &mut visit) } else { visit(ch) }
+    })?;
+
+    found.ok_or_else(|| anyhow!("Stream has fewer than {} characters.", n + 1))
+}
+
+/// Same as `decode_from_bitstream()`, but each decoded character is looked up
+/// in `map` and replaced as it is produced, which saves a second pass over the
+/// text. Characters missing from the map pass through unchanged.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{encode_to_bitstream, decode_from_bitstream_mapped};
+/// use std::collections::HashMap;
+/// 
+/// let data = encode_to_bitstream("banana").unwrap();
+/// let map = HashMap::from([('a', 'A')]);
+/// assert_eq!(decode_from_bitstream_mapped(&data, &map).unwrap(), "bAnAnA");
+/// ```
+pub fn decode_from_bitstream_mapped(input: &[u8], map: &HashMap<char, char>) -> Result<String> {
+    let mut output = String::new();
+
+    decode_stream(input, |ch| output.push(*map.get(&ch).unwrap_or(&ch)))?;
+
+    Ok(output)
+}
+
+/// Same as `decode_from_bitstream()`, but fails at the first character that
+/// is not in `allowed`, naming it and its position in the text, without
+/// decoding any further. Services that only expect a given character set can
+/// reject anything else before it goes on.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{decode_allowlist, encode_to_bitstream};
+/// use std::collections::HashSet;
+/// 
+/// let allowed: HashSet<char> = ('a'..='z').chain([' ', '.']).collect();
+/// let data = encode_to_bitstream("the quick brown fox.").unwrap();
+/// assert_eq!(decode_allowlist(&data, &allowed).unwrap(), "the quick brown fox.");
+/// 
+/// let data = encode_to_bitstream("select * from users; drop table").unwrap();
+/// let err = decode_allowlist(&data, &allowed).unwrap_err();
+/// assert_eq!(err.to_string(), "Character '*' at position 7 is not allowed.");
+/// 
+/// let err = decode_allowlist(&encode_to_bitstream("ab!").unwrap(), &allowed).unwrap_err();
+/// assert_eq!(err.to_string(), "Character '!' at position 2 is not allowed.");
+/// ```
+pub fn decode_allowlist(input: &[u8], allowed: &HashSet<char>) -> Result<String> {
+    let mut output = String::new();
+    let mut denied = None;
+    let mut check = |ch: char| {
+        if allowed.contains(&ch) {
+            output.push(ch);
+            ControlFlow::Continue(())
+        } else {
+            denied = Some(ch);
+            ControlFlow::Break(())
+        }
+    };
+
+    if let Some(text) = raw_text(input)? {
+        let _ = text.chars().try_for_each(&mut check);
+    } else {
+        decode_sections_until(&split_sections(input)?, check)?;
+    }
+    match denied {
+        Some(ch) => Err(anyhow!("Character {:?} at position {} is not allowed.", ch, output.chars().count())),
+        None => Ok(output),
+    }
+}
+
+/// Appends text to an encoded stream. When every character of the addition
+/// already has a code in the stream's tree, the tree is kept and only the new
+/// codes are written after the old ones, without decoding the stream. The
+/// stored length and checksum, if there are any, are brought up to date from
+/// the header alone, so the old data is not checked.
+/// 
+/// If the addition has characters the tree lacks, the stream is decoded and
+/// the joined text encoded again with a new tree, keeping the stream's options
+/// and any record of a stripped byte order mark.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{append_compressed, decode_from_bitstream, decode_from_bitstream_restore_bom};
+/// use codecs::huffman::{encode_to_bitstream, encode_to_bitstream_strip_bom, format_breakdown};
+/// 
+/// let base = encode_to_bitstream("GET /index.html 200\n").unwrap();
+/// 
+/// // Fast path, the tree is reused as is
+/// let fast = append_compressed(&base, "GET /title.html 200\n").unwrap();
+/// assert_eq!(decode_from_bitstream(&fast).unwrap(), "GET /index.html 200\nGET /title.html 200\n");
+/// assert_eq!(format_breakdown(&fast).unwrap().tree_bytes, format_breakdown(&base).unwrap().tree_bytes);
+/// 
+/// // 'P', 'O', 'S' and others are new, so the text is encoded again
+/// let slow = append_compressed(&base, "POST /upload 404\n").unwrap();
+/// assert_eq!(decode_from_bitstream(&slow).unwrap(), "GET /index.html 200\nPOST /upload 404\n");
+/// 
+/// // A stripped byte order mark is still restored after either path
+/// for (input, addition) in [("\u{FEFF}hello world", " hello"), ("\u{FEFF}hello world", " xyzq!"), ("\u{FEFF}hi", "x")] {
+///     let (bom, _) = encode_to_bitstream_strip_bom(input).unwrap();
+///     let appended = append_compressed(&bom, addition).unwrap();
+///     assert_eq!(decode_from_bitstream_restore_bom(&appended).unwrap(), input.to_string() + addition);
+/// }
+/// ```
+pub fn append_compressed(base: &[u8], addition: &str) -> Result<Vec<u8>> {
+    if let Some(text) = raw_text(base)? {
+        let (flags, _) = vwe_to_uint(base)?;
+        return Encoder::new()
+            .raw_below(RAW_BELOW)
+            .encode_text(&(text.to_string() + addition), flags & FLAG_BOM);
+    }
+
+    let sections = split_sections(base)?;
+    let tree = read_tree(&sections)?;
+    let codes = assign_codes(&tree);
+
+    // Runs in the addition are collapsed the same way, as a run split across
+    // the join still expands to the same whitespace
+    let runs = sections.flags & FLAG_WHITESPACE_RUNS != 0;
+    let collapsed = if runs { collapse_runs(addition) } else { Some(addition.to_string()) };
+    let addition = match collapsed.filter(|addition| addition.chars().all(|ch| codes.contains_key(&ch))) {
+        Some(addition) => addition,
+        None => {
+            let mut text = decode_from_bitstream(base)?;
+            text.push_str(addition);
+            let mut encoder = Encoder::new()
+                .integrity(sections.flags & FLAG_CHECKSUM != 0)
+                .bit_order(sections.order)
+                .leaf_format(sections.leaves)
+                .level(if sections.flags & FLAG_CANONICAL != 0 { Level::Default } else { Level::Fast })
+                .collapse_whitespace(runs);
+            encoder.meta = sections.meta;
+            return encoder.encode_text(&text, sections.flags & (ENCODING_MASK | FLAG_BOM));
+        }
+    };
+
+    let packed = PackedBits { bytes: sections.data.to_vec(), pad: sections.pack };
+    let mut writer = BitWriter::resume(packed, sections.order);
+    for ch in addition.chars() {
+        writer.write_code(&codes[&ch]);
+    }
+    let encoded = writer.finish();
+
+    // Drop the length if it has outgrown a variable width number, as `Encoder` would
+    let length = sections
+        .length
+        .and_then(|length| uint_to_vwe(length + addition.chars().count()).ok());
+    let mut flags = sections.flags & !FLAG_LENGTH;
+    if length.is_some() {
+        flags |= FLAG_LENGTH;
+    }
+
+    let mut glob = uint_to_vwe(flags)?;
+    glob.extend(write_tree_len(sections.tree.len(), flags & WIDTH_MASK)?);
+    if let Some(length) = length {
+        glob.extend(length);
+    }
+    if let Some(checksum) = sections.checksum {
+        let mut crc = Crc32::resume(checksum);
+        crc.update(addition.as_bytes());
+        glob.extend_from_slice(&crc.finish().to_be_bytes());
+    }
+    glob.extend_from_slice(sections.tree);
+    glob.push(encoded.pad);
+    glob.extend_from_slice(&encoded.bytes);
+    if let Some(meta) = &sections.meta {
+        glob.extend(write_trailer(meta)?);
+    }
+
+    Ok(glob)
+}
+
+/// Encodes a text without packaging it, for when the encoder and decoder are
+/// in the same process and serialising the tree would be wasted work. Returns
+/// the tree, the encoded data and the number of data packing bits, which
+/// `decode_in_memory()` takes back.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{encode_in_memory, decode_in_memory, encode_to_bitstream, decode_from_bitstream};
+/// 
+/// let input = "Lorem ipsum dolor sit amet";
+/// let (tree, data, pad) = encode_in_memory(input).unwrap();
+/// let output = decode_in_memory(&tree, &data, pad).unwrap();
+/// assert_eq!(output, decode_from_bitstream(&encode_to_bitstream(input).unwrap()).unwrap());
+/// ```
+pub fn encode_in_memory(input: &str) -> Result<(Node, Vec<u8>, u8)> {
+    if input.is_empty() {
+        return Err(anyhow!("Input string is empty."));
+    }
+
+    let tree = gen_tree(input);
+    let encoded = encode_packed(input, &assign_codes(&tree), BitOrder::MsbFirst);
+
+    Ok((tree, encoded.bytes, encoded.pad))
+}
+
+/// Encodes a text to its packed codes alone, returning the bytes and the exact
+/// number of bits used, for combining with other bit level data. The codes
+/// come from the same tree `encode_in_memory()` builds for the text.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{decode_in_memory, encode_in_memory, encode_to_bitvec};
+/// 
+/// let input = "Lorem ipsum dolor sit amet";
+/// let (bytes, bits) = encode_to_bitvec(input).unwrap();
+/// assert_eq!(bytes.len(), bits.div_ceil(8));
+/// 
+/// let (tree, _, _) = encode_in_memory(input).unwrap();
+/// let pad = (bytes.len() * 8 - bits) as u8;
+/// assert_eq!(decode_in_memory(&tree, &bytes, pad).unwrap(), input);
+/// ```
+pub fn encode_to_bitvec(input: &str) -> Result<(Vec<u8>, usize)> {
+    let (_, bytes, pad) = encode_in_memory(input)?;
+    let bits = bytes.len() * 8 - pad as usize;
+
+    Ok((bytes, bits))
+}
+
+/// Decodes data from `encode_in_memory()` with its tree.
+pub fn decode_in_memory(tree: &Node, data: &[u8], pad: u8) -> Result<String> {
+    let mut output = String::new();
+
+    walk_payload(tree, data, pad, |ch| output.push(ch))?;
+
+    Ok(output)
+}
+
+/// Serialises a tree into the compact form streams store it in, for storing
+/// or sending a tree on its own. `deserialize_tree()` reads it back.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{deserialize_tree, encode_in_memory, serialize_tree};
+/// 
+/// let (tree, _, _) = encode_in_memory("Lorem ipsum dolor sit amet").unwrap();
+/// let bytes = serialize_tree(&tree);
+/// assert_eq!(deserialize_tree(&bytes).unwrap(), tree);
+/// 
+/// assert!(deserialize_tree(&bytes[..bytes.len() / 2]).is_err());
+/// assert!(deserialize_tree(&[]).is_err());
+/// assert!(deserialize_tree(&[0x00; 8]).is_err());
+/// ```
+pub fn serialize_tree(tree: &Node) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+
+    traverse_tree(tree, &mut writer, LeafFormat::Utf8);
+
+    writer.finish().bytes
+}
+
+/// Returns the exact number of bits the tree for `input` takes when
+/// serialised, before padding to a whole byte: a bit for each node, and the
+/// UTF-8 bytes of each leaf's character. Nothing is allocated for the bits,
+/// so this predicts the cost of the tree in a stream cheaply.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{encode_in_memory, serialize_tree, tree_serialized_bits};
+/// 
+/// // A root and two leaves of one byte each
+/// assert_eq!(tree_serialized_bits("ab").unwrap(), 1 + 2 * (1 + 8));
+/// 
+/// let input = "Größe: 42 €, приблизительно";
+/// let bits = tree_serialized_bits(input).unwrap();
+/// let (tree, _, _) = encode_in_memory(input).unwrap();
+/// assert_eq!(serialize_tree(&tree).len(), bits.div_ceil(8));
+/// assert!(tree_serialized_bits("").is_err());
+/// ```
+pub fn tree_serialized_bits(input: &str) -> Result<usize> {
+    if input.is_empty() {
+        return Err(anyhow!("Input string is empty."));
+    }
+
+    Ok(tree_bits(&gen_tree(input), LeafFormat::Utf8))
+}
+
+/// Reads a tree serialised by `serialize_tree()`, failing if the bytes do not
+/// describe one.
+pub fn deserialize_tree(bytes: &[u8]) -> Result<Node> {
+    des_tree(bytes, LeafFormat::Utf8)
+}
+
+/// Checks that a tree describes a usable code: every internal node has both
+/// children, no leaf has any, and no character has two leaves, so that
+/// every path ends at exactly one character and every character has exactly
+/// one code. Trees read from a stream are checked this way, which stops a
+/// crafted tree from decoding to something its encoder could not have meant.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{deserialize_tree, serialize_tree, tree_from_code_lengths, validate_tree};
+/// 
+/// let tree = tree_from_code_lengths(&[('a', 1), ('b', 2), ('c', 2)]).unwrap();
+/// assert!(validate_tree(&tree).is_ok());
+/// assert_eq!(deserialize_tree(&serialize_tree(&tree)).unwrap(), tree);
+/// 
+/// // An internal node, a leaf for 'a' and then no second child
+/// assert!(deserialize_tree(&[0b0101_1000, 0b0100_0000]).is_err());
+/// // An internal node with a leaf for 'a' on both sides
+/// assert!(deserialize_tree(&[0b0101_1000, 0b0110_1100, 0b0010_0000]).is_err());
+/// assert!(deserialize_tree(&[0b0101_1000, 0b0110_1100, 0b0100_0000]).is_ok());
+/// ```
+pub fn validate_tree(tree: &Node) -> Result<()> {
+    let mut seen = HashSet::new();
+    let mut escapes = 0;
+    let mut stack = vec![tree];
+    while let Some(node) = stack.pop() {
+        match (&node.left, &node.right) {
+            (Some(left), Some(right)) => {
+                if let Some(ch) = node.ch {
+                    return Err(anyhow!("Leaf {:?} has children.", ch));
+                }
+                stack.push(right);
+                stack.push(left);
+            }
+            (None, None) => match node.ch {
+                Some(ch) if !seen.insert(ch) => return Err(anyhow!("Character {:?} has two leaves.", ch)),
+                Some(_) => {}
+                None if escapes > 0 => return Err(anyhow!("Tree has two escape leaves.")),
+                None => escapes += 1,
+            },
+            _ => return Err(anyhow!("Internal node has one child.")),
+        }
+    }
+
+    Ok(())
+}
+
+/// Builds the Huffman tree for characters with known weights, such as counts
+/// taken from a sample or from logs, without a text to count them from. The
+/// tree is the one a text with exactly these frequencies gets, ties and all.
+/// A character may have a weight of zero, which still gives it a code.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{encode_in_memory, optimal_tree};
+/// 
+/// let weights = [('e', 12), ('t', 9), ('a', 8), ('o', 8), (' ', 18), ('q', 1)];
+/// let input: String = weights
+///     .iter()
+///     .flat_map(|&(ch, weight)| std::iter::repeat_n(ch, weight))
+///     .collect();
+/// let (tree, _, _) = encode_in_memory(&input).unwrap();
+/// assert_eq!(optimal_tree(&weights).unwrap(), tree);
+/// 
+/// let codes = optimal_tree(&[('x', 0), ('y', 0), ('z', 5)]).unwrap().codes();
+/// assert_eq!(codes[0], ('z', "1".to_string()));
+/// 
+/// assert!(optimal_tree(&[]).is_err());
+/// assert!(optimal_tree(&[('a', 1), ('a', 2)]).is_err());
+/// assert!(optimal_tree(&[('a', usize::MAX), ('b', 1)]).is_err());
+/// ```
+pub fn optimal_tree(weights: &[(char, usize)]) -> Result<Node> {
+    if weights.is_empty() {
+        return Err(anyhow!("No weights to build a tree from."));
+    }
+    weights
+        .iter()
+        .try_fold(0usize, |total, &(_, weight)| total.checked_add(weight))
+        .ok_or_else(|| anyhow!("Weights are too large to add up."))?;
+
+    let mut counts = BTreeMap::new();
+    for &(ch, weight) in weights {
+        if counts.insert(ch, weight).is_some() {
+            return Err(anyhow!("Character {:?} has two weights.", ch));
+        }
+    }
+
+    Ok(tree_from_counts(counts))
+}
+
+/// Builds the canonical tree for a code designed elsewhere, given the length
+/// of each character's code. Codes are given out as in `canonical_codes()`.
+/// The lengths must fill the code space exactly, that is satisfy the Kraft
+/// equality, as any gap would be a code that decodes to nothing. A single
+/// character on its own takes a one bit code, as it does everywhere else.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::tree_from_code_lengths;
+/// 
+/// let lengths = [('a', 1), ('b', 2), ('c', 3), ('d', 4), ('e', 4)];
+/// let tree = tree_from_code_lengths(&lengths).unwrap();
+/// let codes = tree.codes();
+/// assert_eq!(codes.len(), lengths.len());
+/// for (ch, len) in lengths {
+///     let code = &codes.iter().find(|(code_ch, _)| *code_ch == ch).unwrap().1;
+///     assert_eq!(code.len(), len as usize);
+/// }
+/// assert_eq!(codes[0], ('a', "0".to_string()));
+/// assert_eq!(codes[4], ('e', "1111".to_string()));
+/// 
+/// assert_eq!(tree_from_code_lengths(&[('x', 1)]).unwrap().codes(), [('x', "0".to_string())]);
+/// 
+/// // Too short, leaving the code 11 unused, and too long
+/// assert!(tree_from_code_lengths(&[('a', 1), ('b', 2)]).is_err());
+/// assert!(tree_from_code_lengths(&[('a', 1), ('b', 1), ('c', 1)]).is_err());
+/// assert!(tree_from_code_lengths(&[('a', 1), ('a', 1)]).is_err());
+/// assert!(tree_from_code_lengths(&[]).is_err());
+/// ```
+pub fn tree_from_code_lengths(pairs: &[(char, u8)]) -> Result<Node> {
+    if let [(ch, 1)] = pairs {
+        return Ok(Node::new_leaf(*ch));
+    }
+
+    let mut seen = BTreeMap::new();
+    for &(ch, len) in pairs {
+        if len == 0 || len as usize > MAX_CANONICAL_LEN {
+            return Err(anyhow!("Code length of {:?} must be from 1 to {}.", ch, MAX_CANONICAL_LEN));
+        }
+        if seen.insert(ch, len).is_some() {
+            return Err(anyhow!("{:?} is given more than one code length.", ch));
+        }
+    }
+
+    let space: u128 = pairs
+        .iter()
+        .map(|&(_, len)| 1u128 << (MAX_CANONICAL_LEN - len as usize))
+        .sum();
+    if space != 1 << MAX_CANONICAL_LEN {
+        return Err(anyhow!("Code lengths do not satisfy the Kraft equality."));
+    }
+
+    tree_from_codes(&assign_canonical(seen.into_iter().map(|(ch, len)| (ch, len as usize)).collect()))
+}
+
+/// Describes how to turn the tree `base` into `new`, for sending a tree to a
+/// receiver that already holds a similar one. `tree_patch()` applies it.
+/// 
+/// Both trees are walked together in preorder. A `0` bit keeps the subtree of
+/// `base` as it is, `10` goes into the two subtrees of an internal node, and
+/// `11` replaces the subtree with the one that follows, serialised as in a
+/// stream. Texts with close frequencies, such as a document and a later
+/// version of it, often share many subtrees, which the diff leaves out. Any
+/// change in the frequencies can reshape the tree, though, so the saving
+/// varies.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{encode_in_memory, encode_to_bitstream, format_breakdown, tree_diff, tree_patch};
+/// 
+/// // A document, and the same document after some more was written
+/// let book = include_str!("../examples/book.txt");
+/// let (base, _, _) = encode_in_memory(&book[..20_000]).unwrap();
+/// let target = &book[..21_000];
+/// let (new, _, _) = encode_in_memory(target).unwrap();
+/// 
+/// let diff = tree_diff(&base, &new);
+/// assert_eq!(tree_patch(&base, &diff).unwrap(), new);
+/// assert!(diff.len() < format_breakdown(&encode_to_bitstream(target).unwrap()).unwrap().tree_bytes);
+/// 
+/// assert_eq!(tree_diff(&base, &base).len(), 1);
+/// assert_eq!(tree_patch(&base, &tree_diff(&base, &base)).unwrap(), base);
+/// ```
+pub fn tree_diff(base: &Node, new: &Node) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+
+    diff_node(base, new, &mut writer);
+
+    writer.finish().bytes
+}
+
+// Recursive helper writing the diff of one subtree
+fn diff_node(base: &Node, new: &Node, writer: &mut BitWriter) {
+    if base == new {
+        writer.write_bit(0);
+        return;
+    }
+
+    writer.write_bit(1);
+    match (base.left.as_ref().zip(base.right.as_ref()), new.left.as_ref().zip(new.right.as_ref())) {
+        (Some((base_left, base_right)), Some((new_left, new_right))) => {
+            writer.write_bit(0);
+            diff_node(base_left, new_left, writer);
+            diff_node(base_right, new_right, writer);
+        }
+        _ => {
+            writer.write_bit(1);
+            traverse_tree(new, writer, LeafFormat::Utf8);
+        }
+    }
+}
+
+/// Applies a diff from `tree_diff()` to `base`, giving back the new tree.
+pub fn tree_patch(base: &Node, diff: &[u8]) -> Result<Node> {
+    let mut bundle = BitBundle::new(diff);
+    patch_node(base, &mut bundle, 0)
+}
+
+// Recursive helper reading the diff of one subtree
+fn patch_node(base: &Node, bundle: &mut BitBundle, depth: usize) -> Result<Node> {
+    if depth > MAX_TREE_DEPTH {
+        return Err(anyhow!("Malformed tree diff."));
+    }
+
+    let truncated = || anyhow!("Truncated tree diff.");
+    if bundle.read_bit().ok_or_else(truncated)? == 0 {
+        return Ok(base.clone());
+    }
+
+    if bundle.read_bit().ok_or_else(truncated)? == 0 {
+        let (left, right) = base
+            .left
+            .as_ref()
+            .zip(base.right.as_ref())
+            .ok_or_else(|| anyhow!("Tree diff goes into a leaf."))?;
+        let left = patch_node(left, bundle, depth + 1)?;
+        let right = patch_node(right, bundle, depth + 1)?;
+        return Ok(Node::new_node(Box::new(left), Box::new(right)));
+    }
+
+    build_tree(bundle, depth, LeafFormat::Utf8).ok_or_else(|| anyhow!("Malformed tree diff."))
+}
+
+/// What a `TreeCursor` reached with a bit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepResult {
+    /// An internal node, so more bits are needed
+    Internal,
+    /// A leaf holding this character
+    Leaf(char),
+    /// The escape leaf of a `Codebook` tree, after which the caller reads the
+    /// literal UTF-8 bytes of the character
+    Escape,
+    /// No code goes this way
+    Invalid,
+}
+
+/// Walks a tree one bit at a time, for decoders that read their own bits,
+/// such as one mixing Huffman codes with other fields in a single bitstream.
+/// After a leaf, the escape leaf or an invalid bit the cursor goes back to the
+/// root by itself, ready for the next code.
+/// 
+/// A tree that is a single leaf codes each character as a single bit, so
+/// every step gives that leaf.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::bits::{BitBundle, BitRead};
+/// use codecs::huffman::{encode_in_memory, StepResult, TreeCursor};
+/// 
+/// let (tree, data, pad) = encode_in_memory("abracadabra").unwrap();
+/// let mut bundle = BitBundle::new(&data);
+/// let mut cursor = TreeCursor::new(&tree);
+/// let mut output = String::new();
+/// for _ in 0..(data.len() * 8 - pad as usize) {
+///     match cursor.step(bundle.read_bit().unwrap()) {
+///         StepResult::Leaf(ch) => output.push(ch),
+///         StepResult::Internal => {}
+///         other => panic!("unexpected {other:?}"),
+///     }
+/// }
+/// assert_eq!(output, "abracadabra");
+/// ```
+#[derive(Debug, Clone)]
+pub struct TreeCursor<'a> {
+    root: &'a Node,
+    node: &'a Node,
+}
+
+impl<'a> TreeCursor<'a> {
+    pub fn new(root: &'a Node) -> Self {
+        Self { root, node: root }
+    }
+
+    /// Follows the branch for `bit`, where zero goes left and anything else right.
+    pub fn step(&mut self, bit: u8) -> StepResult {
+        if let Some(ch) = self.root.ch {
+            return StepResult::Leaf(ch);
+        }
+
+        let next = if bit == 0 { &self.node.left } else { &self.node.right };
+        let Some(next) = next.as_deref() else {
+            self.reset();
+            return StepResult::Invalid;
+        };
+
+        if let Some(ch) = next.ch {
+            self.reset();
+            StepResult::Leaf(ch)
+        } else if next.is_escape() {
+            self.reset();
+            StepResult::Escape
+        } else {
+            self.node = next;
+            StepResult::Internal
+        }
+    }
+
+    /// Goes back to the root, dropping any bits of a partly read code.
+    pub fn reset(&mut self) {
+        self.node = self.root;
+    }
+}
+
+/// The encoding a text was in before it was compressed, as recorded in the
+/// stream flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceEncoding {
+    Utf8,
+    Utf16,
+    Latin1,
+}
+
+/// Reads which encoding the text of a stream came from, so the caller knows
+/// which decoder gives it back in its original form. Raw streams always hold
+/// UTF-8.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{encode_latin1, encode_to_bitstream, source_encoding, SourceEncoding};
+/// 
+/// let data = encode_latin1(b"Lorem ipsum dolor sit amet").unwrap();
+/// assert_eq!(source_encoding(&data).unwrap(), SourceEncoding::Latin1);
+/// assert_eq!(source_encoding(&encode_to_bitstream("abc").unwrap()).unwrap(), SourceEncoding::Utf8);
+/// assert_eq!(source_encoding(&encode_to_bitstream("zzzz").unwrap()).unwrap(), SourceEncoding::Utf8);
+/// ```
+pub fn source_encoding(input: &[u8]) -> Result<SourceEncoding> {
+    if untreed(input)?.is_some() {
+        return Ok(SourceEncoding::Utf8);
+    }
+
+    match split_sections(input)?.flags & ENCODING_MASK {
+        0 => Ok(SourceEncoding::Utf8),
+        ENCODING_UTF16 => Ok(SourceEncoding::Utf16),
+        ENCODING_LATIN1 => Ok(SourceEncoding::Latin1),
+        _ => Err(anyhow!("Unknown source encoding.")),
+    }
+}
+
+/// Encodes UTF-16 text. The tree is built over the characters the code units
+/// make up, so surrogate pairs become a single leaf, and unpaired surrogates
+/// are rejected. `decode_utf16()` gives back the code units.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{encode_utf16, decode_utf16, source_encoding, SourceEncoding};
+/// 
+/// let input: Vec<u16> = "Grüße, 世界 🎉".encode_utf16().collect();
+/// let data = encode_utf16(&input).unwrap();
+/// assert_eq!(source_encoding(&data).unwrap(), SourceEncoding::Utf16);
+/// assert_eq!(decode_utf16(&data).unwrap(), input);
+/// ```
+pub fn encode_utf16(input: &[u16]) -> Result<Vec<u8>> {
+    let text = String::from_utf16(input).map_err(|_| anyhow!("Input is not valid UTF-16."))?;
+
+    Encoder::new().encode_with_flags(&text, ENCODING_UTF16)
+}
+
+/// Decodes a stream produced by `encode_utf16()` back to UTF-16 code units.
+pub fn decode_utf16(input: &[u8]) -> Result<Vec<u16>> {
+    if source_encoding(input)? != SourceEncoding::Utf16 {
+        return Err(anyhow!("Stream was not encoded from UTF-16."));
+    }
+
+    Ok(decode_from_bitstream(input)?.encode_utf16().collect())
+}
+
+/// Encodes Latin-1 (ISO 8859-1) text. Each byte stands for the character with
+/// the same value, and `decode_latin1()` gives back the bytes.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{encode_latin1, decode_latin1};
+/// 
+/// // "Café crème" in Latin-1
+/// let input = b"Caf\xE9 cr\xE8me";
+/// let data = encode_latin1(input).unwrap();
+/// assert_eq!(decode_latin1(&data).unwrap(), input);
+/// ```
+pub fn encode_latin1(input: &[u8]) -> Result<Vec<u8>> {
+    Encoder::new().encode_with_flags(&bytes_to_latin1(input), ENCODING_LATIN1)
+}
+
+/// Decodes a stream produced by `encode_latin1()` back to Latin-1 bytes.
+pub fn decode_latin1(input: &[u8]) -> Result<Vec<u8>> {
+    if source_encoding(input)? != SourceEncoding::Latin1 {
+        return Err(anyhow!("Stream was not encoded from Latin-1."));
+    }
+
+    latin1_to_bytes(&decode_from_bitstream(input)?)
+}
+
+/// Encodes arbitrary bytes, building the tree over byte values. The stream is
+/// laid out as for `encode_latin1()`, but always stores the number of bytes,
+/// which `decode_bytes_from_bitstream()` needs.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{decode_bytes_from_bitstream, decode_from_bitstream, encode_bytes_to_bitstream, HmcFile};
+/// 
+/// // Six codes of one bit, so the last byte ends in two bits of padding that
+/// // would otherwise read as two more copies of the byte coded as `0`
+/// let input = [0x00, 0x00, 0x00, 0x00, 0x00, 0xFF];
+/// let data = encode_bytes_to_bitstream(&input).unwrap();
+/// let mut file = HmcFile::from_bytes(&data).unwrap();
+/// assert_eq!(file.pad, 2);
+/// assert_eq!(decode_bytes_from_bitstream(&data).unwrap(), input);
+/// 
+/// // Claiming there is no padding still gives back exactly six bytes
+/// file.pad = 0;
+/// let unpadded = file.to_bytes().unwrap();
+/// assert_eq!(decode_bytes_from_bitstream(&unpadded).unwrap(), input);
+/// assert!(decode_from_bitstream(&unpadded).is_err());
+/// ```
+pub fn encode_bytes_to_bitstream(input: &[u8]) -> Result<Vec<u8>> {
+    let stream = encode_latin1(input)?;
+    if split_sections(&stream)?.length.is_none() {
+        return Err(anyhow!("Input is too long to store its length."));
+    }
+
+    Ok(stream)
+}
+
+/// Decodes a stream produced by `encode_bytes_to_bitstream()`. Exactly as
+/// many bytes as the stream stores are read, and any bits after the last of
+/// them are taken as padding, whatever the packing byte says.
+pub fn decode_bytes_from_bitstream(input: &[u8]) -> Result<Vec<u8>> {
+    let sections = split_sections(input)?;
+    if sections.flags & ENCODING_MASK != ENCODING_LATIN1 {
+        return Err(anyhow!("Stream was not encoded from bytes."));
+    }
+    reject_runs(&sections)?;
+    let length = sections
+        .length
+        .ok_or_else(|| anyhow!("Stream has no stored length, so its bytes cannot be counted."))?;
+    let tree = read_tree(&sections)?;
+
+    // The padding is not trusted, so walk every bit and stop at the count
+    let mut output = Vec::with_capacity(length.min(sections.data.len() * 8));
+    let mut crc = Crc32::new();
+    let mut wide = false;
+    if length > 0 {
+        walk_payload_until(&tree, sections.data, 0, sections.order, |ch| {
+            match u8::try_from(ch).ok() {
+                Some(byte) => output.push(byte),
+                None => wide = true,
+            }
+            crc.update(ch.encode_utf8(&mut [0; 4]).as_bytes());
+            if wide || output.len() == length { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+        })?;
+    }
+    if wide {
+        return Err(anyhow!("Stream holds a character that is not a byte."));
+    }
+    if output.len() < length {
+        return Err(anyhow!("Truncated data."));
+    }
+
+    check_sections(&sections, output.len(), &crc)?;
+
+    Ok(output)
+}
+
+/// Encodes any input, as text when it is valid UTF-8 and as bytes otherwise.
+/// Text goes through `encode_to_bitstream()`, so its tree is built over whole
+/// characters, and anything else through `encode_bytes_to_bitstream()`, whose
+/// streams are marked as coming from bytes. `decode_auto()` reads that mark,
+/// so one pair of calls is safe for any data.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{decode_auto, encode_auto, source_encoding, SourceEncoding};
+/// 
+/// let text = "naïve café, 東京 🚆".as_bytes();
+/// let data = encode_auto(text).unwrap();
+/// assert_eq!(source_encoding(&data).unwrap(), SourceEncoding::Utf8);
+/// assert_eq!(decode_auto(&data).unwrap(), text);
+/// 
+/// let blob = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0xFF, 0xC0];
+/// let data = encode_auto(&blob).unwrap();
+/// assert_eq!(source_encoding(&data).unwrap(), SourceEncoding::Latin1);
+/// assert_eq!(decode_auto(&data).unwrap(), blob);
+/// 
+/// // Short text is stored raw, and still comes back
+/// assert_eq!(decode_auto(&encode_auto(b"hi").unwrap()).unwrap(), b"hi");
+/// ```
+pub fn encode_auto(input: &[u8]) -> Result<Vec<u8>> {
+    match std::str::from_utf8(input).ok() {
+        Some(text) => encode_to_bitstream(text),
+        None => encode_bytes_to_bitstream(input),
+    }
+}
+
+/// Decodes a stream produced by `encode_auto()` back to the original bytes.
+pub fn decode_auto(input: &[u8]) -> Result<Vec<u8>> {
+    if let Some(text) = raw_text(input)? {
+        return Ok(text.as_bytes().to_vec());
+    }
+
+    match source_encoding(input)? {
+        SourceEncoding::Utf8 => Ok(decode_from_bitstream(input)?.into_bytes()),
+        SourceEncoding::Latin1 => decode_bytes_from_bitstream(input),
+        SourceEncoding::Utf16 => Err(anyhow!("Stream was encoded from UTF-16, see `decode_utf16()`.")),
+    }
+}
+
+/// Decodes a stream produced by `encode_bytes_to_bitstream()` as UTF-8 text.
+/// Unlike a plain UTF-8 check, the error gives the byte offset of the first
+/// invalid sequence, or of a sequence cut short at the end.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{decode_checked, encode_bytes_to_bitstream};
+/// 
+/// let text = "Größe: 42 €";
+/// assert_eq!(decode_checked(&encode_bytes_to_bitstream(text.as_bytes()).unwrap()).unwrap(), text);
+/// 
+/// // A stray continuation byte after "Gr"
+/// let broken = encode_bytes_to_bitstream(b"Gr\x80sse").unwrap();
+/// let error = decode_checked(&broken).unwrap_err().to_string();
+/// assert!(error.contains("byte 2"), "{error}");
+/// 
+/// // The first two of the three bytes of '€'
+/// let cut = encode_bytes_to_bitstream(&"42 €".as_bytes()[..5]).unwrap();
+/// let error = decode_checked(&cut).unwrap_err().to_string();
+/// assert!(error.contains("byte 3"), "{error}");
+/// ```
+pub fn decode_checked(input: &[u8]) -> Result<String> {
+    String::from_utf8(decode_bytes_from_bitstream(input)?).map_err(|error| {
+        let offset = error.utf8_error().valid_up_to();
+        match error.utf8_error().error_len() {
+            Some(_) => anyhow!("Invalid UTF-8 sequence at byte {}.", offset),
+            None => anyhow!("Truncated UTF-8 sequence at byte {}.", offset),
+        }
+    })
+}
+
+/// Sizes of the parts of an encoded stream, as reported by `format_breakdown()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormatBreakdown {
+    /// Bytes taken by the flags, the tree length, the text length and checksum
+    /// if there are any, and the data packing byte
+    pub header_bytes: usize,
+    /// Bytes taken by the serialised tree
+    pub tree_bytes: usize,
+    /// Bytes taken by the encoded data
+    pub payload_bytes: usize,
+    /// Padding bits at the end of the encoded data
+    pub pad_bits: u8,
+    /// Bytes taken by the trailer, if there is one
+    pub trailer_bytes: usize,
+}
+
+/// Reports how much of an encoded stream is spent on the header, the tree and
+/// the encoded data, which helps when deciding whether a shared `Codebook`
+/// would be worth it. A raw stream has no tree, and everything after its
+/// flags counts as data.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{encode_to_bitstream, format_breakdown, FormatBreakdown};
+/// 
+/// let data = encode_to_bitstream("Lorem ipsum dolor sit amet").unwrap();
+/// let breakdown = format_breakdown(&data).unwrap();
+/// let parts = breakdown.header_bytes + breakdown.tree_bytes + breakdown.payload_bytes;
+/// assert_eq!(parts + breakdown.trailer_bytes, data.len());
+/// 
+/// // Short texts are stored raw, after two bytes of flags and their length
+/// let raw = format_breakdown(&encode_to_bitstream("abc").unwrap()).unwrap();
+/// let expected = FormatBreakdown { header_bytes: 3, tree_bytes: 0, payload_bytes: 3, pad_bits: 0, trailer_bytes: 0 };
+/// assert_eq!(raw, expected);
+/// 
+/// // One character repeated is the character and a count
+/// let single = format_breakdown(&encode_to_bitstream("zzzz").unwrap()).unwrap();
+/// assert_eq!((single.tree_bytes, single.payload_bytes), (0, 2));
+/// ```
+pub fn format_breakdown(input: &[u8]) -> Result<FormatBreakdown> {
+    if let Some(untreed) = untreed(input)? {
+        return Ok(FormatBreakdown {
+            header_bytes: untreed.header_len,
+            tree_bytes: 0,
+            payload_bytes: untreed.body.len(),
+            pad_bits: 0,
+            trailer_bytes: 0,
+        });
+    }
+
+    let sections = split_sections(input)?;
+
+    Ok(FormatBreakdown {
+        header_bytes: sections.header_len + 1,
+        tree_bytes: sections.tree.len(),
+        payload_bytes: sections.data.len(),
+        pad_bits: sections.pack,
+        trailer_bytes: input.len() - (sections.header_len + 1 + sections.tree.len() + sections.data.len()),
+    })
+}
+
+/// Returns the number of bits a stream wastes on padding: those filling out
+/// the last byte of the serialised tree, and those after the encoded data.
+/// Summed over many small blocks this shows how much splitting costs. Raw
+/// streams have neither, and canonical code tables are whole bytes.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{encode_to_bitstream, format_breakdown, padding_overhead, tree_serialized_bits};
+/// 
+/// let input = "Lorem ipsum dolor sit amet";
+/// let data = encode_to_bitstream(input).unwrap();
+/// let breakdown = format_breakdown(&data).unwrap();
+/// let tree_pad = breakdown.tree_bytes * 8 - tree_serialized_bits(input).unwrap();
+/// assert_eq!(padding_overhead(&data).unwrap(), tree_pad + breakdown.pad_bits as usize);
+/// assert!(padding_overhead(&data).unwrap() < 16);
+/// 
+/// assert_eq!(padding_overhead(&encode_to_bitstream("abc").unwrap()).unwrap(), 0);
+/// assert_eq!(padding_overhead(&encode_to_bitstream("zzzz").unwrap()).unwrap(), 0);
+/// ```
+pub fn padding_overhead(input: &[u8]) -> Result<usize> {
+    if untreed(input)?.is_some() {
+        return Ok(0);
+    }
+
+    let sections = split_sections(input)?;
+    let tree_pad = if sections.flags & FLAG_CANONICAL != 0 {
+        0
+    } else {
+        (sections.tree.len() * 8).saturating_sub(tree_bits(&read_tree(&sections)?, sections.leaves))
+    };
+
+    Ok(tree_pad + sections.pack as usize)
+}
+
+/// Returns the encoded data of a stream as its bits in groups of eight, one
+/// group for each byte, with the padding bits shown as `_`. Bits are listed
+/// in the order they are read, so for a stream filling bytes from the least
+/// significant bit the groups read as each byte back to front.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{decode_from_bitstream, encode_to_bitstream, payload_debug};
+/// 
+/// // No flags, a tree length of 3, the tree with 'a' as 0 and 'b' as 1, five
+/// // bits of padding and the data
+/// let data = [0x00, 0x03, 0x58, 0x6C, 0x40, 5, 0b0110_0101, 0b1010_0000];
+/// assert_eq!(decode_from_bitstream(&data).unwrap(), "abbaababbab");
+/// assert_eq!(payload_debug(&data).unwrap(), "01100101 101_____");
+/// 
+/// // Streams with no tree have no encoded data to show
+/// let error = payload_debug(&encode_to_bitstream("abc").unwrap()).unwrap_err();
+/// assert_eq!(error.to_string(), "Stream holds raw text with no encoded data.");
+/// let error = payload_debug(&encode_to_bitstream("zzzz").unwrap()).unwrap_err();
+/// assert_eq!(error.to_string(), "Stream holds a single repeated character with no encoded data.");
+/// ```
+pub fn payload_debug(input: &[u8]) -> Result<String> {
+    if single_symbol(input)?.is_some() {
+        return Err(anyhow!("Stream holds a single repeated character with no encoded data."));
+    }
+    if untreed(input)?.is_some() {
+        return Err(anyhow!("Stream holds raw text with no encoded data."));
+    }
+
+    let sections = split_sections(input)?;
+    let total_bits = (sections.data.len() * 8)
+        .checked_sub(sections.pack as usize)
+        .ok_or_else(|| anyhow!("Padding exceeds data length."))?;
+
+    let mut bundle = BitBundle::with_order(sections.data, sections.order);
+    let mut output = String::with_capacity(sections.data.len() * 9);
+    for idx in 0..(sections.data.len() * 8) {
+        if idx > 0 && idx % 8 == 0 {
+            output.push(' ');
+        }
+        let bit = bundle.read_bit().unwrap();
+        output.push(if idx >= total_bits { '_' } else if bit == 0 { '0' } else { '1' });
+    }
+
+    Ok(output)
+}
+
+/// Code statistics of an encoded stream, as reported by `stream_stats()`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StreamStats {
+    /// Number of distinct characters in the tree, not counting an escape
+    pub symbol_count: usize,
+    /// Number of symbols coded in the data, if the stream stores it
+    pub total_symbols: Option<usize>,
+    /// Bits of encoded data, not counting the padding
+    pub payload_bits: usize,
+    /// Average bits spent on each coded symbol, if the number of symbols is stored
+    pub avg_bits_per_symbol: Option<f64>,
+}
+
+/// Reports code statistics of a stream from its header, tree and data length
+/// alone, without decoding the text, for analytics over stored files. The
+/// number of symbols is read from the stored length, so streams written
+/// without integrity data have none. In a stream collapsing runs of
+/// whitespace it counts each run as one symbol. A raw stream reports the
+/// bits of its text as the payload, and a single character stream one
+/// symbol with no payload at all.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{easy_encode, encode_to_bitstream, stream_stats, Encoder};
+/// use std::collections::HashSet;
+/// 
+/// let input = "Lorem ipsum dolor sit amet, consectetur adipiscing elit";
+/// let stats = stream_stats(&encode_to_bitstream(input).unwrap()).unwrap();
+/// let (_, bits) = easy_encode(input).unwrap();
+/// assert_eq!(stats.symbol_count, input.chars().collect::<HashSet<_>>().len());
+/// assert_eq!(stats.total_symbols, Some(input.chars().count()));
+/// assert_eq!(stats.payload_bits, bits.len());
+/// assert_eq!(stats.avg_bits_per_symbol, Some(bits.len() as f64 / input.chars().count() as f64));
+/// 
+/// let fast = Encoder::new().integrity(false).encode(input).unwrap();
+/// assert_eq!(stream_stats(&fast).unwrap().avg_bits_per_symbol, None);
+/// 
+/// let raw = stream_stats(&encode_to_bitstream("abca").unwrap()).unwrap();
+/// assert_eq!((raw.symbol_count, raw.total_symbols, raw.payload_bits), (3, Some(4), 32));
+/// assert_eq!(raw.avg_bits_per_symbol, Some(8.0));
+/// 
+/// let single = stream_stats(&encode_to_bitstream("zzzz").unwrap()).unwrap();
+/// assert_eq!((single.symbol_count, single.total_symbols, single.payload_bits), (1, Some(4), 0));
+/// assert_eq!(single.avg_bits_per_symbol, Some(0.0));
+/// ```
+pub fn stream_stats(input: &[u8]) -> Result<StreamStats> {
+    if let Some((_, count)) = single_symbol(input)? {
+        return Ok(StreamStats {
+            symbol_count: 1,
+            total_symbols: Some(count),
+            payload_bits: 0,
+            avg_bits_per_symbol: Some(0.0),
+        });
+    }
+    if let Some(untreed) = untreed(input)? {
+        let text = std::str::from_utf8(untreed.body)?;
+        let payload_bits = text.len() * 8;
+        return Ok(StreamStats {
+            symbol_count: text.chars().collect::<HashSet<_>>().len(),
+            total_symbols: untreed.length,
+            payload_bits,
+            avg_bits_per_symbol: untreed
+                .length
+                .filter(|&length| length > 0)
+                .map(|length| payload_bits as f64 / length as f64),
+        });
+    }
+
+    let sections = split_sections(input)?;
+    let tree = read_tree(&sections)?;
+    let payload_bits = (sections.data.len() * 8)
+        .checked_sub(sections.pack as usize)
+        .ok_or_else(|| anyhow!("Padding exceeds data length."))?;
+
+    Ok(StreamStats {
+        symbol_count: assign_codes(&tree).len(),
+        total_symbols: sections.length,
+        payload_bits,
+        avg_bits_per_symbol: sections
+            .length
+            .filter(|&length| length > 0)
+            .map(|length| payload_bits as f64 / length as f64),
+    })
+}
+
+/// An encoded stream split into its fields, for reading or adjusting them
+/// without working through the byte layout by hand. With the `serde` feature
+/// it can be serialised in any format serde supports.
+/// 
+/// The stream itself has no version number, instead the flags at the start
+/// say which fields follow and how they are sized. `version` names the layout
+/// of those flags, so that serialised files can be told apart should it ever
+/// change. It is always `HmcFile::VERSION`, and `to_bytes()` refuses any
+/// other. A raw stream has no tree, length or checksum, and its text is the
+/// payload, as are the character and count of a single character stream.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{encode_to_bitstream, HmcFile};
+/// 
+/// let data = encode_to_bitstream("Lorem ipsum dolor sit amet").unwrap();
+/// let mut file = HmcFile::from_bytes(&data).unwrap();
+/// assert_eq!(file.length, Some(26));
+/// assert_eq!(file.version, HmcFile::VERSION);
+/// assert_eq!(file.to_bytes().unwrap(), data);
+/// 
+/// file.version = HmcFile::VERSION + 1;
+/// assert!(file.to_bytes().is_err());
+/// 
+/// let raw = encode_to_bitstream("abc").unwrap();
+/// let file = HmcFile::from_bytes(&raw).unwrap();
+/// assert!(file.tree.is_empty());
+/// assert_eq!((file.length, file.payload.as_slice()), (Some(3), &b"abc"[..]));
+/// assert_eq!(file.to_bytes().unwrap(), raw);
+/// 
+/// let single = encode_to_bitstream("zzzz").unwrap();
+/// let file = HmcFile::from_bytes(&single).unwrap();
+/// assert_eq!(file.payload, [b'z', 4]);
+/// assert_eq!(file.to_bytes().unwrap(), single);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HmcFile {
+    /// Layout of the flags
+    pub version: u8,
+    /// Flags, giving the width of the tree length, the source encoding and
+    /// the bit order. The length, checksum and trailer bits follow the fields
+    /// below when written.
+    pub flags: usize,
+    /// Number of characters in the text
+    pub length: Option<usize>,
+    /// CRC-32 of the text
+    pub checksum: Option<u32>,
+    /// Serialised tree
+    pub tree: Vec<u8>,
+    /// Number of data packing bits
+    pub pad: u8,
+    /// Encoded data
+    pub payload: Vec<u8>,
+    /// File details from the trailer
+    pub meta: Option<HmcMeta>,
+}
+
+impl HmcFile {
+    /// The layout of the flags this crate reads and writes.
+    pub const VERSION: u8 = 1;
+
+    /// Splits an encoded stream into its fields.
+    pub fn from_bytes(input: &[u8]) -> Result<Self> {
+        if let Some(untreed) = untreed(input)? {
+            return Ok(Self {
+                version: Self::VERSION,
+                flags: untreed.flags,
+                length: untreed.length,
+                checksum: None,
+                tree: Vec::new(),
+                pad: 0,
+                payload: untreed.body.to_vec(),
+                meta: None,
+            });
+        }
+
+        let sections = split_sections(input)?;
+
+        Ok(Self {
+            version: Self::VERSION,
+            flags: sections.flags,
+            length: sections.length,
+            checksum: sections.checksum,
+            tree: sections.tree.to_vec(),
+            pad: sections.pack,
+            payload: sections.data.to_vec(),
+            meta: sections.meta,
+        })
+    }
+
+    /// Puts the fields back together as an encoded stream.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        if self.version != Self::VERSION {
+            return Err(anyhow!("Unknown flags layout version {}.", self.version));
+        }
+        // Nothing but the length of a raw text and the payload follow the
+        // flags of a stream with no tree
+        if self.flags & (FLAG_RAW | FLAG_SINGLE) != 0 {
+            let length = self.length.filter(|_| self.flags & FLAG_RAW != 0);
+            let mut glob = uint_to_vwe(self.flags & !FLAG_LENGTH | length.map_or(0, |_| FLAG_LENGTH))?;
+            if let Some(length) = length {
+                glob.extend(uint_to_vwe(length)?);
+            }
+            glob.extend_from_slice(&self.payload);
+            return Ok(glob);
+        }
+
+        let mut flags = self.flags & !(FLAG_LENGTH | FLAG_CHECKSUM | FLAG_TRAILER);
+        if self.length.is_some() {
+            flags |= FLAG_LENGTH;
+        }
+        if self.checksum.is_some() {
+            flags |= FLAG_CHECKSUM;
+        }
+        if self.meta.is_some() {
+            flags |= FLAG_TRAILER;
+        }
+
+        let mut glob = uint_to_vwe(flags)?;
+        glob.extend(write_tree_len(self.tree.len(), flags & WIDTH_MASK)?);
+        if let Some(length) = self.length {
+            glob.extend(uint_to_vwe(length)?);
+        }
+        if let Some(checksum) = self.checksum {
+            glob.extend_from_slice(&checksum.to_be_bytes());
+        }
+        glob.extend_from_slice(&self.tree);
+        glob.push(self.pad);
+        glob.extend_from_slice(&self.payload);
+        if let Some(meta) = &self.meta {
+            glob.extend(write_trailer(meta)?);
+        }
+
+        Ok(glob)
+    }
+}
+
+/// Details of the file a stream was compressed from, kept in a trailer after
+/// the encoded data much like gzip keeps them in its header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HmcMeta {
+    /// Name of the file, without its directory
+    pub filename: String,
+    /// Modification time in seconds since the Unix epoch
+    pub mtime: u64,
+}
+
+/// Reads the file details from a stream's trailer, or `None` if it has none.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{encode_to_bitstream, decode_from_bitstream, read_metadata, Encoder, HmcMeta};
+/// 
+/// let meta = HmcMeta { filename: String::from("notes.txt"), mtime: 1_700_000_000 };
+/// let data = Encoder::new().metadata(meta.clone()).encode("Lorem ipsum").unwrap();
+/// assert_eq!(read_metadata(&data).unwrap(), Some(meta));
+/// assert_eq!(decode_from_bitstream(&data).unwrap(), "Lorem ipsum");
+/// 
+/// let plain = encode_to_bitstream("Lorem ipsum").unwrap();
+/// assert_eq!(read_metadata(&plain).unwrap(), None);
+/// 
+/// // Raw streams never carry a trailer
+/// assert_eq!(read_metadata(&encode_to_bitstream("abc").unwrap()).unwrap(), None);
+/// assert_eq!(read_metadata(&encode_to_bitstream("zzzz").unwrap()).unwrap(), None);
+/// ```
+pub fn read_metadata(input: &[u8]) -> Result<Option<HmcMeta>> {
+    if untreed(input)?.is_some() {
+        return Ok(None);
+    }
+
+    Ok(split_sections(input)?.meta)
+}
+
+/// Compresses a text file, storing its name and modification time in the
+/// trailer so that `decompress_file()` can restore them.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{compress_file, decompress_file, read_metadata};
+/// use std::fs;
+/// 
+/// # fn main() -> anyhow::Result<()> {
+/// let dir = std::env::temp_dir().join("codecs_compress_file");
+/// fs::create_dir_all(&dir)?;
+/// let path = dir.join("iliad.txt");
+/// fs::write(&path, "Sing, O goddess, the anger of Achilles son of Peleus")?;
+/// 
+/// let data = compress_file(&path)?;
+/// assert_eq!(read_metadata(&data)?.unwrap().filename, "iliad.txt");
+/// 
+/// let out = dir.join("out");
+/// fs::create_dir_all(&out)?;
+/// let restored = decompress_file(&data, &out)?;
+/// assert_eq!(restored, out.join("iliad.txt"));
+/// assert_eq!(fs::read_to_string(&restored)?, fs::read_to_string(&path)?);
+/// # Ok(())
+/// # }
+/// ```
+pub fn compress_file(path: impl AsRef<Path>) -> Result<Vec<u8>> {
+    let path = path.as_ref();
+    let input = fs::read_to_string(path)?;
+    let filename = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| anyhow!("Path has no usable file name."))?
+        .to_string();
+    // Files without a usable modification time get zero, as gzip does
+    let mtime = fs::metadata(path)?
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |time| time.as_secs());
+
+    Encoder::new().metadata(HmcMeta { filename, mtime }).encode(&input)
+}
+
+/// Decodes a stream into a file in `dir`, named after the file it was
+/// compressed from, and returns its path. Only the last part of the stored
+/// name is used, so a stream cannot write outside `dir`.
+pub fn decompress_file(input: &[u8], dir: impl AsRef<Path>) -> Result<PathBuf> {
+    let meta = read_metadata(input)?.ok_or_else(|| anyhow!("Stream has no file name."))?;
+    let filename = Path::new(&meta.filename)
+        .file_name()
+        .ok_or_else(|| anyhow!("Stored file name is not usable."))?;
+
+    let path = dir.as_ref().join(filename);
+    fs::write(&path, decode_from_bitstream(input)?)?;
+    let mtime = UNIX_EPOCH + Duration::from_secs(meta.mtime);
+    fs::File::options().write(true).open(&path)?.set_modified(mtime)?;
+
+    Ok(path)
+}
+
+/// How close the Huffman codes for a text come to its entropy, as reported by
+/// `entropy_analysis()`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EntropyReport {
+    /// Shannon entropy of the character frequencies, the lower bound for any
+    /// code that works one character at a time
+    pub entropy_bits_per_symbol: f64,
+    /// Average length of the Huffman codes, weighted by frequency
+    pub huffman_bits_per_symbol: f64,
+    /// Bits per character spent above the entropy
+    pub redundancy: f64,
+    /// Number of characters in the text
+    pub total_symbols: usize,
+}
+
+/// Compares the average Huffman code length for a text against its entropy.
+/// A redundancy near zero means Huffman is as good as any prefix code can be,
+/// and a large one suggests arithmetic coding would do better.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::entropy_analysis;
+/// 
+/// // Frequencies of 1/2, 1/4, 1/8 and 1/8 fit whole bit codes exactly
+/// let report = entropy_analysis("aaaabbcd").unwrap();
+/// assert_eq!(report.total_symbols, 8);
+/// assert!((report.entropy_bits_per_symbol - 1.75).abs() < 1e-9);
+/// assert!(report.redundancy.abs() < 1e-9);
+/// ```
+pub fn entropy_analysis(input: &str) -> Result<EntropyReport> {
+    if input.is_empty() {
+        return Err(anyhow!("Input string is empty."));
+    }
+
+    let counts = count_chars(input);
+    let codes = assign_codes(&tree_from_counts(counts.clone()));
+    let total: usize = counts.values().sum();
+
+    let entropy = shannon_entropy(counts.values().copied(), total);
+    let bits: usize = counts.iter().map(|(ch, &count)| count * codes[ch].len()).sum();
+    let huffman = bits as f64 / total as f64;
+
+    Ok(EntropyReport {
+        entropy_bits_per_symbol: entropy,
+        huffman_bits_per_symbol: huffman,
+        redundancy: huffman - entropy,
+        total_symbols: total,
+    })
+}
+
+// Shannon entropy in bits per symbol of symbols seen `counts` times, out of `total`
+fn shannon_entropy(counts: impl Iterator<Item = usize>, total: usize) -> f64 {
+    counts
+        .filter(|&count| count > 0)
+        .map(|count| {
+            let p = count as f64 / total as f64;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Returns the entropy in bits per character of every run of `window`
+/// characters in the text, one for each starting character, so that the parts
+/// that would compress well or badly can be plotted. Low values mark
+/// repetitive regions and high values varied ones.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::region_entropy;
+/// 
+/// let input = format!("{}{}", "a".repeat(100), "the quick brown fox jumps over the lazy dog");
+/// let entropy = region_entropy(&input, 32).unwrap();
+/// assert_eq!(entropy.len(), input.chars().count() - 32 + 1);
+/// assert!(entropy[0].abs() < 1e-9);
+/// assert!(*entropy.last().unwrap() > 3.0);
+/// 
+/// assert!(region_entropy("abc", 4).is_err());
+/// assert!(region_entropy("abc", 0).is_err());
+/// ```
+pub fn region_entropy(input: &str, window: usize) -> Result<Vec<f64>> {
+    if window == 0 {
+        return Err(anyhow!("Window must hold at least one character."));
+    }
+    let chars: Vec<char> = input.chars().collect();
+    if chars.len() < window {
+        return Err(anyhow!("Window is longer than the text."));
+    }
+
+    // Slide the window a character at a time, keeping its counts up to date
+    let mut counts: HashMap<char, usize> = HashMap::new();
+    for &ch in &chars[..window] {
+        *counts.entry(ch).or_insert(0) += 1;
+    }
+    let mut entropy = Vec::with_capacity(chars.len() - window + 1);
+    entropy.push(shannon_entropy(counts.values().copied(), window));
+    for (&gone, &ch) in chars.iter().zip(&chars[window..]) {
+        *counts.get_mut(&gone).unwrap() -= 1;
+        *counts.entry(ch).or_insert(0) += 1;
+        entropy.push(shannon_entropy(counts.values().copied(), window));
+    }
+
+    Ok(entropy)
+}
+
+/// Returns the average Huffman code length for a text and the width a fixed
+/// width code would need for its alphabet, both in bits per character. The
+/// fixed width is the number of bits needed to number every distinct
+/// character, and at least one.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::avg_code_length;
+/// 
+/// for input in ["ab", "abracadabra", "Lorem ipsum dolor sit amet", "aaaaaaaab"] {
+///     let (huffman, fixed) = avg_code_length(input).unwrap();
+///     assert!(huffman <= fixed);
+/// }
+/// assert_eq!(avg_code_length("abracadabra").unwrap().1, 3.0);
+/// ```
+pub fn avg_code_length(input: &str) -> Result<(f64, f64)> {
+    let report = entropy_analysis(input)?;
+    let alphabet = count_chars(input).len();
+    let fixed = (alphabet as f64).log2().ceil().max(1.0);
+
+    Ok((report.huffman_bits_per_symbol, fixed))
+}
+
+/// Returns the total number of bits each character contributes to the
+/// encoded data, its frequency times its code length, with the most costly
+/// first. This shows which characters dominate the compressed size, and so
+/// whether normalising whitespace or case before encoding would pay off.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{encode_to_bitstream, format_breakdown, symbol_cost};
+/// 
+/// let input = "The  quick   brown fox jumps over  the lazy dog";
+/// let costs = symbol_cost(input).unwrap();
+/// assert_eq!(costs[0].0, ' ');
+/// 
+/// let breakdown = format_breakdown(&encode_to_bitstream(input).unwrap()).unwrap();
+/// let total: usize = costs.iter().map(|(_, bits)| bits).sum();
+/// assert_eq!(total, breakdown.payload_bytes * 8 - breakdown.pad_bits as usize);
+/// ```
+pub fn symbol_cost(input: &str) -> Result<Vec<(char, usize)>> {
+    if input.is_empty() {
+        return Err(anyhow!("Input string is empty."));
+    }
+
+    let counts = count_chars(input);
+    let codes = assign_codes(&tree_from_counts(counts.clone()));
+    let mut costs: Vec<(char, usize)> = counts
+        .into_iter()
+        .map(|(ch, count)| (ch, count * codes[&ch].len()))
+        .collect();
+    // Stable sort, so equal costs stay in codepoint order
+    costs.sort_by_key(|&(_, bits)| std::cmp::Reverse(bits));
+
+    Ok(costs)
+}
+
+/// Returns how many characters of a text get a code of each length from 0 to
+/// 15 bits, the counts DEFLATE needs to build the codes of a dynamic block.
+/// No character gets a code of length 0, which DEFLATE keeps for symbols that
+/// do not appear. Fails if any code is longer than the 15 bits DEFLATE allows.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::code_length_histogram;
+/// 
+/// let input = "Lorem ipsum dolor sit amet, consectetur adipiscing elit";
+/// let histogram = code_length_histogram(input).unwrap();
+/// let alphabet = input.chars().collect::<std::collections::BTreeSet<_>>().len();
+/// assert_eq!(histogram.iter().sum::<usize>(), alphabet);
+/// assert_eq!(histogram[0], 0);
+/// 
+/// // Fibonacci frequencies over 18 letters need a 17 bit code
+/// let mut fib = (1, 1);
+/// let mut text = String::new();
+/// for ch in 'a'..='r' {
+///     text.extend(std::iter::repeat_n(ch, fib.0));
+///     fib = (fib.1, fib.0 + fib.1);
+/// }
+/// assert!(code_length_histogram(&text).is_err());
+/// ```
+pub fn code_length_histogram(input: &str) -> Result<[usize; 16]> {
+    if input.is_empty() {
+        return Err(anyhow!("Input string is empty."));
+    }
+
+    let mut histogram = [0; 16];
+    for (ch, code) in assign_codes(&gen_tree(input)) {
+        let count = histogram
+            .get_mut(code.len())
+            .ok_or_else(|| anyhow!("Code for {:?} is {} bits, more than 15.", ch, code.len()))?;
+        *count += 1;
+    }
+
+    Ok(histogram)
+}
+
+/// Same as `encode_to_bitstream()`, but a leading byte order mark (U+FEFF) is
+/// removed before encoding. Whether one was removed is recorded in the stream
+/// flags, so that `decode_from_bitstream_restore_bom()` can put it back from
+/// the stream alone, and is also returned. A text that had one always gets a
+/// tree, as raw streams have no room for the flag.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{encode_to_bitstream_strip_bom, decode_from_bitstream, decode_from_bitstream_restore_bom};
+/// 
+/// let input = "\u{FEFF}Hello, World!";
+/// let (data, had_bom) = encode_to_bitstream_strip_bom(input).unwrap();
+/// assert!(had_bom);
+/// assert_eq!(decode_from_bitstream(&data).unwrap(), "Hello, World!");
+/// assert_eq!(decode_from_bitstream_restore_bom(&data).unwrap(), input);
+/// 
+/// for input in ["\u{FEFF}abc", "No mark here", "abc"] {
+///     let (data, _) = encode_to_bitstream_strip_bom(input).unwrap();
+///     assert_eq!(decode_from_bitstream_restore_bom(&data).unwrap(), input);
+/// }
+/// ```
+pub fn encode_to_bitstream_strip_bom(input: &str) -> Result<(Vec<u8>, bool)> {
+    let encoder = Encoder::new().raw_below(RAW_BELOW);
+    match input.strip_prefix('\u{FEFF}') {
+        Some(stripped) => Ok((encoder.encode_text(stripped, FLAG_BOM)?, true)),
+        None => Ok((encoder.encode(input)?, false)),
+    }
+}
+
+/// Decodes a stream from `encode_to_bitstream_strip_bom()`, prepending the byte
+/// order mark again if the stream records that one was removed.
+pub fn decode_from_bitstream_restore_bom(input: &[u8]) -> Result<String> {
+    let (flags, _) = vwe_to_uint(input)?;
+    let output = decode_from_bitstream(input)?;
+
+    if flags & FLAG_BOM != 0 {
+        Ok(format!("\u{FEFF}{output}"))
+    } else {
+        Ok(output)
+    }
+}
+
+/// Concatenates several independently encoded streams into one buffer. Each
+/// stream is prefixed with its length as a variable width number, so the
+/// receiver can split the buffer back into individual messages with
+/// `deframe()`. Fails if a stream is longer than 268,435,455 bytes, which is
+/// the largest length a variable width number can hold.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{encode_to_bitstream, frame, deframe};
+/// 
+/// let streams = vec![
+///     encode_to_bitstream("The quick brown fox").unwrap(),
+///     encode_to_bitstream("jumps over").unwrap(),
+///     encode_to_bitstream("the lazy dog.").unwrap(),
+/// ];
+/// let data = frame(&streams).unwrap();
+/// assert_eq!(deframe(&data).unwrap(), streams);
+/// ```
+pub fn frame(streams: &[Vec<u8>]) -> Result<Vec<u8>> {
+    let mut glob = Vec::new();
+
+    for stream in streams {
+        glob.extend(uint_to_vwe(stream.len()).map_err(|_| anyhow!("Stream is too large to frame."))?);
+        glob.extend_from_slice(stream);
+    }
+
+    Ok(glob)
+}
+
+/// Splits a buffer produced by `frame()` back into the individual streams.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{decode_from_bitstream, deframe, encode_to_bitstream, frame};
+/// 
+/// let texts = ["Hello, World!", "abc", "zzzz"];
+/// let streams: Vec<Vec<u8>> = texts.iter().map(|text| encode_to_bitstream(text).unwrap()).collect();
+/// let data = frame(&streams).unwrap();
+/// let decoded: Vec<String> = deframe(&data)
+///     .unwrap()
+///     .iter()
+///     .map(|stream| decode_from_bitstream(stream).unwrap())
+///     .collect();
+/// assert_eq!(decoded, texts);
+/// 
+/// // A frame claiming more bytes than there are
+/// assert!(deframe(&[5, 1, 2]).is_err());
+/// ```
+pub fn deframe(data: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut streams = Vec::new();
+    let mut idx = 0;
+
+    while idx < data.len() {
+        let (len, width) = vwe_to_uint(&data[idx..])?;
+        idx += width;
+        if data.len() - idx < len {
+            return Err(anyhow!("Frame length exceeds available data."));
+        }
+        streams.push(data[idx..(idx + len)].to_vec());
+        idx += len;
+    }
+
+    Ok(streams)
+}
+
+/// A codebook built once from a sample text, so that many messages can be
+/// encoded and decoded without sending the tree along with each of them. Both
+/// sides need to build the codebook from the same sample.
+/// 
+/// Encoded messages are a single byte giving the number of data packing bits,
+/// followed by the data. The codebook also reserves an escape code, so that
+/// characters missing from the sample can still be sent literally with
+/// `encode_with_escape()`.
+/// 
+/// A built codebook is never changed by encoding or decoding, and is `Send`
+/// and `Sync`, so one can be shared through an `Arc` by many threads at once.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::Codebook;
+/// use std::sync::Arc;
+/// use std::thread;
+/// 
+/// let codebook = Arc::new(Codebook::new("the quick brown fox jumps over the lazy dog").unwrap());
+/// let handles: Vec<_> = ["the fox", "a lazy dog", "quick brown"]
+///     .into_iter()
+///     .map(|message| {
+///         let codebook = Arc::clone(&codebook);
+///         thread::spawn(move || (message, codebook.encode(message).unwrap()))
+///     })
+///     .collect();
+/// 
+/// for handle in handles {
+///     let (message, data) = handle.join().unwrap();
+///     assert_eq!(codebook.decode(&data).unwrap(), message);
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Codebook {
+    freqs: BTreeMap<char, usize>,
+    tree: Node,
+    codes: HashMap<char, String>,
+    escape: String,
+}
+
+// Fails to compile if a field ever stops the codebook being shared between threads
+const _: () = {
+    const fn shareable<T: Send + Sync>() {}
+    shareable::<Codebook>();
+};
+
+impl Codebook {
+    /// Builds a codebook from the character frequencies of `sample`.
+    pub fn new(sample: &str) -> Result<Self> {
+        if sample.is_empty() {
+            return Err(anyhow!("Sample string is empty."));
+        }
+
+        Ok(Self::from_counts(count_chars(sample)))
+    }
+
+    fn from_counts(freqs: BTreeMap<char, usize>) -> Self {
+        let mut leaves: Vec<Branch> = freqs
+            .iter()
+            .map(|(&ch, &freq)| Branch::new(Box::new(Node::new_leaf(ch)), freq))
+            .collect();
+        // The escape symbol is expected to be rare, so it gets the longest code
+        leaves.push(Branch::new(Box::new(Node::new_escape()), 0));
+        let tree = build_huffman(leaves);
+        let codes = assign_codes(&tree);
+        let escape = escape_code(&tree, String::new()).unwrap();
+
+        Self { freqs, tree, codes, escape }
+    }
+
+    /// Adds the character frequencies of `sample` to those the codebook was
+    /// built from, and rebuilds the tree. Returns whether any code changed, in
+    /// which case the decoder needs the same update before it can read new
+    /// messages.
+    /// 
+    /// ## Example
+    /// 
+    /// 
+    /// ```
+    /// use codecs::huffman::Codebook;
+    /// 
+    /// let mut codebook = Codebook::new("aaaaaaaabbbbcc").unwrap();
+    /// assert!(!codebook.update_from("aaaaaaaabbbbcc"));
+    /// assert!(codebook.update_from("cccccccccccccccccccccccccccc"));
+    /// let data = codebook.encode("abc").unwrap();
+    /// assert_eq!(codebook.decode(&data).unwrap(), "abc");
+    /// ```
+    pub fn update_from(&mut self, sample: &str) -> bool {
+        let mut freqs = std::mem::take(&mut self.freqs);
+        for (ch, freq) in count_chars(sample) {
+            *freqs.entry(ch).or_insert(0) += freq;
+        }
+
+        let updated = Self::from_counts(freqs);
+        let changed = updated.codes != self.codes || updated.escape != self.escape;
+        *self = updated;
+
+        changed
+    }
+
+    /// Encodes a message, failing if it contains a character that is not in
+    /// the codebook.
+    pub fn encode(&self, input: &str) -> Result<Vec<u8>> {
+        let mut writer = BitWriter::new();
+
+        for ch in input.chars() {
+            let code = self.codes
+                .get(&ch)
+                .ok_or_else(|| anyhow!("Character {:?} is not in the codebook.", ch))?;
+            writer.write_code(code);
+        }
+
+        Ok(self.package(writer.finish()))
+    }
+
+    /// Whether every character of `input` has a code, so that `encode()`
+    /// will not fail on it.
+    pub fn covers(&self, input: &str) -> bool {
+        input.chars().all(|ch| self.codes.contains_key(&ch))
+    }
+
+    /// The characters of `input` that have no code, each listed once in the
+    /// order they first appear.
+    /// 
+    /// ## Example
+    /// 
+    /// 
+    /// ```
+    /// use codecs::huffman::Codebook;
+    /// 
+    /// let codebook = Codebook::new("the quick brown fox").unwrap();
+    /// assert!(codebook.covers("the fox"));
+    /// assert!(!codebook.covers("the lazy dog"));
+    /// assert_eq!(codebook.missing_symbols("the lazy dog"), vec!['l', 'a', 'z', 'y', 'd', 'g']);
+    /// assert!(codebook.missing_symbols("the fox").is_empty());
+    /// ```
+    pub fn missing_symbols(&self, input: &str) -> Vec<char> {
+        let mut missing = Vec::new();
+        for ch in input.chars() {
+            if !self.codes.contains_key(&ch) && !missing.contains(&ch) {
+                missing.push(ch);
+            }
+        }
+
+        missing
+    }
+
+    /// Writes the codes as Rust source, for firmware that decodes with a fixed
+    /// table and never builds a tree. `name` is given a `const` array of each
+    /// character with its code as bits and its length in bits, shortest code
+    /// first, and `name` with `_ESCAPE` the escape code. The output can be
+    /// written to a file and pulled in with `include!`. Codes are taken to fit
+    /// in 64 bits, which they do for any sample that fits in memory.
+    /// 
+    /// ## Example
+    /// 
+    /// 
+    /// ```
+    /// use codecs::huffman::Codebook;
+    /// use std::process::Command;
+    /// 
+    /// # fn main() -> anyhow::Result<()> {
+    /// let codebook = Codebook::new("abracadabra")?;
+    /// let source = codebook.to_rust_const("CODES");
+    /// assert_eq!(source, "\
+    /// pub const CODES: [(char, u64, u8); 5] = [
+    ///     ('a', 0b0, 1),
+    ///     ('b', 0b111, 3),
+    ///     ('d', 0b100, 3),
+    ///     ('r', 0b110, 3),
+    ///     ('c', 0b1011, 4),
+    /// ];
+    /// pub const CODES_ESCAPE: (u64, u8) = (0b1010, 4);
+    /// ");
+    /// 
+    /// // The generated source itself, compiled into a program that decodes a
+    /// // message with the table as firmware would
+    /// let data = codebook.encode("cadabra")?;
+    /// let program = format!("{source}const DATA: &[u8] = &{data:?};\n{}", r#"
+    /// fn main() {
+    ///     let bits = (DATA.len() - 1) * 8 - DATA[0] as usize;
+    ///     let (mut code, mut len, mut output) = (0u64, 0u8, String::new());
+    ///     for idx in 0..bits {
+    ///         code = code << 1 | (DATA[1 + idx / 8] >> (7 - idx % 8) & 1) as u64;
+    ///         len += 1;
+    ///         if let Some(&(ch, _, _)) = CODES.iter().find(|&&(_, bits, bits_len)| (bits, bits_len) == (code, len)) {
+    ///             output.push(ch);
+    ///             (code, len) = (0, 0);
+    ///         }
+    ///     }
+    ///     print!("{output}");
+    /// }
+    /// "#);
+    /// let dir = std::env::temp_dir().join("codecs_to_rust_const");
+    /// std::fs::create_dir_all(&dir)?;
+    /// std::fs::write(dir.join("decode.rs"), program)?;
+    /// let rustc = std::env::var("RUSTC").unwrap_or_else(|_| String::from("rustc"));
+    /// let status = Command::new(rustc)
+    ///     .args(["--edition", "2021", "-A", "warnings", "-o"])
+    ///     .arg(dir.join("decode"))
+    ///     .arg(dir.join("decode.rs"))
+    ///     .status()?;
+    /// assert!(status.success());
+    /// assert_eq!(Command::new(dir.join("decode")).output()?.stdout, b"cadabra");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn to_rust_const(&self, name: &str) -> String {
+        let codes = self.sorted_codes();
+        let mut source = format!("pub const {}: [(char, u64, u8); {}] = [\n", name, codes.len());
+        for (ch, code) in &codes {
+            source.push_str(&format!("    ({:?}, 0b{}, {}),\n", ch, code, code.len()));
+        }
+        source.push_str("];\n");
+        source.push_str(&format!("pub const {}_ESCAPE: (u64, u8) = (0b{}, {});\n", name, self.escape, self.escape.len()));
+
+        source
+    }
+
+    /// Same as `to_rust_const()`, writing a C header instead. `name` is given
+    /// a `static const` array of structs holding each character's codepoint,
+    /// code and code length, and `name` with `_escape` the escape code.
+    /// 
+    /// ## Example
+    /// 
+    /// 
+    /// ```
+    /// use codecs::huffman::Codebook;
+    /// 
+    /// let header = Codebook::new("abracadabra").unwrap().to_c_header("codes");
+    /// assert!(header.starts_with("#include <stdint.h>\n"));
+    /// assert!(header.contains("static const struct { uint32_t symbol; uint64_t bits; uint8_t len; } codes[5] = {\n"));
+    /// assert!(header.contains("    { 0x61, 0x0, 1 },\n"));
+    /// assert!(header.contains("static const struct { uint64_t bits; uint8_t len; } codes_escape = { 0xa, 4 };\n"));
+    /// ```
+    pub fn to_c_header(&self, name: &str) -> String {
+        let codes = self.sorted_codes();
+        let mut header = String::from("#include <stdint.h>\n\n");
+        header.push_str(&format!(
+            "static const struct {{ uint32_t symbol; uint64_t bits; uint8_t len; }} {}[{}] = {{\n",
+            name,
+            codes.len()
+        ));
+        for (ch, code) in &codes {
+            let bits = u64::from_str_radix(code, 2).unwrap();
+            header.push_str(&format!("    {{ {:#x}, {:#x}, {} }},\n", *ch as u32, bits, code.len()));
+        }
+        header.push_str("};\n");
+        let escape = u64::from_str_radix(&self.escape, 2).unwrap();
+        header.push_str(&format!(
+            "static const struct {{ uint64_t bits; uint8_t len; }} {}_escape = {{ {:#x}, {} }};\n",
+            name,
+            escape,
+            self.escape.len()
+        ));
+
+        header
+    }
+
+    // The codes shortest first, and then by character
+    fn sorted_codes(&self) -> Vec<(char, &str)> {
+        let mut codes: Vec<_> = self.codes.iter().map(|(&ch, code)| (ch, code.as_str())).collect();
+        codes.sort_unstable_by_key(|&(ch, code)| (code.len(), ch));
+        codes
+    }
+
+    /// Encodes a message, writing any character that is not in the codebook as
+    /// the escape code followed by its literal UTF-8 bytes.
+    /// 
+    /// ## Example
+    /// 
+    /// 
+    /// ```
+    /// use codecs::huffman::Codebook;
+    /// 
+    /// let codebook = Codebook::new("the quick brown fox jumps over the lazy dog").unwrap();
+    /// let data = codebook.encode_with_escape("dog€").unwrap();
+    /// assert!(codebook.encode("dog€").is_err());
+    /// assert_eq!(codebook.decode(&data).unwrap(), "dog€");
+    /// ```
+    pub fn encode_with_escape(&self, input: &str) -> Result<Vec<u8>> {
+        Ok(self.package(encode_escaped(input, &self.codes, &self.escape)))
+    }
+
+    /// Decodes a message produced by `encode()` or `encode_with_escape()`.
+    pub fn decode(&self, input: &[u8]) -> Result<String> {
+        if input.is_empty() {
+            return Err(anyhow!("Malformed input."));
+        }
+        let mut output = String::new();
+
+        walk_payload(&self.tree, &input[1..], input[0], |ch| output.push(ch))?;
+
+        Ok(output)
+    }
+
+    fn package(&self, encoded: PackedBits) -> Vec<u8> {
+        let mut glob = vec![encoded.pad];
+        glob.extend_from_slice(&encoded.bytes);
+
+        glob
+    }
+}
+
+/// Encodes bytes using a preset dictionary, much like zlib's. The tree is
+/// built from the byte frequencies of `dict` and is not stored, so short
+/// inputs that resemble the dictionary compress far better than they would
+/// with a tree of their own. Bytes missing from the dictionary are escaped.
+/// The same dictionary must be given to `decode_bytes_with_dict()`.
+/// 
+/// The output has the same layout as a `Codebook` message.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{encode_bytes_with_dict, decode_bytes_with_dict, encode_to_bitstream};
+/// 
+/// let dict = b"GET /index.html HTTP/1.1\r\nHost: example.com\r\nAccept: text/html\r\n";
+/// let input = b"GET /about.html HTTP/1.1\r\nHost: example.com\r\n";
+/// let data = encode_bytes_with_dict(input, dict).unwrap();
+/// let plain = encode_to_bitstream(std::str::from_utf8(input).unwrap()).unwrap();
+/// assert!(data.len() < plain.len());
+/// assert_eq!(decode_bytes_with_dict(&data, dict).unwrap(), input);
+/// ```
+pub fn encode_bytes_with_dict(input: &[u8], dict: &[u8]) -> Result<Vec<u8>> {
+    Codebook::new(&bytes_to_latin1(dict))?.encode_with_escape(&bytes_to_latin1(input))
+}
+
+/// Decodes bytes produced by `encode_bytes_with_dict()` with the same
+/// dictionary.
+pub fn decode_bytes_with_dict(input: &[u8], dict: &[u8]) -> Result<Vec<u8>> {
+    latin1_to_bytes(&Codebook::new(&bytes_to_latin1(dict))?.decode(input)?)
+}
+
+/// Encodes a text with a tree over pairs of characters, which can beat
+/// single characters on natural language, where some letter pairs are very
+/// common. Going through the text, each common pair becomes one symbol, and
+/// any other character a symbol of its own, which covers rare pairs and an
+/// odd character at the end. `decode_digrams()` reverses this.
+/// 
+/// The output holds the number of symbols as a variable width number, then
+/// each symbol as its length in bytes followed by its UTF-8, then an
+/// `encode_to_bitstream()` stream of the text with each symbol replaced by a
+/// character standing for its place in the table.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{encode_digrams, decode_digrams, encode_to_bitstream};
+/// 
+/// let book = include_str!("../examples/book.txt");
+/// let data = encode_digrams(book).unwrap();
+/// assert!(data.len() < encode_to_bitstream(book).unwrap().len());
+/// assert_eq!(decode_digrams(&data).unwrap(), book);
+/// assert_eq!(decode_digrams(&encode_digrams("odd").unwrap()).unwrap(), "odd");
+/// ```
+pub fn encode_digrams(input: &str) -> Result<Vec<u8>> {
+    if input.is_empty() {
+        return Err(anyhow!("Input string is empty."));
+    }
+
+    let chars: Vec<char> = input.chars().collect();
+    let mut pairs: HashMap<&[char], usize> = HashMap::new();
+    for pair in chars.windows(2) {
+        *pairs.entry(pair).or_insert(0) += 1;
+    }
+
+    // Pairs rarer than one in every 256 characters cost more in the symbol
+    // table and tree than they save
+    let min_freq = (chars.len() / 256).max(4);
+
+    // Take a common pair wherever one starts, and a single character otherwise
+    let mut tokens = Vec::new();
+    let mut idx = 0;
+    while idx < chars.len() {
+        let pair = chars.get(idx..(idx + 2));
+        let len = match pair.and_then(|pair| pairs.get(pair)) {
+            Some(&freq) if freq >= min_freq => 2,
+            _ => 1,
+        };
+        tokens.push(&chars[idx..(idx + len)]);
+        idx += len;
+    }
+
+    // Symbols are numbered in order of first appearance
+    let mut symbols: Vec<&[char]> = Vec::new();
+    let mut index: HashMap<&[char], usize> = HashMap::new();
+    let mut text = String::with_capacity(tokens.len());
+    for token in tokens {
+        let next = symbols.len();
+        let idx = *index.entry(token).or_insert_with(|| {
+            symbols.push(token);
+            next
+        });
+        check_alphabet(idx + 1)?;
+        text.push(index_to_char(idx).ok_or_else(|| anyhow!("Too many symbols."))?);
+    }
+
+    let mut glob = uint_to_vwe(symbols.len())?;
+    for symbol in symbols {
+        let symbol: String = symbol.iter().collect();
+        glob.extend(uint_to_vwe(symbol.len())?);
+        glob.extend_from_slice(symbol.as_bytes());
+    }
+    glob.extend(encode_to_bitstream(&text)?);
+
+    Ok(glob)
+}
+
+/// Decodes a text produced by `encode_digrams()`.
+pub fn decode_digrams(input: &[u8]) -> Result<String> {
+    decode_symbol_text(input)
+}
+
+/// Encodes a text after up to `merges` rounds of byte pair encoding, as in
+/// BPE. Each round finds the pair of neighbouring symbols that occurs most
+/// often and makes it a new symbol, so recurring words and phrases grow into
+/// single symbols that the tree then codes as one. Rounds stop early once the
+/// most common pair occurs no more times than its new symbol has bytes, as
+/// storing the symbol would then cost about as much as it saves.
+/// `decode_bpe()` reverses this.
+/// 
+/// The output has the same layout as `encode_digrams()` gives, a table of
+/// every symbol followed by the stream of symbols.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{decode_bpe, encode_bpe, encode_to_bitstream};
+/// 
+/// let log: String = (0..200)
+///     .map(|i| format!("[INFO] request handled successfully: id={}\n", i % 10))
+///     .collect();
+/// let data = encode_bpe(&log, 64).unwrap();
+/// assert_eq!(decode_bpe(&data).unwrap(), log);
+/// assert!(data.len() < encode_to_bitstream(&log).unwrap().len() / 2);
+/// 
+/// assert_eq!(decode_bpe(&encode_bpe("abc", 0).unwrap()).unwrap(), "abc");
+/// ```
+pub fn encode_bpe(input: &str, merges: usize) -> Result<Vec<u8>> {
+    if input.is_empty() {
+        return Err(anyhow!("Input string is empty."));
+    }
+
+    // Symbols start as the characters, numbered in order of first appearance
+    let mut symbols: Vec<String> = Vec::new();
+    let mut index: HashMap<char, usize> = HashMap::new();
+    let mut tokens: Vec<usize> = input
+        .chars()
+        .map(|ch| {
+            *index.entry(ch).or_insert_with(|| {
+                symbols.push(ch.to_string());
+                symbols.len() - 1
+            })
+        })
+        .collect();
+
+    for _ in 0..merges {
+        let mut pairs: HashMap<(usize, usize), usize> = HashMap::new();
+        for pair in tokens.windows(2) {
+            *pairs.entry((pair[0], pair[1])).or_insert(0) += 1;
+        }
+        // Ties go to the lowest pair, so the output does not depend on hashing
+        let Some((pair, count)) = pairs
+            .into_iter()
+            .max_by_key(|&(pair, count)| (count, std::cmp::Reverse(pair)))
+        else {
+            break;
+        };
+        // Each merge saves a code for every occurrence but stores the new
+        // symbol's bytes in the table, so stop once that is no longer a win
+        let symbol = symbols[pair.0].clone() + &symbols[pair.1];
+        if count < 2 || count <= symbol.len() {
+            break;
+        }
+
+        let merged = symbols.len();
+        check_alphabet(merged + 1)?;
+        symbols.push(symbol);
+
+        let mut next = Vec::with_capacity(tokens.len());
+        let mut idx = 0;
+        while idx < tokens.len() {
+            if tokens.get(idx..(idx + 2)) == Some(&[pair.0, pair.1]) {
+                next.push(merged);
+                idx += 2;
+            } else {
+                next.push(tokens[idx]);
+                idx += 1;
+            }
+        }
+        tokens = next;
+    }
+
+    let text = tokens
+        .into_iter()
+        .map(|token| index_to_char(token).ok_or_else(|| anyhow!("Too many symbols.")))
+        .collect::<Result<String>>()?;
+
+    let mut glob = uint_to_vwe(symbols.len())?;
+    for symbol in symbols {
+        glob.extend(uint_to_vwe(symbol.len())?);
+        glob.extend_from_slice(symbol.as_bytes());
+    }
+    glob.extend(encode_to_bitstream(&text)?);
+
+    Ok(glob)
+}
+
+/// Decodes a text produced by `encode_bpe()`.
+pub fn decode_bpe(input: &[u8]) -> Result<String> {
+    decode_symbol_text(input)
+}
+
+// Decodes a symbol table followed by a stream of the symbols' places in it
+fn decode_symbol_text(input: &[u8]) -> Result<String> {
+    let (symbols, stream) = split_symbol_table(input)?;
+
+    let mut output = String::new();
+    for ch in decode_from_bitstream(stream)?.chars() {
+        let symbol = symbols
+            .get(char_to_index(ch))
+            .ok_or_else(|| anyhow!("Symbol out of range."))?;
+        output.push_str(symbol);
+    }
+
+    Ok(output)
+}
+
+/// Encodes a text with two trees, one over the block of 256 codepoints each
+/// character falls in and one over its place within the block. Texts with
+/// thousands of distinct characters, such as Chinese or Japanese, need a
+/// large tree when coded a character at a time, while these two trees
+/// between them hold at most a few hundred leaves. `decode_hierarchical()`
+/// reverses this.
+/// 
+/// The output holds the number of characters as a variable width number, a
+/// CRC-32 of the text, the block tree and then the tree within blocks, each
+/// prefixed with its length as a variable width number, then the number of
+/// data packing bits and the data, where each character is its block code
+/// followed by its code within the block.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{decode_hierarchical, encode_hierarchical, encode_to_bitstream, format_breakdown};
+/// 
+/// // A few thousand characters spread over the CJK Unified Ideographs
+/// let mut seed: u32 = 1;
+/// let text: String = (0..3000)
+///     .map(|_| {
+///         seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+///         char::from_u32(0x4E00 + (seed >> 16) % 0x5200).unwrap()
+///     })
+///     .collect();
+/// 
+/// let data = encode_hierarchical(&text).unwrap();
+/// assert_eq!(decode_hierarchical(&data).unwrap(), text);
+/// 
+/// // Both trees and the data together take less room than the flat tree alone
+/// let flat = encode_to_bitstream(&text).unwrap();
+/// assert!(data.len() < format_breakdown(&flat).unwrap().tree_bytes);
+/// ```
+pub fn encode_hierarchical(input: &str) -> Result<Vec<u8>> {
+    if input.is_empty() {
+        return Err(anyhow!("Input string is empty."));
+    }
+
+    // Blocks go no higher than 0x10FF, well below the surrogates
+    let blocks: String = input.chars().filter_map(|ch| char::from_u32(ch as u32 >> 8)).collect();
+    let offsets: String = input.chars().map(|ch| char::from(ch as u32 as u8)).collect();
+    let block_tree = gen_tree(&blocks);
+    let offset_tree = gen_tree(&offsets);
+    let block_codes = assign_codes(&block_tree);
+    let offset_codes = assign_codes(&offset_tree);
+
+    let mut writer = BitWriter::new();
+    for (block, offset) in blocks.chars().zip(offsets.chars()) {
+        writer.write_code(&block_codes[&block]);
+        writer.write_code(&offset_codes[&offset]);
+    }
+    let encoded = writer.finish();
+
+    let mut glob = uint_to_vwe(input.chars().count())?;
+    glob.extend_from_slice(&crc32(input.as_bytes()).to_be_bytes());
+    for tree in [block_tree, offset_tree] {
+        let stree = ser_tree(tree, LeafFormat::Utf8);
+        glob.extend(uint_to_vwe(stree.len())?);
+        glob.extend_from_slice(&stree);
+    }
+    glob.push(encoded.pad);
+    glob.extend_from_slice(&encoded.bytes);
+
+    Ok(glob)
+}
+
+/// Decodes a text produced by `encode_hierarchical()`.
+pub fn decode_hierarchical(input: &[u8]) -> Result<String> {
+    let (count, mut idx) = vwe_to_uint(input)?;
+    let checksum = input
+        .get(idx..(idx + 4))
+        .ok_or_else(|| anyhow!("Truncated checksum."))?;
+    let checksum = u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]);
+    idx += 4;
+
+    let mut trees = Vec::with_capacity(2);
+    for _ in 0..2 {
+        let (tree_len, width) = vwe_to_uint(&input[idx..])?;
+        idx += width;
+        let stree = input
+            .get(idx..(idx + tree_len))
+            .ok_or_else(|| anyhow!("Tree size mismatch."))?;
+        trees.push(des_tree(stree, LeafFormat::Utf8)?);
+        idx += tree_len;
+    }
+    let data = input.get((idx + 1)..).ok_or_else(|| anyhow!("Missing data."))?;
+
+    let mut bundle = BitBundle::new(data);
+    let mut output = String::with_capacity(count.min(data.len() * 8));
+    for _ in 0..count {
+        let block = read_symbol(&trees[0], &mut bundle)? as u32;
+        let offset = read_symbol(&trees[1], &mut bundle)? as u32;
+        let ch = (block <= 0x10FF && offset <= 0xFF)
+            .then(|| char::from_u32(block << 8 | offset))
+            .flatten()
+            .ok_or_else(|| anyhow!("Invalid character in data."))?;
+        output.push(ch);
+    }
+
+    if crc32(output.as_bytes()) != checksum {
+        return Err(anyhow!("Checksum mismatch."));
+    }
+
+    Ok(output)
+}
+
+// Character classes for `encode_class_partitioned()`, as the digit naming
+// each in the selector tree
+const CHAR_CLASSES: usize = 4;
+
+fn char_class(ch: char) -> usize {
+    if ch.is_alphabetic() {
+        0
+    } else if ch.is_numeric() {
+        1
+    } else if ch.is_whitespace() {
+        2
+    } else {
+        3
+    }
+}
+
+/// Encodes a text with a tree for each class of character, letters, digits,
+/// whitespace and the rest, and a small selector tree over the classes. Each
+/// character is written as the code of its class followed by its code within
+/// the class. `decode_class_partitioned()` reverses this.
+/// 
+/// Together the selector and class codes form one prefix code over the text,
+/// so the data is never smaller than a single Huffman tree gives, and the
+/// extra trees add to the header. It comes within a tenth or so on mixed
+/// text, though, and each tree stays small enough to decode from a short
+/// table.
+/// 
+/// The output holds the number of characters as a variable width number, a
+/// CRC-32 of the text, the selector tree and then the letter, digit,
+/// whitespace and other trees, each prefixed with its length as a variable
+/// width number and zero for a class the text does not use, then the number
+/// of data packing bits and the data.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{decode_class_partitioned, encode_class_partitioned, encode_to_bitstream};
+/// 
+/// // Order lines mixing names, quantities and prices
+/// let mut text = String::new();
+/// for i in 0..200 {
+///     text.push_str(&format!("item{} qty {} price {}.{:02}; ", i % 17, i % 9 + 1, 3 + i % 40, i * 7 % 100));
+/// }
+/// 
+/// let data = encode_class_partitioned(&text).unwrap();
+/// assert_eq!(decode_class_partitioned(&data).unwrap(), text);
+/// 
+/// // A single tree over the whole text still wins, but by less than a tenth
+/// let single = encode_to_bitstream(&text).unwrap();
+/// assert!(single.len() < data.len());
+/// assert!(data.len() * 10 < single.len() * 11);
+/// 
+/// assert!(encode_class_partitioned("").is_err());
+/// ```
+pub fn encode_class_partitioned(input: &str) -> Result<Vec<u8>> {
+    if input.is_empty() {
+        return Err(anyhow!("Input string is empty."));
+    }
+
+    let mut classes = vec![String::new(); CHAR_CLASSES];
+    let mut selectors = String::with_capacity(input.len());
+    for ch in input.chars() {
+        let class = char_class(ch);
+        classes[class].push(ch);
+        selectors.push(char::from(b'0' + class as u8));
+    }
+    let selector_tree = gen_tree(&selectors);
+    let class_trees: Vec<Option<Node>> = classes
+        .iter()
+        .map(|chars| (!chars.is_empty()).then(|| gen_tree(chars)))
+        .collect();
+    let selector_codes = assign_codes(&selector_tree);
+    let class_codes: Vec<HashMap<char, String>> = class_trees
+        .iter()
+        .map(|tree| tree.as_ref().map(assign_codes).unwrap_or_default())
+        .collect();
+
+    let mut writer = BitWriter::new();
+    for (ch, selector) in input.chars().zip(selectors.chars()) {
+        writer.write_code(&selector_codes[&selector]);
+        writer.write_code(&class_codes[char_class(ch)][&ch]);
+    }
+    let encoded = writer.finish();
+
+    let mut glob = uint_to_vwe(input.chars().count())?;
+    glob.extend_from_slice(&crc32(input.as_bytes()).to_be_bytes());
+    for tree in std::iter::once(Some(selector_tree)).chain(class_trees) {
+        let stree = tree.map(|tree| ser_tree(tree, LeafFormat::Utf8)).unwrap_or_default();
+        glob.extend(uint_to_vwe(stree.len())?);
+        glob.extend_from_slice(&stree);
+    }
+    glob.push(encoded.pad);
+    glob.extend_from_slice(&encoded.bytes);
+
+    Ok(glob)
+}
+
+/// Decodes a text produced by `encode_class_partitioned()`.
+pub fn decode_class_partitioned(input: &[u8]) -> Result<String> {
+    let (count, mut idx) = vwe_to_uint(input)?;
+    let checksum = input
+        .get(idx..(idx + 4))
+        .ok_or_else(|| anyhow!("Truncated checksum."))?;
+    let checksum = u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]);
+    idx += 4;
+
+    let mut trees = Vec::with_capacity(CHAR_CLASSES + 1);
+    for _ in 0..=CHAR_CLASSES {
+        let (tree_len, width) = vwe_to_uint(input.get(idx..).unwrap_or_default())?;
+        idx += width;
+        let stree = input
+            .get(idx..(idx + tree_len))
+            .ok_or_else(|| anyhow!("Tree size mismatch."))?;
+        trees.push(if tree_len == 0 { None } else { Some(des_tree(stree, LeafFormat::Utf8)?) });
+        idx += tree_len;
+    }
+    let selector_tree = trees[0].take().ok_or_else(|| anyhow!("Missing selector tree."))?;
+    let data = input.get((idx + 1)..).ok_or_else(|| anyhow!("Missing data."))?;
+
+    let mut bundle = BitBundle::new(data);
+    let mut output = String::with_capacity(count.min(data.len() * 8));
+    for _ in 0..count {
+        let class = read_symbol(&selector_tree, &mut bundle)?
+            .to_digit(10)
+            .filter(|&class| (class as usize) < CHAR_CLASSES)
+            .ok_or_else(|| anyhow!("Invalid character class in data."))?;
+        let tree = trees[class as usize + 1]
+            .as_ref()
+            .ok_or_else(|| anyhow!("Missing tree for character class."))?;
+        output.push(read_symbol(tree, &mut bundle)?);
+    }
+
+    if crc32(output.as_bytes()) != checksum {
+        return Err(anyhow!("Checksum mismatch."));
+    }
+
+    Ok(output)
+}
+
+// Reads a table of symbols, each prefixed with its length, returning them and
+// whatever follows the table
+fn split_symbol_table(input: &[u8]) -> Result<(Vec<String>, &[u8])> {
+    let (count, mut idx) = vwe_to_uint(input)?;
+    check_alphabet(count)?;
+
+    // Each symbol takes at least a byte, which bounds a corrupt count
+    let mut symbols = Vec::with_capacity(count.min(input.len()));
+    for _ in 0..count {
+        let (len, width) = vwe_to_uint(&input[idx..])?;
+        idx += width;
+        let bytes = input
+            .get(idx..(idx + len))
+            .ok_or_else(|| anyhow!("Truncated symbol table."))?;
+        let symbol = std::str::from_utf8(bytes).map_err(|_| anyhow!("Symbol is not valid UTF-8."))?;
+        symbols.push(symbol.to_string());
+        idx += len;
+    }
+
+    Ok((symbols, &input[idx..]))
+}
+
+/// Encodes a text made only of the characters in `alphabet`, such as DNA
+/// bases or hex digits. Each leaf of the tree holds the character's place in
+/// the alphabet in as few bits as it takes to number the whole alphabet,
+/// rather than its UTF-8 bytes, so the header is smaller than
+/// `encode_to_bitstream()` gives. Fails on a character outside the alphabet.
+/// `decode_fixed_alphabet()` reverses this.
+/// 
+/// The output holds the size of the alphabet as a variable width number, the
+/// alphabet in UTF-8, the number of characters in the text as a variable
+/// width number and a CRC-32 of the text, then the tree and the data bits
+/// straight after it.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{decode_fixed_alphabet, encode_fixed_alphabet, encode_to_bitstream, format_breakdown};
+/// 
+/// let dna = "GATTACACCGTAGGCTAACGTTAGCATGCATCGATCGGATCCATGCAAGTCTAGCTAGGATCCATT".repeat(4);
+/// let data = encode_fixed_alphabet(&dna, &['A', 'C', 'G', 'T']).unwrap();
+/// assert_eq!(decode_fixed_alphabet(&data).unwrap(), dna);
+/// 
+/// // The codes, and so the data, are the same size either way
+/// let general = encode_to_bitstream(&dna).unwrap();
+/// let payload = format_breakdown(&general).unwrap().payload_bytes;
+/// assert!(data.len() - payload < general.len() - payload);
+/// 
+/// assert!(encode_fixed_alphabet("GATTACA", &['A', 'C', 'G']).is_err());
+/// ```
+pub fn encode_fixed_alphabet(input: &str, alphabet: &[char]) -> Result<Vec<u8>> {
+    if input.is_empty() {
+        return Err(anyhow!("Input string is empty."));
+    }
+    check_alphabet(alphabet.len())?;
+
+    let mut index = HashMap::with_capacity(alphabet.len());
+    for (idx, &ch) in alphabet.iter().enumerate() {
+        if index.insert(ch, idx).is_some() {
+            return Err(anyhow!("Character {:?} is in the alphabet twice.", ch));
+        }
+    }
+
+    // Stand in for each character with its place in the alphabet
+    let mut text = String::with_capacity(input.len());
+    for ch in input.chars() {
+        let idx = index
+            .get(&ch)
+            .ok_or_else(|| anyhow!("Character {:?} is not in the alphabet.", ch))?;
+        text.extend(index_to_char(*idx));
+    }
+    let tree = gen_tree(&text);
+    let codes = assign_codes(&tree);
+
+    let mut glob = uint_to_vwe(alphabet.len())?;
+    glob.extend(alphabet.iter().collect::<String>().bytes());
+    glob.extend(uint_to_vwe(input.chars().count())?);
+    glob.extend_from_slice(&crc32(input.as_bytes()).to_be_bytes());
+
+    let mut writer = BitWriter::new();
+    write_index_tree(&tree, index_width(alphabet.len()), &mut writer);
+    for ch in text.chars() {
+        writer.write_code(&codes[&ch]);
+    }
+    glob.extend(writer.finish().bytes);
+
+    Ok(glob)
+}
+
+/// Decodes a text produced by `encode_fixed_alphabet()`.
+pub fn decode_fixed_alphabet(input: &[u8]) -> Result<String> {
+    let mut bundle = BitBundle::new(input);
+    let size = read_vwe(&mut bundle)?;
+    check_alphabet(size)?;
+
+    // Each character takes at least a byte, which bounds a corrupt size
+    let mut alphabet = Vec::with_capacity(size.min(input.len()));
+    for _ in 0..size {
+        alphabet.push(read_char(&mut bundle).ok_or_else(|| anyhow!("Truncated alphabet."))?);
+    }
+    let count = read_vwe(&mut bundle)?;
+    let checksum = bundle.read_bits(32).ok_or_else(|| anyhow!("Truncated checksum."))? as u32;
+
+    let tree = read_index_tree(&mut bundle, index_width(size), size, 0)
+        .ok_or_else(|| anyhow!("Malformed tree."))?;
+    let mut output = String::with_capacity(count.min(input.len() * 8));
+    for _ in 0..count {
+        let idx = char_to_index(read_symbol(&tree, &mut bundle)?);
+        output.push(alphabet[idx]);
+    }
+
+    if crc32(output.as_bytes()) != checksum {
+        return Err(anyhow!("Checksum mismatch."));
+    }
+
+    Ok(output)
+}
+
+// Bits needed for the place of any character in an alphabet of `size`
+fn index_width(size: usize) -> u8 {
+    (usize::BITS - size.saturating_sub(1).leading_zeros()).max(1) as u8
+}
+
+// Serialise a tree over alphabet places in preorder, as `traverse_tree()` does
+fn write_index_tree(node: &Node, width: u8, writer: &mut BitWriter) {
+    if let Some(ch) = node.ch {
+        writer.write_bit(1);
+        writer.write_bits(char_to_index(ch) as u64, width);
+    } else if let (Some(left), Some(right)) = (&node.left, &node.right) {
+        writer.write_bit(0);
+        write_index_tree(left, width, writer);
+        write_index_tree(right, width, writer);
+    }
+}
+
+// Returns `None` if the data runs out or holds a place outside the alphabet
+fn read_index_tree(bundle: &mut BitBundle, width: u8, size: usize, depth: usize) -> Option<Node> {
+    if depth > MAX_TREE_DEPTH {
+        return None;
+    }
+
+    if bundle.read_bit()? == 1 {
+        let idx = bundle.read_bits(width)? as usize;
+        if idx >= size {
+            return None;
+        }
+        return Some(Node::new_leaf(index_to_char(idx)?));
+    }
+    let left = Box::new(read_index_tree(bundle, width, size, depth + 1)?);
+    let right = Box::new(read_index_tree(bundle, width, size, depth + 1)?);
+    Some(Node::new_node(left, right))
+}
+
+/// Encodes a list of lines as one text, each line ended by a newline, so that
+/// `decode_lines()` gives back exactly the same list. Backslashes and
+/// newlines within a line are escaped as `\\` and `\n`, so they cannot be
+/// mistaken for the end of a line.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{decode_lines, encode_lines};
+/// 
+/// let lines = vec![
+///     "first".to_string(),
+///     "two\nlines".to_string(),
+///     "".to_string(),
+///     "a \\n that is not a newline\\".to_string(),
+/// ];
+/// let data = encode_lines(&lines).unwrap();
+/// assert_eq!(decode_lines(&data).unwrap(), lines);
+/// assert_eq!(decode_lines(&encode_lines(&["".to_string()]).unwrap()).unwrap(), [""]);
+/// ```
+pub fn encode_lines(lines: &[String]) -> Result<Vec<u8>> {
+    if lines.is_empty() {
+        return Err(anyhow!("No lines to encode."));
+    }
+
+    let mut text = String::new();
+    for line in lines {
+        for ch in line.chars() {
+            match ch {
+                '\\' => text.push_str("\\\\"),
+                '\n' => text.push_str("\\n"),
+                _ => text.push(ch),
+            }
+        }
+        text.push('\n');
+    }
+
+    encode_to_bitstream(&text)
+}
+
+/// Decodes a list of lines produced by `encode_lines()`.
+pub fn decode_lines(bytes: &[u8]) -> Result<Vec<String>> {
+    let text = decode_from_bitstream(bytes)?;
+
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => match chars.next() {
+                Some('\\') => line.push('\\'),
+                Some('n') => line.push('\n'),
+                _ => return Err(anyhow!("Invalid escape in line.")),
+            },
+            '\n' => lines.push(std::mem::take(&mut line)),
+            _ => line.push(ch),
+        }
+    }
+    if !line.is_empty() {
+        return Err(anyhow!("Last line is not terminated."));
+    }
+
+    Ok(lines)
+}
+
+/// Encodes a map of strings as one text, each entry written as the key, a
+/// tab, the value and a newline, in key order so that the same map always
+/// gives the same bytes. Backslashes, tabs and newlines within a key or value
+/// are escaped as `\\`, `\t` and `\n`, so `decode_map()` gives back exactly
+/// the same map, empty values included.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use std::collections::BTreeMap;
+/// use codecs::huffman::{decode_map, encode_map};
+/// 
+/// let mut map = BTreeMap::new();
+/// map.insert("name".to_string(), "Zoë \"the\" Æsir".to_string());
+/// map.insert("path\\to".to_string(), "C:\\temp\\".to_string());
+/// map.insert("tab\tkey".to_string(), "line one\nline two".to_string());
+/// map.insert("empty".to_string(), String::new());
+/// map.insert(String::new(), "no key".to_string());
+/// map.insert("emoji 🦀".to_string(), "=\t=".to_string());
+/// 
+/// let data = encode_map(&map).unwrap();
+/// assert_eq!(decode_map(&data).unwrap(), map);
+/// 
+/// // Built in another order, the map still encodes the same
+/// let reversed: BTreeMap<String, String> = map.clone().into_iter().rev().collect();
+/// assert_eq!(encode_map(&reversed).unwrap(), data);
+/// ```
+pub fn encode_map(map: &BTreeMap<String, String>) -> Result<Vec<u8>> {
+    if map.is_empty() {
+        return Err(anyhow!("No entries to encode."));
+    }
+
+    let mut text = String::new();
+    for (key, value) in map {
+        push_map_field(&mut text, key);
+        text.push('\t');
+        push_map_field(&mut text, value);
+        text.push('\n');
+    }
+
+    encode_to_bitstream(&text)
+}
+
+/// Decodes a map of strings produced by `encode_map()`.
+pub fn decode_map(bytes: &[u8]) -> Result<BTreeMap<String, String>> {
+    let text = decode_from_bitstream(bytes)?;
+
+    let mut map = BTreeMap::new();
+    let mut key = None;
+    let mut field = String::new();
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => match chars.next() {
+                Some('\\') => field.push('\\'),
+                Some('t') => field.push('\t'),
+                Some('n') => field.push('\n'),
+                _ => return Err(anyhow!("Invalid escape in map.")),
+            },
+            '\t' if key.is_none() => key = Some(std::mem::take(&mut field)),
+            '\n' => {
+                let key = key.take().ok_or_else(|| anyhow!("Entry has no value."))?;
+                map.insert(key, std::mem::take(&mut field));
+            }
+            '\t' => return Err(anyhow!("Entry has more than one value.")),
+            _ => field.push(ch),
+        }
+    }
+    if key.is_some() || !field.is_empty() {
+        return Err(anyhow!("Last entry is not terminated."));
+    }
+
+    Ok(map)
+}
+
+fn push_map_field(text: &mut String, field: &str) {
+    for ch in field.chars() {
+        match ch {
+            '\\' => text.push_str("\\\\"),
+            '\t' => text.push_str("\\t"),
+            '\n' => text.push_str("\\n"),
+            _ => text.push(ch),
+        }
+    }
+}
+
+/// Encodes several texts under one tree built from their combined frequencies.
+/// Similar texts share most of their tree, so this is more compact than
+/// encoding each on its own, while each text still sits in its own byte
+/// aligned segment and can be decoded alone with `decode_shared_segment()`.
+/// 
+/// The output holds the tree prefixed with its length, then the number of
+/// segments, then each segment's offset into the data and character count,
+/// all as variable width numbers, followed by the data.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{encode_shared, decode_shared, decode_shared_segment};
+/// 
+/// let inputs = ["the cat sat on the mat", "the dog sat on the log", "the end"];
+/// let data = encode_shared(&inputs).unwrap();
+/// assert_eq!(decode_shared(&data).unwrap(), inputs);
+/// assert_eq!(decode_shared_segment(&data, 1).unwrap(), inputs[1]);
+/// ```
+pub fn encode_shared(inputs: &[&str]) -> Result<Vec<u8>> {
+    let combined: String = inputs.concat();
+    if combined.is_empty() {
+        return Err(anyhow!("Input strings are empty."));
+    }
+
+    let tree = gen_tree(&combined);
+    let codes = assign_codes(&tree);
+    let stree = ser_tree(tree, LeafFormat::Utf8);
+
+    let mut glob = Vec::new();
+    glob.extend(uint_to_vwe(stree.len())?);
+    glob.extend_from_slice(&stree);
+    glob.extend(uint_to_vwe(inputs.len())?);
+
+    let mut data = Vec::new();
+    for input in inputs {
+        glob.extend(uint_to_vwe(data.len())?);
+        glob.extend(uint_to_vwe(input.chars().count())?);
+        data.extend(encode_packed(input, &codes, BitOrder::MsbFirst).bytes);
+    }
+    glob.extend(data);
+
+    Ok(glob)
+}
+
+/// Decodes every text from `encode_shared()`.
+pub fn decode_shared(input: &[u8]) -> Result<Vec<String>> {
+    let shared = split_shared(input)?;
+
+    shared.segments
+        .iter()
+        .map(|&(offset, count)| decode_segment(&shared.tree, shared.data, offset, count))
+        .collect()
+}
+
+/// Decodes a single text from `encode_shared()` by its position, without
+/// touching the others.
+pub fn decode_shared_segment(input: &[u8], index: usize) -> Result<String> {
+    let shared = split_shared(input)?;
+    let &(offset, count) = shared.segments
+        .get(index)
+        .ok_or_else(|| anyhow!("No segment {}.", index))?;
+
+    decode_segment(&shared.tree, shared.data, offset, count)
+}
+
+// The parts of a shared tree container
+struct Shared<'a> {
+    tree: Node,
+    // Offset into the data and character count of each segment
+    segments: Vec<(usize, usize)>,
+    data: &'a [u8],
+}
+
+fn split_shared(input: &[u8]) -> Result<Shared<'_>> {
+    let (tree_len, mut idx) = vwe_to_uint(input)?;
+    let stree = input
+        .get(idx..(idx + tree_len))
+        .ok_or_else(|| anyhow!("Tree size mismatch."))?;
+    idx += tree_len;
+
+    let (count, width) = vwe_to_uint(&input[idx..])?;
+    idx += width;
+    // Each segment takes at least two bytes, which bounds a corrupt count
+    let mut segments = Vec::with_capacity(count.min(input.len() / 2));
+    for _ in 0..count {
+        let (offset, width) = vwe_to_uint(&input[idx..])?;
+        idx += width;
+        let (chars, width) = vwe_to_uint(&input[idx..])?;
+        idx += width;
+        segments.push((offset, chars));
+    }
+
+    Ok(Shared { tree: des_tree(stree, LeafFormat::Utf8)?, segments, data: &input[idx..] })
+}
+
+fn decode_segment(tree: &Node, data: &[u8], offset: usize, count: usize) -> Result<String> {
+    let segment = data.get(offset..).ok_or_else(|| anyhow!("Segment offset out of range."))?;
+    let mut bundle = BitBundle::new(segment);
+    let mut output = String::with_capacity(count.min(segment.len() * 8));
+    for _ in 0..count {
+        output.push(read_symbol(tree, &mut bundle)?);
+    }
+
+    Ok(output)
+}
+
+/// Approximate frequencies of the printable ASCII characters and newline in
+/// English prose, per 100,000 characters. Every character has a non-zero
+/// count, so `english_codebook()` has a code for all of them.
+pub const ENGLISH_ASCII_FREQ: &[(char, usize)] = &[
+    ('\n', 800), (' ', 17000), ('!', 50), ('"', 200), ('#', 5), ('$', 5),
+    ('%', 5), ('&', 5), ('\'', 250), ('(', 30), (')', 30), ('*', 5),
+    ('+', 5), (',', 1000), ('-', 200), ('.', 900), ('/', 5), ('0', 50),
+    ('1', 50), ('2', 50), ('3', 50), ('4', 50), ('5', 50), ('6', 50),
+    ('7', 50), ('8', 50), ('9', 50), (':', 40), (';', 40), ('<', 5),
+    ('=', 5), ('>', 5), ('?', 60), ('@', 5), ('A', 300), ('B', 150),
+    ('C', 150), ('D', 100), ('E', 100), ('F', 100), ('G', 80), ('H', 200),
+    ('I', 400), ('J', 50), ('K', 40), ('L', 100), ('M', 150), ('N', 100),
+    ('O', 120), ('P', 120), ('Q', 10), ('R', 100), ('S', 250), ('T', 450),
+    ('U', 40), ('V', 30), ('W', 180), ('X', 10), ('Y', 60), ('Z', 10),
+    ('[', 5), ('\\', 5), (']', 5), ('^', 5), ('_', 5), ('`', 5),
+    ('a', 6000), ('b', 1100), ('c', 2200), ('d', 3400), ('e', 9000), ('f', 1700),
+    ('g', 1600), ('h', 4800), ('i', 5400), ('j', 120), ('k', 600), ('l', 3100),
+    ('m', 1900), ('n', 5300), ('o', 5900), ('p', 1400), ('q', 80), ('r', 4500),
+    ('s', 4900), ('t', 6700), ('u', 2100), ('v', 800), ('w', 1800), ('x', 120),
+    ('y', 1500), ('z', 60), ('{', 5), ('|', 5), ('}', 5), ('~', 5),
+];
+
+// Reject a zero count or a repeated character at compile time
+const _: () = {
+    let mut i = 0;
+    while i < ENGLISH_ASCII_FREQ.len() {
+        assert!(ENGLISH_ASCII_FREQ[i].1 > 0);
+        if i > 0 {
+            assert!((ENGLISH_ASCII_FREQ[i - 1].0 as u32) < (ENGLISH_ASCII_FREQ[i].0 as u32));
+        }
+        i += 1;
+    }
+};
+
+/// A ready made `Codebook` for short English texts, built from
+/// `ENGLISH_ASCII_FREQ`. Messages too short to carry their own tree compress
+/// well with it, and anything outside printable ASCII can still be sent with
+/// `encode_with_escape()`.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{english_codebook, encode_to_bitstream};
+/// 
+/// let codebook = english_codebook();
+/// let input = "Meet me at the station at noon.";
+/// let data = codebook.encode(input).unwrap();
+/// assert_eq!(codebook.decode(&data).unwrap(), input);
+/// assert!(data.len() < encode_to_bitstream(input).unwrap().len());
+/// ```
+pub fn english_codebook() -> Codebook {
+    Codebook::from_counts(ENGLISH_ASCII_FREQ.iter().copied().collect())
+}
+
+/// Builds a `Codebook` from the character frequencies of `sample`, adding
+/// `alpha` to the count of every character of `alphabet`, as in Laplace
+/// smoothing. A small sample often misses characters the messages will use,
+/// and smoothing gives each character of the alphabet a code of its own
+/// rather than leaving it to the escape.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{build_codebook_smoothed, Codebook};
+/// 
+/// let alphabet: Vec<char> = ('a'..='z').chain([' ']).collect();
+/// let sample = "the cat sat on the mat";
+/// assert!(Codebook::new(sample).unwrap().encode("quiz").is_err());
+/// 
+/// let codebook = build_codebook_smoothed(sample, &alphabet, 1).unwrap();
+/// assert!(codebook.covers("quiz"));
+/// let data = codebook.encode("quiz the cat").unwrap();
+/// assert_eq!(codebook.decode(&data).unwrap(), "quiz the cat");
+/// 
+/// assert!(build_codebook_smoothed("", &alphabet, 0).is_err());
+/// // Counts too large to add up
+/// assert!(build_codebook_smoothed("a", &['a'], usize::MAX).is_err());
+/// assert!(build_codebook_smoothed("", &['a', 'b'], usize::MAX).is_err());
+/// ```
+pub fn build_codebook_smoothed(sample: &str, alphabet: &[char], alpha: usize) -> Result<Codebook> {
+    let too_large = || anyhow!("Smoothed counts are too large to add up.");
+    let mut counts = count_chars(sample);
+    if alpha > 0 {
+        for &ch in alphabet {
+            let count = counts.entry(ch).or_insert(0);
+            *count = count.checked_add(alpha).ok_or_else(too_large)?;
+        }
+    }
+    if counts.is_empty() {
+        return Err(anyhow!("Sample string is empty and nothing is added for the alphabet."));
+    }
+    // The tree adds every count together at its root
+    counts
+        .values()
+        .try_fold(0usize, |total, &count| total.checked_add(count))
+        .ok_or_else(too_large)?;
+
+    Ok(Codebook::from_counts(counts))
+}
+
+/// Builds one codebook from the character frequencies of several files
+/// together, for a set of similar files that should share a tree rather than
+/// each carry its own. Each file is read and counted a chunk at a time, so a
+/// large training set is never held in memory whole. The codebook then
+/// encodes each file with `Codebook::encode_with_escape()`, which escapes any
+/// character the training files lacked.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{build_codebook_from_paths, encode_to_bitstream};
+/// # fn main() -> anyhow::Result<()> {
+/// let dir = std::env::temp_dir().join("codecs_codebook_from_paths");
+/// std::fs::create_dir_all(&dir)?;
+/// let first = dir.join("first.log");
+/// let second = dir.join("second.log");
+/// std::fs::write(&first, "INFO server started on port 8080\nINFO accepted connection\n")?;
+/// std::fs::write(&second, "WARN slow response from upstream\nINFO closed connection\n")?;
+/// 
+/// let codebook = build_codebook_from_paths(&[first.as_path(), second.as_path()])?;
+/// let third = "INFO accepted connection from upstream\nWARN queue is 90% full\n";
+/// let data = codebook.encode_with_escape(third)?;
+/// assert_eq!(codebook.decode(&data)?, third);
+/// assert!(data.len() < encode_to_bitstream(third)?.len());
+/// 
+/// assert!(build_codebook_from_paths(&[]).is_err());
+/// assert!(build_codebook_from_paths(&[dir.join("missing.log").as_path()]).is_err());
+/// # Ok(())
+/// # }
+/// ```
+pub fn build_codebook_from_paths(paths: &[&Path]) -> Result<Codebook> {
+    let mut counts = BTreeMap::new();
+    for path in paths {
+        count_file(path, &mut counts)?;
+    }
+    if counts.is_empty() {
+        return Err(anyhow!("Training files are empty."));
+    }
+
+    Ok(Codebook::from_counts(counts))
+}
+
+// Adds the character frequencies of a UTF-8 file to `counts`, reading it in
+// chunks and carrying over any character split between two of them
+fn count_file(path: &Path, counts: &mut BTreeMap<char, usize>) -> Result<()> {
+    let mut file = fs::File::open(path)?;
+    let mut buf = vec![0; 1 << 16];
+    let mut carry = 0;
+    loop {
+        let read = file.read(&mut buf[carry..])?;
+        let filled = carry + read;
+        let valid = match std::str::from_utf8(&buf[..filled]) {
+            Err(err) if err.error_len().is_some() || read == 0 => {
+                return Err(anyhow!("{} is not valid UTF-8.", path.display()));
+            }
+            Err(err) => err.valid_up_to(),
+            _ => filled,
+        };
+        let text = std::str::from_utf8(&buf[..valid]).unwrap();
+        let rest = filled - valid;
+        for ch in text.chars() {
+            *counts.entry(ch).or_insert(0) += 1;
+        }
+        if read == 0 {
+            return Ok(());
+        }
+        buf.copy_within((filled - rest)..filled, 0);
+        carry = rest;
+    }
+}
+
+// Find the code of the escape leaf, if the tree has one
+fn escape_code(node: &Node, code: String) -> Option<String> {
+    if node.is_escape() {
+        return Some(code);
+    }
+
+    let left = node.left.as_ref().and_then(|l| escape_code(l, code.clone() + "0"));
+    left.or_else(|| node.right.as_ref().and_then(|r| escape_code(r, code + "1")))
+}
+
+/// Huffman containers from outside this crate that `decode_reference()` reads.
+/// Both describe the codes by their lengths alone, with canonical codes as in
+/// `canonical_codes()`, and decode to bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReferenceFormat {
+    /// A table laid out as in a JPEG DHT segment (ITU-T T.81, Annex C):
+    /// sixteen bytes giving the number of codes of each length from 1 to 16,
+    /// then the symbols in the order their codes are given out. The table is
+    /// followed by the number of symbols as four big endian bytes, and the
+    /// data packed most significant bit first.
+    JpegTable,
+    /// The code lengths of each of the 256 byte values as one byte each, zero
+    /// for a byte without a code, with codes given out as in DEFLATE (RFC
+    /// 1951, section 3.2.2). The number of symbols follows as four big endian
+    /// bytes, then the data packed least significant bit first as DEFLATE
+    /// does, with each code starting from its most significant bit.
+    DeflateLengths,
+}
+
+/// Decodes data from a Huffman container produced elsewhere, in one of the
+/// layouts described by `ReferenceFormat`.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::{decode_reference, ReferenceFormat};
+/// 
+/// // 'a' has code 0, 'b' 10 and 'c' 11, and the data is "abcab"
+/// let mut jpeg = vec![1, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, b'a', b'b', b'c'];
+/// jpeg.extend([0, 0, 0, 5, 0b0101_1010]);
+/// assert_eq!(decode_reference(&jpeg, ReferenceFormat::JpegTable).unwrap(), b"abcab");
+/// 
+/// // The same codes with the data "abcca", filling each byte from the bottom
+/// let mut deflate = vec![0; 256];
+/// deflate[b'a' as usize] = 1;
+/// deflate[b'b' as usize] = 2;
+/// deflate[b'c' as usize] = 2;
+/// deflate.extend([0, 0, 0, 5, 0b0111_1010]);
+/// assert_eq!(decode_reference(&deflate, ReferenceFormat::DeflateLengths).unwrap(), b"abcca");
+/// ```
+pub fn decode_reference(input: &[u8], format: ReferenceFormat) -> Result<Vec<u8>> {
+    let (codes, rest, order) = match format {
+        ReferenceFormat::JpegTable => {
+            let counts = input.get(..16).ok_or_else(|| anyhow!("Truncated table."))?;
+            let total: usize = counts.iter().map(|&count| count as usize).sum();
+            let symbols = input.get(16..(16 + total)).ok_or_else(|| anyhow!("Truncated table."))?;
+            let lengths = counts
+                .iter()
+                .enumerate()
+                .flat_map(|(i, &count)| std::iter::repeat_n(i + 1, count as usize))
+                .zip(symbols)
+                .map(|(len, &symbol)| (char::from(symbol), len))
+                .collect();
+            (assign_canonical_sorted(lengths), &input[(16 + total)..], BitOrder::MsbFirst)
+        }
+        ReferenceFormat::DeflateLengths => {
+            let table = input.get(..256).ok_or_else(|| anyhow!("Truncated table."))?;
+            if table.iter().any(|&len| len > 15) {
+                return Err(anyhow!("Code length exceeds 15."));
+            }
+            let lengths = (0..=255u8)
+                .zip(table)
+                .filter(|&(_, &len)| len > 0)
+                .map(|(byte, &len)| (char::from(byte), len as usize))
+                .collect();
+            (assign_canonical(lengths), &input[256..], BitOrder::LsbFirst)
+        }
+    };
+
+    let count = rest.get(..4).ok_or_else(|| anyhow!("Truncated symbol count."))?;
+    let count = u32::from_be_bytes([count[0], count[1], count[2], count[3]]) as usize;
+    let tree = tree_from_codes(&codes)?;
+
+    let mut bundle = BitBundle::with_order(&rest[4..], order);
+    let mut output = Vec::with_capacity(count.min(rest.len() * 8));
+    for _ in 0..count {
+        // Symbols were read as Latin-1, so each fits in a byte
+        output.push(read_symbol_strict(&tree, &mut bundle)? as u8);
+    }
+
+    Ok(output)
+}
+
+// Build a tree holding the given codes, failing if one is a prefix of another
+fn tree_from_codes(codes: &[(char, String)]) -> Result<Node> {
+    let invalid = || anyhow!("Invalid code table.");
+    let mut root = Node { ch: None, left: None, right: None };
+
+    for (ch, code) in codes {
+        let mut node = &mut root;
+        for bit in code.bytes() {
+            if node.ch.is_some() {
+                return Err(invalid());
+            }
+            let next = if bit == b'0' { &mut node.left } else { &mut node.right };
+            node = next.get_or_insert_with(|| Box::new(Node { ch: None, left: None, right: None }));
+        }
+        if node.ch.is_some() || node.left.is_some() || node.right.is_some() {
+            return Err(invalid());
+        }
+        node.ch = Some(*ch);
+    }
+
+    Ok(root)
+}
+
+// Same as `read_symbol()`, but a root leaf is an ordinary one bit code that
+// must be a zero, as external formats give every code at least one bit
+fn read_symbol_strict(tree: &Node, bundle: &mut BitBundle) -> Result<char> {
+    let mut nodeptr = tree;
+    loop {
+        let bit = bundle.read_bit().ok_or_else(|| anyhow!("Truncated data."))?;
+        let next = if bit == 0 { &nodeptr.left } else { &nodeptr.right };
+        nodeptr = next.as_ref().ok_or_else(|| anyhow!("Invalid code in data."))?;
+        if let Some(ch) = nodeptr.ch {
+            return Ok(ch);
+        }
+    }
+}
+
+/// Compresses tabular records, such as columns of a log, with a separate tree
+/// for each column. Fields in the same column tend to share characters, so
+/// this usually beats a single tree over the whole text. All records must
+/// have the same number of fields, and at least one.
+/// 
+/// The output holds the record and column counts as variable width numbers,
+/// then each column's tree prefixed with its length, then a single byte of
+/// data packing bits and the data. Each field in the data is its character
+/// count followed by its codes.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::RecordCodec;
+/// 
+/// let records = vec![
+///     vec!["2024-01-01".to_string(), "INFO".to_string()],
+///     vec!["2024-01-02".to_string(), "WARN".to_string()],
+///     vec!["2024-01-02".to_string(), "".to_string()],
+/// ];
+/// let data = RecordCodec::encode(&records).unwrap();
+/// assert_eq!(RecordCodec::decode(&data).unwrap(), records);
+/// ```
+pub struct RecordCodec;
+
+impl RecordCodec {
+    /// Encodes the records, building one tree per column.
+    pub fn encode(records: &[Vec<String>]) -> Result<Vec<u8>> {
+        let columns = records.first().map_or(0, |record| record.len());
+        if records.iter().any(|record| record.len() != columns) {
+            return Err(anyhow!("Records have differing numbers of fields."));
+        }
+        if !records.is_empty() && columns == 0 {
+            return Err(anyhow!("Records have no fields."));
+        }
+
+        let mut glob = Vec::new();
+        glob.extend(uint_to_vwe(records.len())?);
+        glob.extend(uint_to_vwe(columns)?);
+
+        // A column without any characters gets no tree
+        let mut codes = Vec::with_capacity(columns);
+        for column in 0..columns {
+            let mut counts = BTreeMap::new();
+            for ch in records.iter().flat_map(|record| record[column].chars()) {
+                *counts.entry(ch).or_insert(0) += 1;
+            }
+            if counts.is_empty() {
+                glob.extend(uint_to_vwe(0)?);
+                codes.push(HashMap::new());
+            } else {
+                let tree = tree_from_counts(counts);
+                codes.push(assign_codes(&tree));
+                let stree = ser_tree(tree, LeafFormat::Utf8);
+                glob.extend(uint_to_vwe(stree.len())?);
+                glob.extend_from_slice(&stree);
+            }
+        }
+
+        let mut writer = BitWriter::new();
+        for record in records {
+            for (field, codes) in record.iter().zip(&codes) {
+                for byte in uint_to_vwe(field.chars().count())? {
+                    writer.write_byte(byte);
+                }
+                for ch in field.chars() {
+                    writer.write_code(&codes[&ch]);
+                }
+            }
+        }
+        let encoded = writer.finish();
+        glob.push(encoded.pad);
+        glob.extend_from_slice(&encoded.bytes);
+
+        Ok(glob)
+    }
+
+    /// Decodes records produced by `encode()`.
+    pub fn decode(input: &[u8]) -> Result<Vec<Vec<String>>> {
+        let (count, width) = vwe_to_uint(input)?;
+        let mut idx = width;
+        let (columns, width) = vwe_to_uint(&input[idx..])?;
+        idx += width;
+        if count > 0 && columns == 0 {
+            return Err(anyhow!("Records have no fields."));
+        }
+
+        // Each column takes at least a byte, which bounds a corrupt count
+        let mut trees = Vec::with_capacity(columns.min(input.len()));
+        for _ in 0..columns {
+            let (tree_len, width) = vwe_to_uint(&input[idx..])?;
+            idx += width;
+            if input.len() < idx + tree_len {
+                return Err(anyhow!("Tree size mismatch."));
+            }
+            trees.push((tree_len > 0).then(|| des_tree(&input[idx..(idx + tree_len)], LeafFormat::Utf8)).transpose()?);
+            idx += tree_len;
+        }
+
+        // Skip the data packing byte, as field lengths mark where the data ends
+        let data = input.get((idx + 1)..).ok_or_else(|| anyhow!("Malformed input."))?;
+        let mut bundle = BitBundle::new(data);
+        // Each record takes at least a byte per field
+        let mut records = Vec::with_capacity(count.min(data.len()));
+        for _ in 0..count {
+            let mut record = Vec::with_capacity(columns);
+            for tree in &trees {
+                let len = read_vwe(&mut bundle)?;
+                let mut field = String::new();
+                if len > 0 {
+                    let tree = tree.as_ref().ok_or_else(|| anyhow!("Field in a column without a tree."))?;
+                    for _ in 0..len {
+                        field.push(read_symbol(tree, &mut bundle)?);
+                    }
+                }
+                record.push(field);
+            }
+            records.push(record);
+        }
+
+        Ok(records)
+    }
+}
+
+/// The commonly used items, for importing in one go. Items behind a feature
+/// are only included when the feature is on.
+/// 
+/// ## Example
+/// 
+/// 
+/// ```
+/// use codecs::huffman::prelude::*;
+/// 
+/// let data = encode_to_bitstream("Lorem ipsum dolor sit amet").unwrap();
+/// assert_eq!(decode_from_bitstream(&data).unwrap(), "Lorem ipsum dolor sit amet");
+/// ```
+pub mod prelude {
+    pub use super::{
+        decode_from_bitstream, encode_to_bitstream, Codebook, Encoder, EntropyReport,
+        FormatBreakdown, HmcFile, HmcMeta, SourceEncoding,
+    };
+}
@@ -0,0 +1,265 @@
+//! ## Ints
+//! Compresses a list of integers with whichever of a few integer codes suits
+//! it best, and records the choice in a header so that `decompress()` needs
+//! nothing else.
+//! 
+//! - `Method::DeltaVarint` stores the difference from the previous value,
+//!   zigzag mapped so that small steps either way stay small, in LEB128
+//!   bytes. This suits sorted or slowly changing values such as timestamps.
+//! - `Method::Rice` splits each value into a unary high part and a fixed
+//!   number of low bits. This suits values spread evenly over a range.
+//! - `Method::EliasGamma` gives each value a code of about twice its bit
+//!   length. This suits mostly tiny values with the odd large one.
+//! 
+//! `choose()` works out the size each would give and picks the smallest, so
+//! the range, order and spread of the values all play their part.
+//! 
+//! ## Example
+//! 
+//! 
+//! ```
+//! use codecs::ints::{choose, compress, decompress, Method};
+//! 
+//! // Timestamps a minute apart, give or take
+//! let times: Vec<u64> = (0..100).map(|i| 1_700_000_000 + i * 60 + i % 7).collect();
+//! assert_eq!(choose(&times), Method::DeltaVarint);
+//! assert_eq!(decompress(&compress(&times)).unwrap(), times);
+//! 
+//! // Values spread evenly over 0 to 999
+//! let mut seed: u64 = 7;
+//! let random: Vec<u64> = (0..100)
+//!     .map(|_| {
+//!         seed ^= seed << 13;
+//!         seed ^= seed >> 7;
+//!         seed ^= seed << 17;
+//!         seed % 1000
+//!     })
+//!     .collect();
+//! assert_eq!(choose(&random), Method::Rice);
+//! assert_eq!(decompress(&compress(&random)).unwrap(), random);
+//! 
+//! // Mostly zeroes, with the odd spike
+//! let small: Vec<u64> = (0..100).map(|i| if i % 10 == 9 { 200 } else { 0 }).collect();
+//! assert_eq!(choose(&small), Method::EliasGamma);
+//! assert_eq!(decompress(&compress(&small)).unwrap(), small);
+//! 
+//! for values in [vec![], vec![u64::MAX, 0, u64::MAX], vec![5]] {
+//!     assert_eq!(decompress(&compress(&values)).unwrap(), values);
+//! }
+//! ```
+use crate::bits::{BitBundle, BitRead, BitWrite, BitWriter};
+use anyhow::{anyhow, Result};
+
+/// An integer code `compress()` can pick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    DeltaVarint,
+    Rice,
+    EliasGamma,
+}
+
+impl Method {
+    fn to_byte(self) -> u8 {
+        match self {
+            Method::DeltaVarint => 0,
+            Method::Rice => 1,
+            Method::EliasGamma => 2,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Self> {
+        match byte {
+            0 => Ok(Method::DeltaVarint),
+            1 => Ok(Method::Rice),
+            2 => Ok(Method::EliasGamma),
+            _ => Err(anyhow!("Unknown integer code.")),
+        }
+    }
+}
+
+/// Returns the method that compresses `values` the smallest. Ties go to the
+/// earlier method in the order delta varint, Rice, Elias gamma.
+pub fn choose(values: &[u64]) -> Method {
+    let (_, rice_bits) = best_rice(values);
+    let sizes = [
+        (Method::DeltaVarint, delta_varint(values).len() as u128 * 8),
+        (Method::Rice, rice_bits),
+        (Method::EliasGamma, values.iter().map(|&value| gamma_len(value)).sum()),
+    ];
+
+    sizes.into_iter().min_by_key(|&(_, bits)| bits).unwrap().0
+}
+
+/// Compresses a list of integers with the method `choose()` picks. The output
+/// holds the method in a byte and the number of values in LEB128, then for
+/// Rice the number of low bits in a byte, then the coded values.
+pub fn compress(values: &[u64]) -> Vec<u8> {
+    let method = choose(values);
+    let mut glob = vec![method.to_byte()];
+    write_varint(values.len() as u64, &mut glob);
+
+    match method {
+        Method::DeltaVarint => glob.extend(delta_varint(values)),
+        Method::Rice => {
+            let (k, _) = best_rice(values);
+            glob.push(k);
+            let mut writer = BitWriter::new();
+            for &value in values {
+                for _ in 0..(value >> k) {
+                    writer.write_bit(1);
+                }
+                writer.write_bit(0);
+                writer.write_bits(value & low_mask(k), k);
+            }
+            glob.extend(writer.finish().bytes);
+        }
+        Method::EliasGamma => {
+            let mut writer = BitWriter::new();
+            for &value in values {
+                let code = value as u128 + 1;
+                let len = 127 - code.leading_zeros();
+                for _ in 0..len {
+                    writer.write_bit(0);
+                }
+                for i in (0..=len).rev() {
+                    writer.write_bit(((code >> i) & 1) as u8);
+                }
+            }
+            glob.extend(writer.finish().bytes);
+        }
+    }
+
+    glob
+}
+
+/// Decompresses a list of integers produced by `compress()`.
+pub fn decompress(bytes: &[u8]) -> Result<Vec<u64>> {
+    let method = Method::from_byte(*bytes.first().ok_or_else(|| anyhow!("Missing integer code."))?)?;
+    let mut idx = 1;
+    let count = read_varint(bytes, &mut idx)?;
+    // Every value takes at least a bit, which bounds a corrupt count
+    let mut values = Vec::with_capacity((count as usize).min(bytes.len() * 8));
+
+    match method {
+        Method::DeltaVarint => {
+            let mut prev: u64 = 0;
+            for _ in 0..count {
+                let delta = unzigzag(read_varint(bytes, &mut idx)?);
+                prev = prev.wrapping_add(delta as u64);
+                values.push(prev);
+            }
+        }
+        Method::Rice => {
+            let k = *bytes.get(idx).ok_or_else(|| anyhow!("Missing Rice parameter."))?;
+            if k > 63 {
+                return Err(anyhow!("Invalid Rice parameter."));
+            }
+            let mut bundle = BitBundle::new(&bytes[(idx + 1)..]);
+            for _ in 0..count {
+                let mut high: u128 = 0;
+                while read_bit(&mut bundle)? == 1 {
+                    high += 1;
+                }
+                let low = bundle.read_bits(k).ok_or_else(|| anyhow!("Truncated data."))?;
+                let value = high << k | low as u128;
+                values.push(u64::try_from(value).map_err(|_| anyhow!("Value out of range."))?);
+            }
+        }
+        Method::EliasGamma => {
+            let mut bundle = BitBundle::new(&bytes[idx..]);
+            for _ in 0..count {
+                let mut len = 0;
+                while read_bit(&mut bundle)? == 0 {
+                    len += 1;
+                    if len > 64 {
+                        return Err(anyhow!("Code too long."));
+                    }
+                }
+                let mut code: u128 = 1;
+                for _ in 0..len {
+                    code = code << 1 | read_bit(&mut bundle)? as u128;
+                }
+                values.push(u64::try_from(code - 1).map_err(|_| anyhow!("Value out of range."))?);
+            }
+        }
+    }
+
+    Ok(values)
+}
+
+fn read_bit(bundle: &mut BitBundle) -> Result<u8> {
+    bundle.read_bit().ok_or_else(|| anyhow!("Truncated data."))
+}
+
+fn low_mask(k: u8) -> u64 {
+    if k == 0 { 0 } else { u64::MAX >> (64 - k) }
+}
+
+// The Rice parameter giving the fewest bits, and that number of bits
+fn best_rice(values: &[u64]) -> (u8, u128) {
+    (0..64u8)
+        .map(|k| {
+            let bits = values
+                .iter()
+                .map(|&value| (value >> k) as u128 + 1 + k as u128)
+                .sum();
+            (k, bits)
+        })
+        .min_by_key(|&(_, bits)| bits)
+        .unwrap()
+}
+
+// Bits in the Elias gamma code for `value`, which codes one more than it
+fn gamma_len(value: u64) -> u128 {
+    let len = 127 - (value as u128 + 1).leading_zeros();
+    2 * len as u128 + 1
+}
+
+// Differences between neighbours, the first from zero, zigzag mapped in LEB128
+fn delta_varint(values: &[u64]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut prev: u64 = 0;
+    for &value in values {
+        write_varint(zigzag(value.wrapping_sub(prev) as i64), &mut bytes);
+        prev = value;
+    }
+
+    bytes
+}
+
+// Interleave negative and positive numbers, so that both start small
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+// Seven bits to a byte, least significant first, with the top bit set on
+// every byte but the last
+fn write_varint(mut value: u64, bytes: &mut Vec<u8>) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], idx: &mut usize) -> Result<u64> {
+    let mut value: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes.get(*idx).ok_or_else(|| anyhow!("Truncated varint."))?;
+        *idx += 1;
+        let part = (byte & 0x7F) as u64;
+        if shift == 63 && part > 1 {
+            return Err(anyhow!("Varint out of range."));
+        }
+        value |= part << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(anyhow!("Varint too long."))
+}
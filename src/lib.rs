@@ -4,5 +4,29 @@
 //! 
 //! ### Available algorithms
 //! * Huffman
+//! * Fibonacci
+//! * Base85
+//! * Ints, which picks an integer code to suit the values
+//! * RLE
+//! 
+//! The `codec` module puts them all behind one `Codec` trait.
+//! 
+//! The `container` module packs several compressed files behind a central
+//! directory.
+//! 
+//! The `bits` module holds the bit readers and writers the codecs share, and
+//! `checksum` the CRC-32 used for integrity checks. `fuzz`, with the `fuzz`
+//! feature, runs the decoders over malformed input to check they fail
+//! cleanly.
 
+pub mod base85;
+pub mod bits;
+pub mod checksum;
+pub mod codec;
+pub mod container;
+pub mod fibonacci;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
 pub mod huffman;
+pub mod ints;
+pub mod rle;
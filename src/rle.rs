@@ -0,0 +1,109 @@
+//! ## RLE
+//! Run-length encoding writes a run of repeated bytes as the byte and a count.
+//! Bytes are copied as they are, except that a run of four or more becomes
+//! three bytes: a reserved escape byte, the length of the run from 1 to 255,
+//! and the repeated byte. The escape byte itself is always written this way,
+//! even on its own, so an escape that is common in the data costs two bytes
+//! for each run of it. For more, see
+//! [article](https://en.wikipedia.org/wiki/Run-length_encoding).
+//! 
+//! The output opens with the escape byte, so `decode()` works whichever was
+//! chosen. `encode()` picks the least frequent byte of the input, and
+//! `encode_with_escape()` takes one from the caller.
+//! 
+//! ## Example
+//! 
+//! 
+//! ```
+//! use codecs::rle::{decode, encode, encode_with_escape};
+//! 
+//! // Scanlines of background with a few spans of colour, most bytes zero
+//! let mut image = Vec::new();
+//! for row in 0..32u8 {
+//!     image.extend([0; 20]);
+//!     image.extend([row, 0, row.wrapping_mul(7), 0, 1, 2, 0, 3]);
+//!     image.extend([0xFF; 12]);
+//! }
+//! 
+//! let auto = encode(&image);
+//! assert_eq!(decode(&auto).unwrap(), image);
+//! assert!(auto.len() < image.len());
+//! 
+//! // Zero stands alone between the coloured bytes, escaping each costs two more
+//! let zero = encode_with_escape(&image, 0);
+//! assert_eq!(decode(&zero).unwrap(), image);
+//! assert!(auto.len() < zero.len());
+//! for escape in [0x00, 0x01, 0x80, 0xFF] {
+//!     assert!(auto.len() <= encode_with_escape(&image, escape).len());
+//! }
+//! 
+//! assert_eq!(decode(&encode(b"")).unwrap(), b"");
+//! assert!(decode(&[0x00, 0x00, b'a']).is_err());
+//! ```
+use anyhow::{anyhow, Result};
+
+// Shortest run of a byte other than the escape worth writing as a run, being
+// longer than the three bytes it takes
+const MIN_RUN: usize = 4;
+const MAX_RUN: usize = 255;
+
+/// Encodes bytes with the least frequent byte of the input as the escape,
+/// the lowest such byte on a tie.
+pub fn encode(input: &[u8]) -> Vec<u8> {
+    let mut counts = [0usize; 256];
+    for &byte in input {
+        counts[byte as usize] += 1;
+    }
+    let escape = (0..=255u8).min_by_key(|&byte| counts[byte as usize]).unwrap();
+
+    encode_with_escape(input, escape)
+}
+
+/// Encodes bytes with `escape` marking runs. A byte rare in the data keeps
+/// the output small, as each run of the escape itself takes three bytes.
+pub fn encode_with_escape(input: &[u8], escape: u8) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() + 1);
+    output.push(escape);
+
+    let mut idx = 0;
+    while idx < input.len() {
+        let byte = input[idx];
+        let run = input[idx..]
+            .iter()
+            .take(MAX_RUN)
+            .take_while(|&&next| next == byte)
+            .count();
+
+        if byte == escape || run >= MIN_RUN {
+            output.extend_from_slice(&[escape, run as u8, byte]);
+        } else {
+            output.extend(std::iter::repeat_n(byte, run));
+        }
+        idx += run;
+    }
+
+    output
+}
+
+/// Decodes bytes produced by `encode()` or `encode_with_escape()`.
+pub fn decode(input: &[u8]) -> Result<Vec<u8>> {
+    let (&escape, data) = input.split_first().ok_or_else(|| anyhow!("Missing escape byte."))?;
+    let mut output = Vec::with_capacity(data.len());
+
+    let mut bytes = data.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte != escape {
+            output.push(byte);
+            continue;
+        }
+
+        let run = *bytes.next().ok_or_else(|| anyhow!("Truncated run."))?;
+        let value = *bytes.next().ok_or_else(|| anyhow!("Truncated run."))?;
+        if run == 0 {
+            return Err(anyhow!("Run of length zero."));
+        }
+        output.extend(std::iter::repeat_n(value, run as usize));
+    }
+
+    Ok(output)
+}